@@ -9,8 +9,9 @@ use std::fmt;
 const SMASK_U64: u64 = 0b1111111110000000000000000000000000000000000000000000000000000000;
 const PMASK_U64: u64 = 0b0000000001111111111111111111111111111111111111111111111111111111;
 
-/// The bitpacked type
+/// The bitpacked type. This is a transparent wrapper around the `u64`, so slices of raw addresses can be read as addresses.
 #[derive(Copy, Clone, Serialize, Deserialize, PartialEq, Eq, std::hash::Hash, PartialOrd, Ord)]
+#[repr(transparent)]
 pub struct NodeAddress {
     na: u64,
 }
@@ -0,0 +1,485 @@
+//! # Tree Bundles
+//!
+//! A bundle is a single file with a tree and everything needed to use it: the point cloud's data, its labels and
+//! names, and the parameters the tree was built with. Unlike a YAML manifest pointing at data files, the parts
+//! can't drift apart, and `utils::load_bundle` gives back a ready tree. The layout is:
+//!
+//! * The 8 magic bytes `GOKOBNDL`
+//! * The format version, a little endian `u32`
+//! * The length of the manifest, a little endian `u64`
+//! * The [`BundleManifest`], as JSON
+//! * The sections, in the order and with the lengths listed in the manifest
+//!
+//! The sections are:
+//!
+//! * `tree`: the tree, as `utils::save_tree` writes it, with its persistent plugins
+//! * `data`: the points, as little endian `f32`s
+//! * `labels`: the labels, as little endian `i64`s, if the point cloud is labeled
+//! * `label_mask`: a byte per point, 0 for the points without a label, if any are missing
+//! * `names`: the names of the points as a JSON list, if the point cloud is named
+//!
+//! Point clouds that can be bundled implement [`BundleCloud`].
+
+use crate::errors::{GokoError, GokoResult};
+use crate::tree_header::{checksum, BuildParameters};
+use crate::utils::{decode_tree, encode_tree};
+use crate::CoverTreeWriter;
+use pointcloud::data_sources::DataRam;
+use pointcloud::label_sources::SmallIntLabels;
+use pointcloud::name_sources::VecNames;
+use pointcloud::*;
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+use std::sync::Arc;
+
+/// The bytes every bundle starts with.
+pub const BUNDLE_MAGIC: &[u8; 8] = b"GOKOBNDL";
+/// The current version of the format. Files with a larger version are rejected.
+pub const BUNDLE_VERSION: u32 = 1;
+
+/// The contents of a point cloud, as they're stored in a bundle.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BundledPoints {
+    /// The dimension of the points
+    pub dim: usize,
+    /// The points, one after another
+    pub data: Vec<f32>,
+    /// The labels, and which of them are known if some aren't
+    pub labels: Option<(Vec<i64>, Option<Vec<bool>>)>,
+    /// The names of the points
+    pub names: Option<Vec<String>>,
+}
+
+/// A point cloud that can be written into a bundle and rebuilt from it.
+pub trait BundleCloud: PointCloud + Sized {
+    /// Copies out the contents of the point cloud.
+    fn to_bundle(&self) -> GokoResult<BundledPoints>;
+    /// Rebuilds the point cloud, erroring if the bundle is missing a part it needs.
+    fn from_bundle(points: BundledPoints) -> GokoResult<Self>;
+}
+
+fn missing_section(name: &str) -> GokoError {
+    GokoError::CorruptTreeFile(format!("the bundle has no {} section", name))
+}
+
+/// Checks the length of a section against the number of points, `None` is a length that overflowed.
+fn check_section_len(name: &str, len: usize, expected: Option<usize>) -> GokoResult<()> {
+    if Some(len) != expected {
+        return Err(GokoError::CorruptTreeFile(format!(
+            "the {} section has the wrong length",
+            name
+        )));
+    }
+    Ok(())
+}
+
+fn dense_data<D: PointCloud<Point = [f32]>>(point_cloud: &D) -> GokoResult<Vec<f32>> {
+    let mut data = Vec::with_capacity(point_cloud.len() * point_cloud.dim());
+    for pi in 0..point_cloud.len() {
+        data.extend_from_slice(&point_cloud.point(pi)?);
+    }
+    Ok(data)
+}
+
+impl<M: Metric<[f32]>> BundleCloud for DataRam<M> {
+    fn to_bundle(&self) -> GokoResult<BundledPoints> {
+        Ok(BundledPoints {
+            dim: self.dim(),
+            data: dense_data(self)?,
+            labels: None,
+            names: None,
+        })
+    }
+
+    fn from_bundle(points: BundledPoints) -> GokoResult<Self> {
+        Ok(DataRam::new(points.data, points.dim)?)
+    }
+}
+
+impl<M: Metric<[f32]>> BundleCloud for DefaultLabeledCloud<M> {
+    fn to_bundle(&self) -> GokoResult<BundledPoints> {
+        let mut labels = Vec::with_capacity(self.len());
+        let mut mask = Vec::with_capacity(self.len());
+        for pi in 0..self.len() {
+            let label = self.label(pi)?;
+            labels.push(label.copied().unwrap_or(0));
+            mask.push(label.is_some());
+        }
+        let mask = if mask.iter().all(|m| *m) {
+            None
+        } else {
+            Some(mask)
+        };
+        Ok(BundledPoints {
+            dim: self.dim(),
+            data: dense_data(self)?,
+            labels: Some((labels, mask)),
+            names: None,
+        })
+    }
+
+    fn from_bundle(points: BundledPoints) -> GokoResult<Self> {
+        let (labels, mask) = points.labels.ok_or_else(|| missing_section("labels"))?;
+        Ok(SimpleLabeledCloud::new(
+            DataRam::new(points.data, points.dim)?,
+            SmallIntLabels::new(labels, mask),
+        ))
+    }
+}
+
+impl<D: BundleCloud> BundleCloud for SimpleNamedCloud<D, VecNames> {
+    fn to_bundle(&self) -> GokoResult<BundledPoints> {
+        let mut points = self.data().to_bundle()?;
+        points.names = Some(self.names());
+        Ok(points)
+    }
+
+    fn from_bundle(mut points: BundledPoints) -> GokoResult<Self> {
+        let names = points
+            .names
+            .take()
+            .ok_or_else(|| missing_section("names"))?;
+        Ok(SimpleNamedCloud::new(
+            D::from_bundle(points)?,
+            VecNames::new(names),
+        ))
+    }
+}
+
+/// The sections of a bundle, by name, in the order they're stored.
+pub type BundleSections<'a> = Vec<(String, &'a [u8])>;
+
+/// The manifest of a bundle, which lists what's in it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BundleManifest {
+    /// The format version the file was written with
+    pub version: u32,
+    /// The number of points
+    pub count: usize,
+    /// The dimension of the points
+    pub dim: usize,
+    /// The parameters the tree was built with
+    pub parameters: BuildParameters,
+    /// A hash of the sections
+    pub checksum: u64,
+    /// The names and lengths of the sections after the manifest
+    pub sections: Vec<(String, u64)>,
+}
+
+impl BundleManifest {
+    /// Splits a bundle into its manifest and its sections, checking the checksum.
+    pub fn decode(bytes: &[u8]) -> GokoResult<(BundleManifest, BundleSections<'_>)> {
+        if bytes.len() < 20 || &bytes[..8] != BUNDLE_MAGIC {
+            return Err(GokoError::CorruptTreeFile(
+                "this isn't a bundle".to_string(),
+            ));
+        }
+        let version = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+        if version > BUNDLE_VERSION {
+            return Err(GokoError::UnsupportedTreeFileVersion(version));
+        }
+        let manifest_len = u64::from_le_bytes(bytes[12..20].try_into().unwrap()) as usize;
+        if bytes.len() - 20 < manifest_len {
+            return Err(GokoError::CorruptTreeFile(
+                "the manifest is truncated".to_string(),
+            ));
+        }
+        let manifest: BundleManifest = serde_json::from_slice(&bytes[20..20 + manifest_len])
+            .map_err(|e| GokoError::CorruptTreeFile(e.to_string()))?;
+        let mut remaining = &bytes[20 + manifest_len..];
+        let mut sections = Vec::with_capacity(manifest.sections.len());
+        for (name, len) in &manifest.sections {
+            if (remaining.len() as u64) < *len {
+                return Err(GokoError::CorruptTreeFile(
+                    "the sections are truncated".to_string(),
+                ));
+            }
+            let (section, tail) = remaining.split_at(*len as usize);
+            sections.push((name.clone(), section));
+            remaining = tail;
+        }
+        if checksum(sections.iter().map(|(_, s)| *s)) != manifest.checksum {
+            return Err(GokoError::CorruptTreeFile(
+                "the checksum doesn't match".to_string(),
+            ));
+        }
+        Ok((manifest, sections))
+    }
+}
+
+/// Encodes the tree and its point cloud into a bundle.
+pub fn encode_bundle<D: BundleCloud>(cover_tree: &CoverTreeWriter<D>) -> GokoResult<Vec<u8>> {
+    let points = cover_tree.parameters.point_cloud.to_bundle()?;
+    let mut sections = vec![
+        ("tree".to_string(), encode_tree(cover_tree)?),
+        (
+            "data".to_string(),
+            points.data.iter().flat_map(|x| x.to_le_bytes()).collect(),
+        ),
+    ];
+    if let Some((labels, mask)) = &points.labels {
+        sections.push((
+            "labels".to_string(),
+            labels.iter().flat_map(|x| x.to_le_bytes()).collect(),
+        ));
+        if let Some(mask) = mask {
+            sections.push((
+                "label_mask".to_string(),
+                mask.iter().map(|m| *m as u8).collect(),
+            ));
+        }
+    }
+    if let Some(names) = &points.names {
+        sections.push(("names".to_string(), serde_json::to_vec(names)?));
+    }
+
+    write_bundle(
+        cover_tree.parameters.point_cloud.len(),
+        points.dim,
+        BuildParameters::from(cover_tree.parameters.as_ref()),
+        &sections,
+    )
+}
+
+/// Writes the manifest and the sections.
+fn write_bundle(
+    count: usize,
+    dim: usize,
+    parameters: BuildParameters,
+    sections: &[(String, Vec<u8>)],
+) -> GokoResult<Vec<u8>> {
+    let manifest = BundleManifest {
+        version: BUNDLE_VERSION,
+        count,
+        dim,
+        parameters,
+        checksum: checksum(sections.iter().map(|(_, s)| &s[..])),
+        sections: sections
+            .iter()
+            .map(|(name, s)| (name.clone(), s.len() as u64))
+            .collect(),
+    };
+    let manifest = serde_json::to_vec(&manifest)?;
+    let mut bytes = Vec::with_capacity(
+        20 + manifest.len() + sections.iter().map(|(_, s)| s.len()).sum::<usize>(),
+    );
+    bytes.extend_from_slice(BUNDLE_MAGIC);
+    bytes.extend_from_slice(&BUNDLE_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&(manifest.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(&manifest);
+    for (_, section) in sections {
+        bytes.extend_from_slice(section);
+    }
+    Ok(bytes)
+}
+
+/// Rebuilds the point cloud and the tree from a bundle.
+pub fn decode_bundle<D: BundleCloud>(bytes: &[u8]) -> GokoResult<CoverTreeWriter<D>> {
+    let (manifest, sections) = BundleManifest::decode(bytes)?;
+    let section = |name: &str| {
+        sections
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, section)| *section)
+    };
+
+    let count = manifest.count;
+    let data = section("data").ok_or_else(|| missing_section("data"))?;
+    check_section_len(
+        "data",
+        data.len(),
+        count
+            .checked_mul(manifest.dim)
+            .and_then(|n| n.checked_mul(4)),
+    )?;
+    let labels = match section("labels") {
+        None => None,
+        Some(labels) => {
+            check_section_len("labels", labels.len(), count.checked_mul(8))?;
+            let mask = match section("label_mask") {
+                None => None,
+                Some(mask) => {
+                    check_section_len("label_mask", mask.len(), Some(count))?;
+                    Some(mask.iter().map(|m| *m != 0).collect())
+                }
+            };
+            let labels: Vec<i64> = labels
+                .chunks_exact(8)
+                .map(|x| i64::from_le_bytes(x.try_into().unwrap()))
+                .collect();
+            Some((labels, mask))
+        }
+    };
+    let names = match section("names") {
+        None => None,
+        Some(names) => {
+            let names: Vec<String> = serde_json::from_slice(names)
+                .map_err(|e| GokoError::CorruptTreeFile(e.to_string()))?;
+            check_section_len("names", names.len(), Some(count))?;
+            Some(names)
+        }
+    };
+    let point_cloud = D::from_bundle(BundledPoints {
+        dim: manifest.dim,
+        data: data
+            .chunks_exact(4)
+            .map(|x| f32::from_le_bytes(x.try_into().unwrap()))
+            .collect(),
+        labels,
+        names,
+    })?;
+
+    let tree = section("tree").ok_or_else(|| missing_section("tree"))?;
+    decode_tree(tree, Arc::new(point_cloud))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::random_data;
+    use crate::covertree::CoverTreeBuilder;
+    use crate::utils::{load_bundle, save_bundle};
+
+    type NamedCloud = SimpleNamedCloud<DefaultLabeledCloud<L2>, VecNames>;
+
+    fn build_named_tree() -> CoverTreeWriter<NamedCloud> {
+        let data = random_data(200, 3, 0);
+        let labels: Vec<i64> = (0..200).map(|i| i % 4).collect();
+        let mask: Vec<bool> = (0..200).map(|i| i % 7 != 0).collect();
+        let names: Vec<String> = (0..200).map(|i| format!("point_{}", i)).collect();
+        let point_cloud = SimpleNamedCloud::new(
+            SimpleLabeledCloud::new(
+                DataRam::new(data, 3).unwrap(),
+                SmallIntLabels::new(labels, Some(mask)),
+            ),
+            VecNames::new(names),
+        );
+        let mut builder = CoverTreeBuilder::new();
+        builder
+            .set_scale_base(1.5)
+            .set_leaf_cutoff(5)
+            .set_min_res_index(-5)
+            .set_rng_seed(3);
+        let mut tree = builder.build(Arc::new(point_cloud)).unwrap();
+        tree.generate_summaries();
+        tree
+    }
+
+    #[test]
+    fn bundle_round_trip() {
+        let tree = build_named_tree();
+        let dir = tempdir::TempDir::new("bundle").unwrap();
+        let path = dir.path().join("tree.bundle");
+        save_bundle(&path, &tree).unwrap();
+
+        let loaded = load_bundle::<_, NamedCloud>(&path).unwrap();
+        let point_cloud = &loaded.parameters.point_cloud;
+        let original = &tree.parameters.point_cloud;
+        assert_eq!(point_cloud.names(), original.names());
+        assert_eq!(point_cloud.index("point_17").unwrap(), 17);
+        for pi in 0..original.len() {
+            assert_eq!(point_cloud.label(pi).unwrap(), original.label(pi).unwrap());
+            assert_eq!(point_cloud.point(pi).unwrap(), original.point(pi).unwrap());
+        }
+        assert_eq!(
+            BuildParameters::from(loaded.parameters.as_ref()),
+            BuildParameters::from(tree.parameters.as_ref())
+        );
+
+        let reader = loaded.reader();
+        assert_eq!(reader.node_count(), tree.reader().node_count());
+        let root_address = reader.root_address();
+        assert!(reader.get_node_label_summary(root_address).is_some());
+        let point = original.point(5).unwrap();
+        assert_eq!(
+            reader.knn(&point, 5).unwrap(),
+            tree.reader().knn(&point, 5).unwrap()
+        );
+    }
+
+    #[test]
+    fn bundle_errors() {
+        let tree = build_named_tree();
+        let mut bytes = encode_bundle(&tree).unwrap();
+
+        // The names are required by the named cloud, but not by the inner one
+        assert!(decode_bundle::<DefaultLabeledCloud<L2>>(&bytes).is_ok());
+        assert!(decode_bundle::<DataRam<L2>>(&bytes).is_ok());
+
+        let unnamed = decode_bundle::<DataRam<L2>>(&bytes).unwrap();
+        let unnamed_bytes = encode_bundle(&unnamed).unwrap();
+        assert!(matches!(
+            decode_bundle::<NamedCloud>(&unnamed_bytes),
+            Err(GokoError::CorruptTreeFile(_))
+        ));
+        assert!(matches!(
+            decode_bundle::<DefaultLabeledCloud<L2>>(&unnamed_bytes),
+            Err(GokoError::CorruptTreeFile(_))
+        ));
+
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        assert!(matches!(
+            decode_bundle::<NamedCloud>(&bytes),
+            Err(GokoError::CorruptTreeFile(_))
+        ));
+        assert!(matches!(
+            decode_bundle::<NamedCloud>(b"GOKOTREE"),
+            Err(GokoError::CorruptTreeFile(_))
+        ));
+    }
+
+    #[test]
+    fn bundle_section_lengths() {
+        let tree = build_named_tree();
+        let bytes = encode_bundle(&tree).unwrap();
+        let (manifest, sections) = BundleManifest::decode(&bytes).unwrap();
+        let sections: Vec<(String, Vec<u8>)> = sections
+            .into_iter()
+            .map(|(name, s)| (name, s.to_vec()))
+            .collect();
+        let rewrite = |count: usize, dim: usize, name: &str, drop: usize| {
+            let mut sections = sections.clone();
+            for (n, s) in sections.iter_mut() {
+                if n == name {
+                    s.truncate(s.len() - drop);
+                }
+            }
+            write_bundle(count, dim, manifest.parameters.clone(), &sections).unwrap()
+        };
+
+        let unchanged = rewrite(manifest.count, manifest.dim, "data", 0);
+        assert!(decode_bundle::<NamedCloud>(&unchanged).is_ok());
+        for (name, drop) in [("labels", 8), ("label_mask", 1)].iter() {
+            let shortened = rewrite(manifest.count, manifest.dim, name, *drop);
+            assert!(matches!(
+                decode_bundle::<NamedCloud>(&shortened),
+                Err(GokoError::CorruptTreeFile(_))
+            ));
+        }
+        let mut names = tree.parameters.point_cloud.names();
+        names.pop();
+        let mut short_names = sections.clone();
+        for (n, s) in short_names.iter_mut() {
+            if n == "names" {
+                *s = serde_json::to_vec(&names).unwrap();
+            }
+        }
+        let short_names = write_bundle(
+            manifest.count,
+            manifest.dim,
+            manifest.parameters.clone(),
+            &short_names,
+        )
+        .unwrap();
+        assert!(matches!(
+            decode_bundle::<NamedCloud>(&short_names),
+            Err(GokoError::CorruptTreeFile(_))
+        ));
+        let overflowing = rewrite(usize::MAX / 2, 3, "data", 0);
+        assert!(matches!(
+            decode_bundle::<NamedCloud>(&overflowing),
+            Err(GokoError::CorruptTreeFile(_))
+        ));
+    }
+}
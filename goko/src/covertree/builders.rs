@@ -19,6 +19,7 @@
 use super::data_caches::*;
 use super::layer::*;
 use super::node::*;
+use super::query_tools::QueryStats;
 use super::*;
 use crate::plugins::TreePluginSet;
 use crate::*;
@@ -26,13 +27,18 @@ use pbr::ProgressBar;
 use rand::rngs::SmallRng;
 use rand::SeedableRng;
 use std::cmp::{max, min};
+use std::collections::HashMap;
+use std::fmt;
 use std::fs::read_to_string;
+use std::io::Stdout;
 use std::path::Path;
 use std::sync::{atomic, Arc, RwLock};
 use yaml_rust::YamlLoader;
 
 use crossbeam_channel::{unbounded, Receiver, Sender};
-use errors::GokoResult;
+use errors::{GokoError, GokoResult};
+use pointcloud::validation::ValidationPolicy;
+use rayon::ThreadPool;
 
 use std::time::Instant;
 
@@ -43,20 +49,24 @@ struct BuilderNode {
     covered: CoveredData,
 }
 
-type NodeSplitResult<D> = GokoResult<(NodeAddress, CoverNode<D>)>;
+type NodeSplitResult<D> = GokoResult<(NodeAddress, CoverNode<D>, QueryStats)>;
 
 impl BuilderNode {
     fn new<D: PointCloud>(
         parameters: &CoverTreeParameters<D>,
         partition_type: PartitionType,
+        point_indexes: Vec<usize>,
+        stats: &mut QueryStats,
     ) -> GokoResult<BuilderNode> {
         let covered = match partition_type {
             PartitionType::Nearest => CoveredData::NearestCoveredData(
-                NearestCoveredData::new::<D>(&parameters.point_cloud)?,
+                NearestCoveredData::new::<D>(&parameters.point_cloud, point_indexes, stats)?,
             ),
-            PartitionType::First => {
-                CoveredData::FirstCoveredData(FirstCoveredData::new::<D>(&parameters.point_cloud)?)
-            }
+            PartitionType::First => CoveredData::FirstCoveredData(FirstCoveredData::new::<D>(
+                &parameters.point_cloud,
+                point_indexes,
+                stats,
+            )?),
         };
         let scale_index = (covered.max_distance()).log(parameters.scale_base).ceil() as i32;
         assert!(
@@ -76,31 +86,54 @@ impl BuilderNode {
         (self.scale_index, self.covered.center_index()).into()
     }
 
+    /// Splits this node and its children on rayon, sending them back as they're done. This is spawned on `pool` if
+    /// there is one, the children are spawned on whatever pool their parent ran on. A failed split cancels the
+    /// token, so that the other tasks stop splitting.
+    ///
+    /// Each task holds a sender until it's done with the parameters, so the channel closes once all the tasks have
+    /// stopped.
     fn split_parallel<D: PointCloud>(
         self,
         parameters: &Arc<CoverTreeParameters<D>>,
         node_sender: &Arc<Sender<NodeSplitResult<D>>>,
+        cancellation: &CancellationToken,
+        pool: Option<&ThreadPool>,
     ) {
         let parameters = Arc::clone(parameters);
         let node_sender = Arc::clone(node_sender);
-        rayon::spawn(move || {
-            let na = self.address();
-            match self.split(&parameters) {
-                Ok((new_node, mut new_nodes)) => {
-                    node_sender.send(Ok((na, new_node))).unwrap();
-                    while let Some(node) = new_nodes.pop() {
-                        node.split_parallel(&parameters, &node_sender);
+        let cancellation = cancellation.clone();
+        let task = move || {
+            if !cancellation.is_cancelled() {
+                let na = self.address();
+                let mut stats = QueryStats::default();
+                match self.split(&parameters, &mut stats) {
+                    Ok((new_node, mut new_nodes)) => {
+                        let _ = node_sender.send(Ok((na, new_node, stats)));
+                        while let Some(node) = new_nodes.pop() {
+                            node.split_parallel(&parameters, &node_sender, &cancellation, None);
+                        }
                     }
-                }
-                Err(e) => node_sender.send(Err(e)).unwrap(),
-            };
-        });
+                    Err(e) => {
+                        cancellation.cancel();
+                        let _ = node_sender.send(Err(e));
+                    }
+                };
+            }
+            drop(parameters);
+            drop(node_sender);
+        };
+        match pool {
+            Some(pool) => pool.spawn(task),
+            None => rayon::spawn(task),
+        }
     }
 
     fn split<D: PointCloud>(
         self,
         parameters: &Arc<CoverTreeParameters<D>>,
+        stats: &mut QueryStats,
     ) -> GokoResult<(CoverNode<D>, Vec<BuilderNode>)> {
+        stats.nodes_visited += 1;
         let scale_index = self.scale_index;
         let current_address =
             unsafe { NodeAddress::new_unchecked(scale_index, self.covered.center_index()) };
@@ -131,6 +164,7 @@ impl BuilderNode {
                     covered,
                     next_scale_index,
                     parameters,
+                    stats,
                 )?,
                 CoveredData::NearestCoveredData(covered) => BuilderNode::split_nearest(
                     &mut node,
@@ -138,6 +172,7 @@ impl BuilderNode {
                     covered,
                     next_scale_index,
                     parameters,
+                    stats,
                 )?,
             }
         };
@@ -161,6 +196,7 @@ impl BuilderNode {
         covered: NearestCoveredData,
         split_scale_index: i32,
         parameters: &Arc<CoverTreeParameters<D>>,
+        stats: &mut QueryStats,
     ) -> GokoResult<Vec<BuilderNode>> {
         let mut small_rng: SmallRng = match parameters.rng_seed {
             Some(seed) => SmallRng::seed_from_u64(seed ^ parent_address.point_index() as u64),
@@ -168,7 +204,7 @@ impl BuilderNode {
         };
         let next_scale = parameters.scale_base.powi(split_scale_index);
         let (nested_potential, mut splits) =
-            covered.split(next_scale, &parameters.point_cloud, &mut small_rng)?;
+            covered.split(next_scale, &parameters.point_cloud, &mut small_rng, stats)?;
         let mut new_nodes = Vec::new();
 
         let mut inserts = Vec::new();
@@ -222,6 +258,7 @@ impl BuilderNode {
         covered: FirstCoveredData,
         split_scale_index: i32,
         parameters: &Arc<CoverTreeParameters<D>>,
+        stats: &mut QueryStats,
     ) -> GokoResult<Vec<BuilderNode>> {
         let mut small_rng: SmallRng = match parameters.rng_seed {
             Some(seed) => SmallRng::seed_from_u64(seed ^ parent_address.raw()),
@@ -267,7 +304,7 @@ impl BuilderNode {
 
         while fars.len() > 0 {
             let new_close =
-                fars.pick_center(next_scale, &parameters.point_cloud, &mut small_rng)?;
+                fars.pick_center(next_scale, &parameters.point_cloud, &mut small_rng, stats)?;
             //println!("\t\t [{}] New Covered: {:?}",split_count, new_close);
             if new_close.len() == 1 && parameters.use_singletons {
                 /*
@@ -302,8 +339,83 @@ impl BuilderNode {
     }
 }
 
+/// How far along a build is, see [`CoverTreeBuilder::set_progress_observer`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BuildProgress {
+    /// The number of nodes that have been created and put in the tree
+    pub nodes_created: usize,
+    /// The number of nodes known so far, including the ones waiting to be split. This grows as the build goes on.
+    pub nodes_total: usize,
+    /// The number of points that aren't a singleton or the center of a leaf yet
+    pub points_remaining: usize,
+    /// The lowest scale index a node has been created on
+    pub scale_index: i32,
+}
+
+/// Called with the progress of a build after each node is created.
+pub type ProgressObserver = Arc<dyn Fn(&BuildProgress) + Send + Sync>;
+
+/// Stops a build from another thread. Clones share the same flag, so keep one and give one to the builder.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<atomic::AtomicBool>);
+
+impl CancellationToken {
+    /// A token that hasn't been cancelled.
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Cancels the builds using this token. They stop splitting nodes and return `GokoError::BuildCancelled`.
+    pub fn cancel(&self) {
+        self.0.store(true, atomic::Ordering::SeqCst);
+    }
+
+    /// If `cancel` has been called.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(atomic::Ordering::SeqCst)
+    }
+}
+
+/// Keeps track of a build's progress for the progress bar and observer, and checks for cancellation.
+struct BuildMonitor<'a> {
+    pb: ProgressBar<Stdout>,
+    progress: BuildProgress,
+    observer: Option<&'a ProgressObserver>,
+    cancellation: Option<&'a CancellationToken>,
+}
+
+impl<'a> BuildMonitor<'a> {
+    fn node_inserted<D: PointCloud>(
+        &mut self,
+        parameters: &CoverTreeParameters<D>,
+        node: &CoverNode<D>,
+    ) {
+        let total_nodes = parameters.total_nodes.load(atomic::Ordering::SeqCst);
+        self.progress.nodes_created += 1;
+        self.progress.nodes_total = total_nodes;
+        self.progress.points_remaining = self
+            .progress
+            .points_remaining
+            .saturating_sub(node.singletons_len() + node.is_leaf() as usize);
+        self.progress.scale_index = min(self.progress.scale_index, node.scale_index());
+        if parameters.verbosity > 1 {
+            self.pb.total = total_nodes as u64;
+            self.pb.inc();
+        }
+        if let Some(observer) = self.observer {
+            observer(&self.progress);
+        }
+    }
+
+    fn check_cancelled(&self) -> GokoResult<()> {
+        match self.cancellation {
+            Some(cancellation) if cancellation.is_cancelled() => Err(GokoError::BuildCancelled),
+            _ => Ok(()),
+        }
+    }
+}
+
 /// A construction object for a covertree. See [`crate::covertree::CoverTreeParameters`] for docs
-#[derive(Debug)]
 pub struct CoverTreeBuilder {
     pub(crate) scale_base: f32,
     pub(crate) leaf_cutoff: usize,
@@ -312,6 +424,30 @@ pub struct CoverTreeBuilder {
     pub(crate) partition_type: PartitionType,
     pub(crate) verbosity: u32,
     pub(crate) rng_seed: Option<u64>,
+    pub(crate) validation: Option<ValidationPolicy>,
+    pub(crate) deterministic: bool,
+    pub(crate) progress_observer: Option<ProgressObserver>,
+    pub(crate) cancellation: Option<CancellationToken>,
+    pub(crate) thread_pool: Option<Arc<ThreadPool>>,
+}
+
+impl fmt::Debug for CoverTreeBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CoverTreeBuilder")
+            .field("scale_base", &self.scale_base)
+            .field("leaf_cutoff", &self.leaf_cutoff)
+            .field("min_res_index", &self.min_res_index)
+            .field("use_singletons", &self.use_singletons)
+            .field("partition_type", &self.partition_type)
+            .field("verbosity", &self.verbosity)
+            .field("rng_seed", &self.rng_seed)
+            .field("validation", &self.validation)
+            .field("deterministic", &self.deterministic)
+            .field("progress_observer", &self.progress_observer.is_some())
+            .field("cancellation", &self.cancellation)
+            .field("thread_pool", &self.thread_pool)
+            .finish()
+    }
 }
 
 impl Default for CoverTreeBuilder {
@@ -324,6 +460,11 @@ impl Default for CoverTreeBuilder {
             partition_type: PartitionType::Nearest,
             verbosity: 0,
             rng_seed: None,
+            validation: None,
+            deterministic: false,
+            progress_observer: None,
+            cancellation: None,
+            thread_pool: None,
         }
     }
 }
@@ -339,10 +480,16 @@ impl CoverTreeBuilder {
             partition_type: PartitionType::Nearest,
             verbosity: 0,
             rng_seed: None,
+            validation: None,
+            deterministic: false,
+            progress_observer: None,
+            cancellation: None,
+            thread_pool: None,
         }
     }
 
-    /// Creates a builder from an open yaml object
+    /// Creates a builder from an open yaml object. Panics if the file can't be read, or if `validation` isn't one of
+    /// `error`, `drop` or `dedup`.
     pub fn from_yaml<P: AsRef<Path>>(path: P) -> Self {
         let config = read_to_string(&path).expect("Unable to read config file");
         let params_files = YamlLoader::load_from_str(&config).unwrap();
@@ -352,6 +499,16 @@ impl CoverTreeBuilder {
         } else {
             PartitionType::Nearest
         };
+        let validation = match params["validation"].as_str() {
+            Some("error") => Some(ValidationPolicy::Error),
+            Some("drop") => Some(ValidationPolicy::Drop),
+            Some("dedup") => Some(ValidationPolicy::Dedup),
+            Some(other) => panic!(
+                "Unknown validation policy {:?}, expected error, drop or dedup",
+                other
+            ),
+            None => None,
+        };
         CoverTreeBuilder {
             scale_base: params["scale_base"].as_f64().unwrap_or(2.0) as f32,
             leaf_cutoff: params["leaf_cutoff"].as_i64().unwrap_or(1) as usize,
@@ -360,6 +517,11 @@ impl CoverTreeBuilder {
             partition_type,
             verbosity: params["verbosity"].as_i64().unwrap_or(0) as u32,
             rng_seed: params["rng_seed"].as_i64().map(|i| i as u64),
+            validation,
+            deterministic: params["deterministic"].as_bool().unwrap_or(false),
+            progress_observer: None,
+            cancellation: None,
+            thread_pool: None,
         }
     }
 
@@ -393,9 +555,68 @@ impl CoverTreeBuilder {
         self.rng_seed = Some(x);
         self
     }
+    /// Checks the point cloud before building and applies the policy to the points that fail, see
+    /// [`pointcloud::validation`]. By default nothing is checked.
+    pub fn set_validation(&mut self, x: ValidationPolicy) -> &mut Self {
+        self.validation = Some(x);
+        self
+    }
+    /// Builds the tree one depth at a time and inserts the nodes in a fixed order, so that the nodes, their addresses,
+    /// where the singletons end up and the order the layers store and save the nodes in are identical no matter how
+    /// many threads rayon has. If no seed is set this uses a seed of 0. This
+    /// holds all the points of a depth in memory at once and waits for a whole depth before starting the next, so
+    /// it's a little slower.
+    pub fn set_deterministic(&mut self, x: bool) -> &mut Self {
+        self.deterministic = x;
+        self
+    }
+    /// Calls the observer with the build's progress after each node is created, from the thread that called
+    /// `build`. This is called a lot, so keep it cheap.
+    pub fn set_progress_observer<F>(&mut self, x: F) -> &mut Self
+    where
+        F: Fn(&BuildProgress) + Send + Sync + 'static,
+    {
+        self.progress_observer = Some(Arc::new(x));
+        self
+    }
+    /// Cancelling the token stops the build, which then returns `GokoError::BuildCancelled`.
+    pub fn set_cancellation(&mut self, x: CancellationToken) -> &mut Self {
+        self.cancellation = Some(x);
+        self
+    }
+    /// Runs the build on this pool instead of rayon's global pool. The thread that calls `build` only collects the
+    /// nodes, so a pool with a single thread works.
+    pub fn set_thread_pool(&mut self, x: Arc<ThreadPool>) -> &mut Self {
+        self.thread_pool = Some(x);
+        self
+    }
+    /// Runs the closure on the thread pool, if there is one.
+    fn install<R: Send, F: FnOnce() -> R + Send>(&self, f: F) -> R {
+        match &self.thread_pool {
+            Some(pool) => pool.install(f),
+            None => f(),
+        }
+    }
     /// Pass a point cloud object when ready.
     /// To do, make this point cloud an Arc
     pub fn build<D: PointCloud>(&self, point_cloud: Arc<D>) -> GokoResult<CoverTreeWriter<D>> {
+        self.build_with_stats(point_cloud)
+            .map(|(tree, _stats)| tree)
+    }
+
+    /// Same as `build`, but also counts the distances computed and nodes split while building.
+    pub fn build_with_stats<D: PointCloud>(
+        &self,
+        point_cloud: Arc<D>,
+    ) -> GokoResult<(CoverTreeWriter<D>, QueryStats)> {
+        let mut stats = QueryStats::default();
+        let point_indexes = match self.validation {
+            Some(policy) => self
+                .install(|| point_cloud.validate())?
+                .apply(policy, &point_cloud.reference_indexes())?,
+            None => point_cloud.reference_indexes(),
+        };
+        let point_count = point_indexes.len();
         let parameters = CoverTreeParameters {
             total_nodes: atomic::AtomicUsize::new(1),
             scale_base: self.scale_base,
@@ -405,11 +626,16 @@ impl CoverTreeBuilder {
             partition_type: self.partition_type,
             point_cloud,
             verbosity: self.verbosity,
-            rng_seed: self.rng_seed,
+            rng_seed: match self.rng_seed {
+                None if self.deterministic => Some(0),
+                rng_seed => rng_seed,
+            },
             plugins: RwLock::new(TreePluginSet::new()),
         };
 
-        let root = BuilderNode::new(&parameters, self.partition_type)?;
+        let root = self.install(|| {
+            BuilderNode::new(&parameters, self.partition_type, point_indexes, &mut stats)
+        })?;
         let root_address = root.address();
         let scale_range = root_address.scale_index() - parameters.min_res_index;
         let mut layers = Vec::with_capacity(scale_range as usize);
@@ -418,17 +644,20 @@ impl CoverTreeBuilder {
             layers.push(CoverLayerWriter::new(parameters.min_res_index + i as i32));
         }
 
-        let (node_sender, node_receiver): (
-            Sender<NodeSplitResult<D>>,
-            Receiver<NodeSplitResult<D>>,
-        ) = unbounded();
-
-        let node_sender = Arc::new(node_sender);
         let parameters = Arc::new(parameters);
-        root.split_parallel(&parameters, &node_sender);
-        let mut pb = ProgressBar::new(1u64);
+        let mut monitor = BuildMonitor {
+            pb: ProgressBar::new(1u64),
+            progress: BuildProgress {
+                nodes_created: 0,
+                nodes_total: 1,
+                points_remaining: point_count,
+                scale_index: root_address.scale_index(),
+            },
+            observer: self.progress_observer.as_ref(),
+            cancellation: self.cancellation.as_ref(),
+        };
         if parameters.verbosity > 1 {
-            pb.format("╢▌▌░╟");
+            monitor.pb.format("╢▌▌░╟");
         }
 
         let (_final_addresses_reader, final_addresses) = monomap::new();
@@ -438,34 +667,101 @@ impl CoverTreeBuilder {
             layers,
             root_address,
             final_addresses,
+            mapped_addresses: None,
+            loading: None,
+            saved_plugins: HashMap::new(),
+            plugin_encoders: Vec::new(),
         };
 
         let mut inserted_nodes: usize = 0;
         let now = Instant::now();
-        loop {
-            if let Ok(res) = node_receiver.recv() {
-                let (new_addr, new_node) = res.unwrap();
-                for singleton in new_node.singletons() {
-                    cover_tree.final_addresses.insert(*singleton, new_addr);
-                }
-                if new_node.is_leaf() {
-                    cover_tree
-                        .final_addresses
-                        .insert(new_addr.point_index(), new_addr);
+        // Stops the split tasks when the build errors or is cancelled
+        let stop = CancellationToken::new();
+        if self.deterministic {
+            let mut unsplit_nodes = vec![root];
+            while !unsplit_nodes.is_empty() {
+                monitor.check_cancelled()?;
+                let cancellation = &self.cancellation;
+                let stop = &stop;
+                let splits: Vec<_> = self.install(|| {
+                    unsplit_nodes
+                        .into_par_iter()
+                        .map(|node| {
+                            if stop.is_cancelled() {
+                                return Err(GokoError::BuildCancelled);
+                            }
+                            if let Some(c) = cancellation {
+                                if c.is_cancelled() {
+                                    stop.cancel();
+                                    return Err(GokoError::BuildCancelled);
+                                }
+                            }
+                            let mut stats = QueryStats::default();
+                            let split = node.split(&parameters, &mut stats);
+                            if split.is_err() {
+                                stop.cancel();
+                            }
+                            let (new_node, children) = split?;
+                            Ok((new_node, children, stats))
+                        })
+                        .collect()
+                });
+                let mut new_nodes = Vec::with_capacity(splits.len());
+                unsplit_nodes = Vec::new();
+                for split in splits {
+                    let (new_node, children, split_stats) = split?;
+                    stats += split_stats;
+                    new_nodes.push(new_node);
+                    unsplit_nodes.extend(children);
                 }
-                unsafe {
-                    cover_tree.insert_raw(new_addr, new_node);
-                }
-                inserted_nodes += 1;
-                if parameters.verbosity > 1 {
-                    pb.total = parameters.total_nodes.load(atomic::Ordering::SeqCst) as u64;
-                    pb.inc();
+                new_nodes.sort_by_key(|n| n.address());
+                for new_node in new_nodes {
+                    insert_built_node(&mut cover_tree, &mut monitor, new_node.address(), new_node);
+                    inserted_nodes += 1;
                 }
             }
-            // Stop if there are enough done, and there are no more outstanding parameter references
-            if inserted_nodes == parameters.total_nodes.load(atomic::Ordering::SeqCst) {
-                break;
+        } else {
+            let (node_sender, node_receiver): (
+                Sender<NodeSplitResult<D>>,
+                Receiver<NodeSplitResult<D>>,
+            ) = unbounded();
+            let node_sender = Arc::new(node_sender);
+            root.split_parallel(
+                &parameters,
+                &node_sender,
+                &stop,
+                self.thread_pool.as_deref(),
+            );
+            // The tasks hold the other senders, so the channel closes when they've all stopped
+            drop(node_sender);
+            let mut receive_nodes = || -> GokoResult<()> {
+                loop {
+                    monitor.check_cancelled()?;
+                    match node_receiver.recv() {
+                        Ok(res) => {
+                            let (new_addr, new_node, split_stats) = res?;
+                            stats += split_stats;
+                            insert_built_node(&mut cover_tree, &mut monitor, new_addr, new_node);
+                            inserted_nodes += 1;
+                        }
+                        Err(_) => {
+                            monitor.check_cancelled()?;
+                            return Ok(());
+                        }
+                    }
+                    // Stop if there are enough done, and there are no more outstanding parameter references
+                    if inserted_nodes == parameters.total_nodes.load(atomic::Ordering::SeqCst) {
+                        return Ok(());
+                    }
+                }
+            };
+            let received = receive_nodes();
+            if received.is_err() {
+                stop.cancel();
             }
+            // Wait for the tasks that are still running, so none of them are left writing after the build returns
+            while node_receiver.recv().is_ok() {}
+            received?;
         }
         if parameters.verbosity > 1 {
             println!("\nWriting layers...");
@@ -480,13 +776,40 @@ impl CoverTreeBuilder {
                 (inserted_nodes as f32) / now.elapsed().as_secs_f32()
             );
         }
-        Ok(cover_tree)
+        Ok((cover_tree, stats))
+    }
+}
+
+/// Adds a node that's been split to the tree and records the points that end on it.
+fn insert_built_node<D: PointCloud>(
+    cover_tree: &mut CoverTreeWriter<D>,
+    monitor: &mut BuildMonitor,
+    new_addr: NodeAddress,
+    new_node: CoverNode<D>,
+) {
+    monitor.node_inserted(&cover_tree.parameters, &new_node);
+    for singleton in new_node.singletons() {
+        cover_tree.final_addresses.insert(*singleton, new_addr);
+    }
+    if new_node.is_leaf() {
+        cover_tree
+            .final_addresses
+            .insert(new_addr.point_index(), new_addr);
+    }
+    unsafe {
+        cover_tree.insert_raw(new_addr, new_node);
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::covertree::tests::random_data;
+    use crate::tree_file_format::CoreProto;
+    use crate::tree_header::TreeFileHeader;
+    use crate::utils::encode_tree;
+    use protobuf::Message;
+    use std::sync::Mutex;
     use std::{thread, time};
 
     pub fn create_test_parameters(
@@ -517,7 +840,13 @@ mod tests {
         data.push(0.0);
 
         let test_parameters = create_test_parameters(data, 1);
-        let build_node = BuilderNode::new(&test_parameters, PartitionType::Nearest).unwrap();
+        let build_node = BuilderNode::new(
+            &test_parameters,
+            PartitionType::Nearest,
+            test_parameters.point_cloud.reference_indexes(),
+            &mut QueryStats::default(),
+        )
+        .unwrap();
         let (scale_index, center_index) = (
             build_node.address().scale_index(),
             build_node.address().point_index(),
@@ -532,7 +861,9 @@ mod tests {
         println!("The scale_index should be 0, but is {}", scale_index);
         assert!(scale_index == 0);
 
-        let (new_node, unfinished_nodes) = build_node.split(&test_parameters).unwrap();
+        let (new_node, unfinished_nodes) = build_node
+            .split(&test_parameters, &mut QueryStats::default())
+            .unwrap();
         println!("New Node: {:#?}", new_node);
         let split_count = test_parameters.total_nodes.load(atomic::Ordering::SeqCst) - 1;
         println!(
@@ -558,7 +889,13 @@ mod tests {
         data.push(0.0);
 
         let test_parameters = create_test_parameters(data, 1);
-        let build_node = BuilderNode::new(&test_parameters, PartitionType::First).unwrap();
+        let build_node = BuilderNode::new(
+            &test_parameters,
+            PartitionType::First,
+            test_parameters.point_cloud.reference_indexes(),
+            &mut QueryStats::default(),
+        )
+        .unwrap();
         let (scale_index, center_index) = (
             build_node.address().scale_index(),
             build_node.address().point_index(),
@@ -573,7 +910,9 @@ mod tests {
         println!("The scale_index should be 0, but is {}", scale_index);
         assert!(scale_index == 0);
 
-        let (new_node, unfinished_nodes) = build_node.split(&test_parameters).unwrap();
+        let (new_node, unfinished_nodes) = build_node
+            .split(&test_parameters, &mut QueryStats::default())
+            .unwrap();
         let split_count = test_parameters.total_nodes.load(atomic::Ordering::SeqCst) - 1;
         println!(
             "We should have split count be equal to the work count: split {} , work {}",
@@ -594,15 +933,26 @@ mod tests {
         let data = vec![0.49, 0.491, -0.49, 0.0];
         let test_parameters = create_test_parameters(data, 1);
 
-        let build_node = BuilderNode::new(&test_parameters, PartitionType::First).unwrap();
+        let build_node = BuilderNode::new(
+            &test_parameters,
+            PartitionType::First,
+            test_parameters.point_cloud.reference_indexes(),
+            &mut QueryStats::default(),
+        )
+        .unwrap();
 
         let (node_sender, node_receiver): (
-            Sender<GokoResult<(NodeAddress, CoverNode<DefaultCloud<L2>>)>>,
-            Receiver<GokoResult<(NodeAddress, CoverNode<DefaultCloud<L2>>)>>,
+            Sender<NodeSplitResult<DefaultCloud<L2>>>,
+            Receiver<NodeSplitResult<DefaultCloud<L2>>>,
         ) = unbounded();
         let node_sender = Arc::new(node_sender);
 
-        build_node.split_parallel(&test_parameters, &node_sender);
+        build_node.split_parallel(
+            &test_parameters,
+            &node_sender,
+            &CancellationToken::new(),
+            None,
+        );
         thread::sleep(time::Duration::from_millis(100));
         let split_count = test_parameters.total_nodes.load(atomic::Ordering::SeqCst) - 1;
         println!(
@@ -613,7 +963,7 @@ mod tests {
         assert!(split_count + 1 == node_receiver.len());
         assert!(split_count == 3);
         while let Ok(pat) = node_receiver.try_recv() {
-            let (na, node) = pat.unwrap();
+            let (na, node, _stats) = pat.unwrap();
             println!("{:?}", node);
             match na.into() {
                 Some((-1, 3)) => assert!(!node.is_leaf()),
@@ -631,15 +981,26 @@ mod tests {
         let data = vec![0.49, 0.491, -0.49, 0.0];
         let test_parameters = create_test_parameters(data, 1);
 
-        let build_node = BuilderNode::new(&test_parameters, PartitionType::Nearest).unwrap();
+        let build_node = BuilderNode::new(
+            &test_parameters,
+            PartitionType::Nearest,
+            test_parameters.point_cloud.reference_indexes(),
+            &mut QueryStats::default(),
+        )
+        .unwrap();
 
         let (node_sender, node_receiver): (
-            Sender<GokoResult<(NodeAddress, CoverNode<DefaultCloud<L2>>)>>,
-            Receiver<GokoResult<(NodeAddress, CoverNode<DefaultCloud<L2>>)>>,
+            Sender<NodeSplitResult<DefaultCloud<L2>>>,
+            Receiver<NodeSplitResult<DefaultCloud<L2>>>,
         ) = unbounded();
         let node_sender = Arc::new(node_sender);
 
-        build_node.split_parallel(&test_parameters, &node_sender);
+        build_node.split_parallel(
+            &test_parameters,
+            &node_sender,
+            &CancellationToken::new(),
+            None,
+        );
         thread::sleep(time::Duration::from_millis(100));
         let split_count = test_parameters.total_nodes.load(atomic::Ordering::SeqCst) - 1;
         println!(
@@ -650,7 +1011,7 @@ mod tests {
         assert!(split_count + 1 == node_receiver.len());
         assert!(split_count == 3);
         while let Ok(pat) = node_receiver.try_recv() {
-            let (na, node) = pat.unwrap();
+            let (na, node, _stats) = pat.unwrap();
             println!("{:?}", node);
 
             match na.into() {
@@ -677,6 +1038,7 @@ mod tests {
             verbosity: 0,
             partition_type: PartitionType::First,
             rng_seed: Some(0),
+            ..Default::default()
         };
         let tree = builder.build(point_cloud).unwrap();
         let reader = tree.reader();
@@ -727,6 +1089,7 @@ mod tests {
             verbosity: 0,
             partition_type: PartitionType::First,
             rng_seed: Some(0),
+            ..Default::default()
         };
         let tree = builder.build(point_cloud).unwrap();
         let reader = tree.reader();
@@ -737,4 +1100,196 @@ mod tests {
             .is_some());
         assert!(reader.no_dangling_refs());
     }
+
+    #[test]
+    fn validation_policies() {
+        let data = vec![0.49, 0.491, f32::NAN, 0.49, 0.0];
+        let point_cloud = Arc::new(DefaultCloud::<L2>::new(data, 1).unwrap());
+
+        let mut builder = CoverTreeBuilder::new();
+        builder
+            .set_rng_seed(0)
+            .set_validation(ValidationPolicy::Error);
+        assert!(builder.build(Arc::clone(&point_cloud)).is_err());
+        builder.set_validation(ValidationPolicy::Dedup);
+        assert!(builder.build(Arc::clone(&point_cloud)).is_err());
+
+        builder.set_validation(ValidationPolicy::Drop);
+        let tree = builder.build(point_cloud).unwrap();
+        let reader = tree.reader();
+        let root_count = reader
+            .get_node_and(reader.root_address(), |n| n.coverage_count())
+            .unwrap();
+        assert_eq!(root_count, 2);
+        assert!(reader.known_path(0).is_ok());
+        assert!(reader.no_dangling_refs());
+
+        let nans = Arc::new(DefaultCloud::<L2>::new(vec![f32::NAN; 3], 1).unwrap());
+        assert!(matches!(builder.build(nans), Err(GokoError::EmptyCoverSet)));
+    }
+
+    fn node_addresses<D: PointCloud>(tree: &CoverTreeWriter<D>) -> Vec<NodeAddress> {
+        let mut addresses = Vec::new();
+        for (_si, layer) in tree.reader().layers() {
+            layer.for_each_node(|_pi, n| addresses.push(n.address()));
+        }
+        addresses.sort();
+        addresses
+    }
+
+    #[test]
+    fn deterministic_builds_ignore_thread_count() {
+        let data = random_data(2000, 3, 0);
+        let point_cloud = Arc::new(DefaultCloud::<L2>::new(data, 3).unwrap());
+        for partition_type in &[PartitionType::Nearest, PartitionType::First] {
+            let mut builder = CoverTreeBuilder::new();
+            builder
+                .set_scale_base(1.3)
+                .set_leaf_cutoff(3)
+                .set_min_res_index(-8)
+                .set_rng_seed(7)
+                .set_deterministic(true);
+            builder.partition_type = *partition_type;
+
+            let encoded: Vec<Vec<u8>> = [1, 2, 5]
+                .iter()
+                .map(|num_threads| {
+                    let pool = rayon::ThreadPoolBuilder::new()
+                        .num_threads(*num_threads)
+                        .build()
+                        .unwrap();
+                    let tree = pool.install(|| builder.build(Arc::clone(&point_cloud)).unwrap());
+                    encode_tree(&tree).unwrap()
+                })
+                .collect();
+            assert!(encoded.iter().all(|e| e == &encoded[0]));
+            let (_header, body) = TreeFileHeader::decode(&encoded[0]).unwrap();
+            let proto = CoreProto::parse_from_bytes(body.proto).unwrap();
+            assert_eq!(proto.get_name_map().len(), point_cloud.len());
+            assert_eq!(proto.get_name_map()["17"], 17);
+
+            // The same nodes as the usual build, just put in the layers in order
+            let tree = builder.build(Arc::clone(&point_cloud)).unwrap();
+            assert!(tree.reader().validate().unwrap().is_empty());
+            builder.set_deterministic(false);
+            let parallel_tree = builder.build(Arc::clone(&point_cloud)).unwrap();
+            assert_eq!(node_addresses(&tree), node_addresses(&parallel_tree));
+        }
+    }
+
+    #[test]
+    fn progress_observer_sees_every_node() {
+        let data = random_data(1000, 3, 0);
+        let point_cloud = Arc::new(DefaultCloud::<L2>::new(data, 3).unwrap());
+        for deterministic in &[false, true] {
+            let updates = Arc::new(Mutex::new(Vec::new()));
+            let observer_updates = Arc::clone(&updates);
+            let mut builder = CoverTreeBuilder::new();
+            builder
+                .set_scale_base(1.3)
+                .set_leaf_cutoff(3)
+                .set_min_res_index(-8)
+                .set_deterministic(*deterministic)
+                .set_progress_observer(move |p| observer_updates.lock().unwrap().push(*p));
+            let tree = builder.build(Arc::clone(&point_cloud)).unwrap();
+            let reader = tree.reader();
+
+            let updates = updates.lock().unwrap();
+            assert_eq!(updates.len(), reader.node_count());
+            assert!(updates
+                .iter()
+                .enumerate()
+                .all(|(i, p)| p.nodes_created == i + 1 && p.nodes_created <= p.nodes_total));
+            let last = updates.last().unwrap();
+            assert_eq!(last.nodes_total, reader.node_count());
+            assert_eq!(last.points_remaining, 0);
+            assert_eq!(
+                last.scale_index,
+                reader.layers().map(|(si, _)| si).min().unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn cancelled_builds_stop() {
+        let data = random_data(1000, 3, 0);
+        let point_cloud = Arc::new(DefaultCloud::<L2>::new(data, 3).unwrap());
+        for deterministic in &[false, true] {
+            let token = CancellationToken::new();
+            let observer_token = token.clone();
+            let mut builder = CoverTreeBuilder::new();
+            builder
+                .set_scale_base(1.3)
+                .set_leaf_cutoff(3)
+                .set_min_res_index(-8)
+                .set_deterministic(*deterministic)
+                .set_cancellation(token.clone())
+                .set_progress_observer(move |p| {
+                    if p.nodes_created == 10 {
+                        observer_token.cancel();
+                    }
+                });
+            match builder.build(Arc::clone(&point_cloud)) {
+                Err(GokoError::BuildCancelled) => {}
+                other => panic!("Expected a cancelled build, got {:?}", other.map(|_| ())),
+            }
+            assert!(token.is_cancelled());
+            // None of the split tasks are still holding on to the tree
+            assert_eq!(Arc::strong_count(&point_cloud), 1);
+        }
+
+        let token = CancellationToken::new();
+        token.cancel();
+        let mut builder = CoverTreeBuilder::new();
+        builder.set_cancellation(token);
+        assert!(matches!(
+            builder.build(point_cloud),
+            Err(GokoError::BuildCancelled)
+        ));
+    }
+
+    #[test]
+    fn builds_on_a_thread_pool() {
+        let data = random_data(1000, 3, 0);
+        let point_cloud = Arc::new(DefaultCloud::<L2>::new(data, 3).unwrap());
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        let mut builder = CoverTreeBuilder::new();
+        builder
+            .set_scale_base(1.3)
+            .set_leaf_cutoff(3)
+            .set_min_res_index(-8)
+            .set_rng_seed(3)
+            .set_thread_pool(Arc::new(pool));
+        let tree = builder.build(Arc::clone(&point_cloud)).unwrap();
+        assert!(tree.reader().validate().unwrap().is_empty());
+
+        builder.thread_pool = None;
+        let global_tree = builder.build(point_cloud).unwrap();
+        assert_eq!(node_addresses(&tree), node_addresses(&global_tree));
+    }
+
+    #[test]
+    fn build_stats() {
+        let data = random_data(1000, 3, 0);
+        let point_cloud = Arc::new(DefaultCloud::<L2>::new(data, 3).unwrap());
+        for deterministic in &[false, true] {
+            let mut builder = CoverTreeBuilder::new();
+            builder
+                .set_scale_base(1.3)
+                .set_leaf_cutoff(3)
+                .set_min_res_index(-8)
+                .set_rng_seed(0)
+                .set_deterministic(*deterministic);
+            let (tree, stats) = builder.build_with_stats(Arc::clone(&point_cloud)).unwrap();
+            let reader = tree.reader();
+            assert_eq!(stats.nodes_visited, reader.node_count());
+            // The root's cache has the distance from its center to every other point
+            assert!(stats.distance_evaluations >= 999);
+            assert_eq!(stats.heap_pushes, 0);
+            assert_eq!(stats.singletons_scanned, 0);
+        }
+    }
 }
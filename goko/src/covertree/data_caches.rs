@@ -16,7 +16,8 @@
 * specific language governing permissions and limitations
 * under the License.
 */
-use crate::errors::GokoResult;
+use super::query_tools::QueryStats;
+use crate::errors::{GokoError, GokoResult};
 use pointcloud::*;
 use rand::rngs::SmallRng;
 use rand::seq::SliceRandom;
@@ -77,10 +78,12 @@ impl UncoveredData {
         radius: f32,
         point_cloud: &Arc<D>,
         rng: &mut SmallRng,
+        stats: &mut QueryStats,
     ) -> GokoResult<FirstCoveredData> {
         let new_center: usize = rng.gen_range(0..self.coverage.len());
         let center_index = self.coverage.remove(new_center);
         let dists = point_cloud.distances_to_point_index(center_index, &self.coverage)?;
+        stats.distance_evaluations += dists.len();
 
         let mut close_index = Vec::with_capacity(self.coverage.len());
         let mut close_dist = Vec::with_capacity(self.coverage.len());
@@ -128,10 +131,14 @@ fn find_split(dist_indexes: &[(f32, usize)], thresh: f32) -> usize {
 }
 
 impl FirstCoveredData {
-    pub(crate) fn new<D: PointCloud>(point_cloud: &Arc<D>) -> GokoResult<FirstCoveredData> {
-        let mut coverage = point_cloud.reference_indexes();
-        let center_index = coverage.pop().unwrap();
+    pub(crate) fn new<D: PointCloud>(
+        point_cloud: &Arc<D>,
+        mut coverage: Vec<usize>,
+        stats: &mut QueryStats,
+    ) -> GokoResult<FirstCoveredData> {
+        let center_index = coverage.pop().ok_or(GokoError::EmptyCoverSet)?;
         let dists = point_cloud.distances_to_point_index(center_index, &coverage)?;
+        stats.distance_evaluations += dists.len();
         Ok(FirstCoveredData {
             dists,
             coverage,
@@ -186,10 +193,14 @@ pub(crate) struct NearestCoveredData {
 }
 
 impl NearestCoveredData {
-    pub(crate) fn new<D: PointCloud>(point_cloud: &Arc<D>) -> GokoResult<NearestCoveredData> {
-        let mut point_indexes = point_cloud.reference_indexes();
-        let center_index = point_indexes.pop().unwrap();
+    pub(crate) fn new<D: PointCloud>(
+        point_cloud: &Arc<D>,
+        mut point_indexes: Vec<usize>,
+        stats: &mut QueryStats,
+    ) -> GokoResult<NearestCoveredData> {
+        let center_index = point_indexes.pop().ok_or(GokoError::EmptyCoverSet)?;
         let center_dists = point_cloud.distances_to_point_index(center_index, &point_indexes)?;
+        stats.distance_evaluations += center_dists.len();
         let dists = vec![];
         let centers = vec![];
         Ok(NearestCoveredData {
@@ -206,6 +217,7 @@ impl NearestCoveredData {
         radius: f32,
         point_cloud: &Arc<D>,
         rng: &mut SmallRng,
+        stats: &mut QueryStats,
     ) -> GokoResult<()> {
         let mut coverage: Vec<bool> = self.center_dists.iter().map(|d| d < &radius).collect();
 
@@ -220,6 +232,7 @@ impl NearestCoveredData {
             let center_index = *uncovered_indexes.choose(rng).unwrap();
             let new_dists =
                 point_cloud.distances_to_point_index(center_index, &self.point_indexes)?;
+            stats.distance_evaluations += new_dists.len();
             coverage
                 .iter_mut()
                 .zip(&new_dists)
@@ -281,8 +294,9 @@ impl NearestCoveredData {
         radius: f32,
         point_cloud: &Arc<D>,
         rng: &mut SmallRng,
+        stats: &mut QueryStats,
     ) -> GokoResult<(NearestCoveredData, Vec<NearestCoveredData>)> {
-        self.cover_thyself(radius, point_cloud, rng)?;
+        self.cover_thyself(radius, point_cloud, rng, stats)?;
         Ok(self.assign_to_nearest())
     }
 
@@ -320,7 +334,10 @@ mod tests {
 
         let point_cloud = DefaultLabeledCloud::<L2>::new_simple(data, 1, labels);
 
-        let cache = FirstCoveredData::new(&Arc::new(point_cloud)).unwrap();
+        let indexes = point_cloud.reference_indexes();
+        let cache =
+            FirstCoveredData::new(&Arc::new(point_cloud), indexes, &mut QueryStats::default())
+                .unwrap();
         let (close, far) = cache.split(1.0).unwrap();
 
         assert_eq!(1, close.len());
@@ -344,7 +361,12 @@ mod tests {
         };
         let mut small_rng = SmallRng::seed_from_u64(0);
         let close = cache
-            .pick_center(1.0, &point_cloud, &mut small_rng)
+            .pick_center(
+                1.0,
+                &point_cloud,
+                &mut small_rng,
+                &mut QueryStats::default(),
+            )
             .unwrap();
 
         assert!(!close.coverage.contains(&close.center_index));
@@ -370,7 +392,10 @@ mod tests {
         //data.sort_unstable_by(|a, b| (a).partial_cmp(&b).unwrap_or(Ordering::Equal));
         let point_cloud = DefaultLabeledCloud::<L2>::new_simple(data.clone(), 1, labels);
 
-        let cache = FirstCoveredData::new(&Arc::new(point_cloud)).unwrap();
+        let indexes = point_cloud.reference_indexes();
+        let cache =
+            FirstCoveredData::new(&Arc::new(point_cloud), indexes, &mut QueryStats::default())
+                .unwrap();
 
         let thresh = 0.5;
         let mut true_close = Vec::new();
@@ -402,10 +427,20 @@ mod tests {
 
         let point_cloud = Arc::new(DefaultLabeledCloud::<L2>::new_simple(data, 1, labels));
 
-        let mut cache = NearestCoveredData::new(&point_cloud).unwrap();
+        let mut cache = NearestCoveredData::new(
+            &point_cloud,
+            point_cloud.reference_indexes(),
+            &mut QueryStats::default(),
+        )
+        .unwrap();
         let mut small_rng = SmallRng::seed_from_u64(0);
         cache
-            .cover_thyself(1.0, &point_cloud, &mut small_rng)
+            .cover_thyself(
+                1.0,
+                &point_cloud,
+                &mut small_rng,
+                &mut QueryStats::default(),
+            )
             .unwrap();
 
         assert_eq!(1, cache.dists.len());
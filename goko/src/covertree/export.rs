@@ -0,0 +1,274 @@
+//! # Tree Export
+//!
+//! Walks a tree from the root and writes it out for visualization, either as a Graphviz DOT graph or as a nested
+//! JSON hierarchy that `d3.hierarchy` can read directly. Large trees can be cut down with a maximum depth and by
+//! pruning subtrees that cover only a few points.
+//!
+//! ```rust,ignore
+//! let mut exporter = TreeExporter::new(&reader);
+//! exporter
+//!     .set_max_depth(4)
+//!     .set_min_coverage(10)
+//!     .add_plugin_attribute::<Dirichlet>("dirichlet");
+//! std::fs::write("tree.dot", exporter.to_dot())?;
+//! ```
+
+use super::node::CoverNode;
+use super::CoverTreeReader;
+use crate::*;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Computes an extra attribute of a node. Return `None` to leave it off for that node.
+pub type AttributeFn<'a, D> = Box<dyn Fn(&CoverNode<D>) -> Option<Value> + 'a>;
+
+/// A node of the exported hierarchy. The `children` field makes this readable by `d3.hierarchy`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportNode {
+    /// The node's address, as `"(scale_index, center_index)"`
+    pub name: String,
+    /// The scale index of the node
+    pub scale_index: i32,
+    /// The index of the node's center
+    pub center_index: usize,
+    /// The radius of the node, 0 for leaves that only cover their center
+    pub radius: f32,
+    /// The number of points covered by this node
+    pub coverage_count: usize,
+    /// The number of singletons attached to this node
+    pub singletons_len: usize,
+    /// The number of covered points in children that were left out by the depth limit or pruning
+    pub hidden_coverage: usize,
+    /// The label summary of the node, if requested and present
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label_summary: Option<Value>,
+    /// The extra attributes that were added to the exporter
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub attributes: BTreeMap<String, Value>,
+    /// The exported children
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<ExportNode>,
+}
+
+/// Configures and runs an export of a tree, see the [module docs](crate::covertree::export).
+pub struct TreeExporter<'a, D: PointCloud> {
+    reader: &'a CoverTreeReader<D>,
+    max_depth: Option<usize>,
+    min_coverage: usize,
+    label_summaries: bool,
+    attributes: Vec<(String, AttributeFn<'a, D>)>,
+}
+
+impl<'a, D: PointCloud> TreeExporter<'a, D> {
+    /// An exporter for the whole tree, without label summaries or extra attributes.
+    pub fn new(reader: &'a CoverTreeReader<D>) -> TreeExporter<'a, D> {
+        TreeExporter {
+            reader,
+            max_depth: None,
+            min_coverage: 0,
+            label_summaries: false,
+            attributes: Vec::new(),
+        }
+    }
+
+    /// Only export nodes this many steps from the root, the root is at depth 0.
+    pub fn set_max_depth(&mut self, x: usize) -> &mut Self {
+        self.max_depth = Some(x);
+        self
+    }
+
+    /// Leave out the children that cover fewer than this many points, along with their subtrees.
+    pub fn set_min_coverage(&mut self, x: usize) -> &mut Self {
+        self.min_coverage = x;
+        self
+    }
+
+    /// Include the label summaries of the nodes. These need to be generated on the tree first.
+    pub fn set_label_summaries(&mut self, x: bool) -> &mut Self {
+        self.label_summaries = x;
+        self
+    }
+
+    /// Adds an attribute computed from each node.
+    pub fn add_attribute<F>(&mut self, name: &str, f: F) -> &mut Self
+    where
+        F: Fn(&CoverNode<D>) -> Option<Value> + 'a,
+    {
+        self.attributes.push((name.to_string(), Box::new(f)));
+        self
+    }
+
+    /// Adds the serialized node plugin of type `T` as an attribute, for example the `Dirichlet` alphas.
+    pub fn add_plugin_attribute<T: Serialize + Send + Sync + 'static>(
+        &mut self,
+        name: &str,
+    ) -> &mut Self {
+        self.add_attribute(name, |n| {
+            n.get_plugin_and::<T, _, _>(|p| serde_json::to_value(p).ok())
+                .flatten()
+        })
+    }
+
+    /// Walks the tree and builds the hierarchy that the other formats are written from.
+    pub fn hierarchy(&self) -> ExportNode {
+        self.export_node(self.reader.root_address(), 0)
+            .expect("The root is missing from the tree")
+    }
+
+    fn export_node(&self, address: NodeAddress, depth: usize) -> Option<ExportNode> {
+        let (mut export, children) = self.reader.get_node_and(address, |n| {
+            let label_summary = if self.label_summaries {
+                n.label_summary()
+                    .and_then(|s| serde_json::to_value(&*s).ok())
+            } else {
+                None
+            };
+            let attributes = self
+                .attributes
+                .iter()
+                .filter_map(|(name, f)| f(n).map(|v| (name.clone(), v)))
+                .collect();
+            let export = ExportNode {
+                name: format!("({}, {})", address.scale_index(), address.point_index()),
+                scale_index: address.scale_index(),
+                center_index: address.point_index(),
+                radius: n.radius().max(0.0),
+                coverage_count: n.coverage_count(),
+                singletons_len: n.singletons_len(),
+                hidden_coverage: 0,
+                label_summary,
+                attributes,
+                children: Vec::new(),
+            };
+            (export, n.children().map(|c| c.to_vec()).unwrap_or_default())
+        })?;
+
+        for child in children {
+            let coverage = self
+                .reader
+                .get_node_and(child, |c| c.coverage_count())
+                .unwrap_or(0);
+            let too_deep = matches!(self.max_depth, Some(d) if depth >= d);
+            if too_deep || coverage < self.min_coverage {
+                export.hidden_coverage += coverage;
+            } else if let Some(child_export) = self.export_node(child, depth + 1) {
+                export.children.push(child_export);
+            }
+        }
+        Some(export)
+    }
+
+    /// The nested JSON hierarchy.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(&self.hierarchy()).unwrap()
+    }
+
+    /// A Graphviz DOT digraph, with the node's properties in its label.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph covertree {\n    node [shape=box];\n");
+        let mut unvisited = vec![self.hierarchy()];
+        while let Some(node) = unvisited.pop() {
+            let id = dot_id(&node);
+            let mut label = format!(
+                "{}\\nradius: {}\\ncoverage: {}\\nsingletons: {}",
+                node.name, node.radius, node.coverage_count, node.singletons_len
+            );
+            if node.hidden_coverage > 0 {
+                write!(label, "\\nhidden: {}", node.hidden_coverage).unwrap();
+            }
+            if let Some(summary) = &node.label_summary {
+                write!(label, "\\nlabels: {}", summary).unwrap();
+            }
+            for (name, value) in &node.attributes {
+                write!(label, "\\n{}: {}", name, value).unwrap();
+            }
+            writeln!(
+                dot,
+                "    {} [label=\"{}\"];",
+                id,
+                label.replace('"', "\\\"")
+            )
+            .unwrap();
+            for child in &node.children {
+                writeln!(dot, "    {} -> {};", id, dot_id(child)).unwrap();
+            }
+            unvisited.extend(node.children);
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+fn dot_id(node: &ExportNode) -> String {
+    // Scale indexes can be negative, which a bare ID can't hold, so it's quoted
+    format!("\"{}_{}\"", node.scale_index, node.center_index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::build_basic_tree;
+    use crate::plugins::discrete::prelude::*;
+
+    fn count_nodes(node: &ExportNode) -> usize {
+        1 + node.children.iter().map(count_nodes).sum::<usize>()
+    }
+
+    #[test]
+    fn export_whole_tree() {
+        let mut tree = build_basic_tree();
+        tree.generate_summaries();
+        tree.add_plugin::<GokoDirichlet>(GokoDirichlet {});
+        let reader = tree.reader();
+
+        let mut exporter = TreeExporter::new(&reader);
+        exporter
+            .set_label_summaries(true)
+            .add_plugin_attribute::<Dirichlet>("dirichlet");
+        let hierarchy = exporter.hierarchy();
+        assert_eq!(count_nodes(&hierarchy), reader.node_count());
+        assert_eq!(hierarchy.coverage_count, 5);
+        assert!(hierarchy.label_summary.is_some());
+        assert!(hierarchy.attributes.contains_key("dirichlet"));
+
+        let json: Value = serde_json::from_str(&exporter.to_json()).unwrap();
+        assert_eq!(json["coverage_count"], 5);
+        assert!(json["children"].is_array());
+
+        let dot = exporter.to_dot();
+        assert!(dot.starts_with("digraph covertree {"));
+        assert_eq!(dot.matches(" -> ").count(), reader.node_count() - 1);
+        let root = reader.root_address();
+        assert!(dot.contains(&format!(
+            "    \"{}_{}\" [label=",
+            root.scale_index(),
+            root.point_index()
+        )));
+        assert!(dot.contains("\"-"));
+    }
+
+    #[test]
+    fn export_depth_and_pruning() {
+        let tree = build_basic_tree();
+        let reader = tree.reader();
+
+        let mut exporter = TreeExporter::new(&reader);
+        exporter.set_max_depth(0);
+        let root = exporter.hierarchy();
+        assert!(root.children.is_empty());
+        assert_eq!(
+            root.hidden_coverage + root.singletons_len,
+            root.coverage_count
+        );
+
+        let mut exporter = TreeExporter::new(&reader);
+        exporter.set_min_coverage(2);
+        let mut unvisited = vec![exporter.hierarchy()];
+        while let Some(node) = unvisited.pop() {
+            assert!(node.coverage_count >= 2);
+            unvisited.extend(node.children);
+        }
+    }
+}
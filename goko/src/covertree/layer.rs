@@ -35,17 +35,23 @@ use crate::monomap::{MonoReadHandle, MonoWriteHandle};
 use pointcloud::*;
 
 //use rayon;
+use super::mapped::MappedLayer;
 use super::node::*;
 use crate::tree_file_format::*;
 use crate::*;
+use std::collections::HashSet;
 use std::iter::FromIterator;
 
 /// Actual reader, primarily contains a read head to the hash-map.
 /// This also contains a reference to the scale_index so that it is easy to save and load. It is largely redundant,
 /// but helps with unit tests.
+///
+/// Layers of a tree opened from a memory mapped file, see `covertree::mapped`, read their nodes out of the file. The
+/// hash-map then only holds the nodes that plugins have been attached to.
 pub struct CoverLayerReader<D: PointCloud> {
     scale_index: i32,
     node_reader: MonoReadHandle<usize, CoverNode<D>>,
+    mapped: Option<MappedLayer>,
 }
 
 impl<D: PointCloud> Clone for CoverLayerReader<D> {
@@ -53,6 +59,7 @@ impl<D: PointCloud> Clone for CoverLayerReader<D> {
         CoverLayerReader {
             scale_index: self.scale_index,
             node_reader: self.node_reader.clone(),
+            mapped: self.mapped.clone(),
         }
     }
 }
@@ -63,7 +70,11 @@ impl<D: PointCloud> CoverLayerReader<D> {
     where
         F: FnOnce(&CoverNode<D>) -> T,
     {
-        self.node_reader.get_and(&pi, |n| f(n))
+        match &self.mapped {
+            // Nodes are never removed from the map, so this can't miss a node that's copied in after the check
+            Some(mapped) if !self.node_reader.contains_key(&pi) => mapped.get_node_and(pi, f),
+            _ => self.node_reader.get_and(&pi, |n| f(n)),
+        }
     }
 
     /// Reads the contents of a plugin, due to the nature of the plugin map we have to access it with a
@@ -81,20 +92,36 @@ impl<D: PointCloud> CoverLayerReader<D> {
     }
 
     /// Read only access to all nodes.
-    pub fn for_each_node<F>(&self, f: F)
+    pub fn for_each_node<F>(&self, mut f: F)
     where
         F: FnMut(&usize, &CoverNode<D>),
     {
-        self.node_reader.for_each(f)
+        match &self.mapped {
+            Some(mapped) => {
+                for pi in mapped.center_indexes() {
+                    if self.node_reader.get_and(&pi, |n| f(&pi, n)).is_none() {
+                        mapped.get_node_and(pi, |n| f(&pi, n));
+                    }
+                }
+            }
+            None => self.node_reader.for_each(f),
+        }
     }
 
     /// Maps all nodes on the layer, useful for collecting statistics.
-    pub fn map_nodes<Map, Target, Collector>(&self, f: Map) -> Collector
+    pub fn map_nodes<Map, Target, Collector>(&self, mut f: Map) -> Collector
     where
         Map: FnMut(&usize, &CoverNode<D>) -> Target,
         Collector: FromIterator<Target>,
     {
-        self.node_reader.map_into(f)
+        match &self.mapped {
+            Some(_) => {
+                let mut targets = Vec::with_capacity(self.len());
+                self.for_each_node(|pi, n| targets.push(f(pi, n)));
+                targets.into_iter().collect()
+            }
+            None => self.node_reader.map_into(f),
+        }
     }
 
     /// Grabs all children indexes and allows you to query against them. Usually used at the tree level so that you
@@ -103,25 +130,29 @@ impl<D: PointCloud> CoverLayerReader<D> {
     where
         F: FnOnce(&[NodeAddress]) -> T,
     {
-        self.node_reader
-            .get_and(&pi, |n| n.children().map(f))
-            .flatten()
+        self.get_node_and(pi, |n| n.children().map(f)).flatten()
     }
 
     /// Grabs all children indexes and allows you to query against them. Usually used at the tree level so that you
     /// can access the child nodes as they are not on this layer.
     pub fn node_center_indexes(&self) -> Vec<usize> {
-        self.node_reader.map_into(|pi, _| *pi)
+        match &self.mapped {
+            Some(mapped) => mapped.center_indexes().collect(),
+            None => self.node_reader.map_into(|pi, _| *pi),
+        }
     }
 
     /// Total number of nodes on this layer
     pub fn len(&self) -> usize {
-        self.node_reader.len()
+        match &self.mapped {
+            Some(mapped) => mapped.len(),
+            None => self.node_reader.len(),
+        }
     }
 
     /// Total number of nodes on this layer
     pub fn is_empty(&self) -> bool {
-        self.node_reader.is_empty()
+        self.len() == 0
     }
 
     /// Read only accessor for the scale index.
@@ -134,6 +165,7 @@ impl<D: PointCloud> CoverLayerReader<D> {
         CoverLayerReader {
             scale_index: self.scale_index,
             node_reader: self.node_reader.factory().handle(),
+            mapped: self.mapped.clone(),
         }
     }
 }
@@ -142,6 +174,9 @@ impl<D: PointCloud> CoverLayerReader<D> {
 pub struct CoverLayerWriter<D: PointCloud> {
     scale_index: i32,
     node_writer: MonoWriteHandle<usize, CoverNode<D>>,
+    mapped: Option<MappedLayer>,
+    /// The mapped nodes that have been copied into the hash-map to be updated
+    copied: HashSet<usize>,
 }
 
 impl<D: PointCloud> CoverLayerWriter<D> {
@@ -150,6 +185,7 @@ impl<D: PointCloud> CoverLayerWriter<D> {
         CoverLayerReader {
             scale_index: self.scale_index,
             node_reader: self.node_writer.factory().handle(),
+            mapped: self.mapped.clone(),
         }
     }
 
@@ -159,13 +195,34 @@ impl<D: PointCloud> CoverLayerWriter<D> {
         CoverLayerWriter {
             scale_index,
             node_writer,
+            mapped: None,
+            copied: HashSet::new(),
         }
     }
 
+    /// A layer whose nodes are read out of a memory mapped file.
+    pub(crate) fn mapped(mapped: MappedLayer) -> CoverLayerWriter<D> {
+        let mut layer = CoverLayerWriter::new(mapped.scale_index());
+        layer.mapped = Some(mapped);
+        // Past the first two refreshes the copied nodes are only ever updated through the oplog, the second refresh
+        // would clone them and drop their plugins
+        layer.node_writer.refresh();
+        layer.node_writer.refresh();
+        layer
+    }
+
     pub(crate) unsafe fn update_node<F>(&mut self, pi: usize, update_fn: F)
     where
         F: Fn(&mut CoverNode<D>) + 'static + Send + Sync,
     {
+        // Mapped nodes are read only, so they're copied into the map the first time they're updated
+        if let Some(mapped) = &self.mapped {
+            if self.copied.insert(pi) {
+                if let Some(node) = mapped.get_node_and(pi, |n| n.clone()) {
+                    self.node_writer.insert(pi, node);
+                }
+            }
+        }
         self.node_writer.update(pi, update_fn);
     }
 
@@ -182,6 +239,8 @@ impl<D: PointCloud> CoverLayerWriter<D> {
         CoverLayerWriter {
             scale_index,
             node_writer,
+            mapped: None,
+            copied: HashSet::new(),
         }
     }
 
@@ -193,7 +252,7 @@ impl<D: PointCloud> CoverLayerWriter<D> {
     pub(crate) fn save(&self) -> LayerProto {
         let mut layer_proto = LayerProto::new();
         let mut node_protos = layer_proto.take_nodes();
-        self.node_writer.for_each(|_pi, node| {
+        self.reader().for_each_node(|_pi, node| {
             node_protos.push(node.save());
         });
         layer_proto.set_nodes(node_protos);
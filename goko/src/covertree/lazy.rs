@@ -0,0 +1,374 @@
+//! # Lazy Loading
+//!
+//! Loads a saved tree one layer at a time in a background thread, starting from the root. The tree is readable as
+//! soon as the file is read, and any access to a node or a layer blocks until its layer is in. As the coarse layers
+//! come first, `path`, `known_path` and the layer summaries near the root are available long before the leaves are.
+//!
+//! If a layer fails to load, the queries that need it return the error, as do `wait_for_layer` and `finish`. Iterating
+//! over the layers of a tree that failed to load panics, like a poisoned lock.
+//!
+//! ```rust,ignore
+//! let lazy_tree = load_tree_lazy("tree.dat", point_cloud)?;
+//! let reader = lazy_tree.reader();
+//! println!("{}", reader.layer_fractal_dim(reader.root_address().scale_index()));
+//! let tree = lazy_tree.finish()?;
+//! ```
+//!
+//! Plugins saved with the tree are attached once all the layers are in, so they're on the writer from `finish`.
+
+use super::layer::CoverLayerWriter;
+use super::node::CoverNode;
+use super::{CoverTreeReader, CoverTreeWriter};
+use crate::errors::{GokoError, GokoResult};
+use crate::tree_file_format::*;
+use crate::tree_header::TreeFileHeader;
+use crate::*;
+use protobuf::wire_format::WireType;
+use protobuf::{CodedInputStream, Message};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// The field number of `layers` in `CoreProto`.
+const LAYERS_FIELD: u32 = 11;
+
+/// Tracks how many layers have been loaded, counting from the layer that holds the root.
+pub(crate) struct LayerLoading {
+    loaded: AtomicUsize,
+    len: usize,
+    /// Why the loading stopped, if it stopped before all the layers were loaded
+    failure: Mutex<Option<String>>,
+    condvar: Condvar,
+}
+
+impl LayerLoading {
+    fn new(len: usize) -> LayerLoading {
+        LayerLoading {
+            loaded: AtomicUsize::new(0),
+            len,
+            failure: Mutex::new(None),
+            condvar: Condvar::new(),
+        }
+    }
+
+    fn loaded(&self) -> usize {
+        self.loaded.load(Ordering::Acquire)
+    }
+
+    fn set_loaded(&self, loaded: usize) {
+        let _guard = self.failure.lock().unwrap();
+        self.loaded.store(loaded, Ordering::Release);
+        self.condvar.notify_all();
+    }
+
+    fn set_failed(&self, failure: String) {
+        let mut guard = self.failure.lock().unwrap();
+        guard.get_or_insert(failure);
+        self.condvar.notify_all();
+    }
+
+    /// The error of a failed load.
+    pub(crate) fn failure(&self) -> GokoResult<()> {
+        match &*self.failure.lock().unwrap() {
+            Some(failure) => Err(GokoError::CorruptTreeFile(failure.clone())),
+            None => Ok(()),
+        }
+    }
+
+    /// Blocks until the layer at this internal index, and so all the layers above it, is loaded. Returns the error if
+    /// the loading stopped before it got to the layer.
+    pub(crate) fn wait_for(&self, internal_index: usize) -> GokoResult<()> {
+        let needed = self.len.saturating_sub(internal_index);
+        if self.loaded() >= needed {
+            return Ok(());
+        }
+        let mut guard = self.failure.lock().unwrap();
+        while self.loaded() < needed {
+            if let Some(failure) = &*guard {
+                return Err(GokoError::CorruptTreeFile(failure.clone()));
+            }
+            guard = self.condvar.wait(guard).unwrap();
+        }
+        Ok(())
+    }
+
+    /// Blocks until every layer is loaded.
+    pub(crate) fn wait_for_all(&self) -> GokoResult<()> {
+        self.wait_for(0)
+    }
+}
+
+/// Records a failure if the loading thread stops before all the layers are loaded, so that readers don't wait forever
+/// if it panics.
+struct LoadingGuard(Arc<LayerLoading>);
+
+impl Drop for LoadingGuard {
+    fn drop(&mut self) {
+        if self.0.loaded() < self.0.len {
+            self.0
+                .set_failed("the layers stopped loading before they were all in".to_string());
+        }
+    }
+}
+
+/// A tree whose layers are being loaded in the background, see the [module docs](crate::covertree::lazy).
+pub struct LazyCoverTree<D: PointCloud> {
+    reader: CoverTreeReader<D>,
+    loading: Arc<LayerLoading>,
+    handle: JoinHandle<GokoResult<CoverTreeWriter<D>>>,
+}
+
+impl<D: PointCloud> LazyCoverTree<D> {
+    /// Splits the layers off the protobuf and starts loading them. The rest of the protobuf is decoded right away,
+    /// and checked against the header if there is one.
+    pub(crate) fn load(
+        proto: Vec<u8>,
+        sections: HashMap<String, Vec<u8>>,
+        point_cloud: Arc<D>,
+        header: Option<&TreeFileHeader>,
+    ) -> GokoResult<LazyCoverTree<D>> {
+        let (rest, layer_ranges) = split_layers(&proto)?;
+        let cover_proto = CoreProto::parse_from_bytes(&rest).map_err(GokoError::from)?;
+        let parameters = Arc::new(CoverTreeWriter::parameters_from_proto(
+            &cover_proto,
+            point_cloud,
+            header.and_then(|h| h.parameters.rng_seed),
+        ));
+        if let Some(header) = header {
+            header.check_parameters(&parameters)?;
+        }
+        let root_address: NodeAddress = (
+            cover_proto.get_root_scale(),
+            cover_proto.get_root_index() as usize,
+        )
+            .into();
+        let layers = (0..layer_ranges.len())
+            .map(|i| CoverLayerWriter::new(parameters.min_res_index - 1 + i as i32))
+            .collect();
+        let loading = Arc::new(LayerLoading::new(layer_ranges.len()));
+        let (_final_addresses_reader, final_addresses) = monomap::new();
+
+        let mut writer = CoverTreeWriter {
+            parameters,
+            layers,
+            root_address,
+            final_addresses,
+            mapped_addresses: None,
+            loading: Some(Arc::clone(&loading)),
+            saved_plugins: HashMap::new(),
+            plugin_encoders: Vec::new(),
+        };
+        let reader = writer.reader();
+        let guard = LoadingGuard(Arc::clone(&loading));
+        let handle = thread::spawn(move || {
+            for (count, i) in (0..layer_ranges.len()).rev().enumerate() {
+                let loaded = LayerProto::parse_from_bytes(&proto[layer_ranges[i].clone()])
+                    .map_err(GokoError::from)
+                    .and_then(|layer_proto| writer.load_layer(i, &layer_proto));
+                if let Err(e) = loaded {
+                    guard.0.set_failed(e.to_string());
+                    return Err(e);
+                }
+                guard.0.set_loaded(count + 1);
+            }
+            drop(guard);
+            writer.loading = None;
+            writer.set_saved_plugins(sections);
+            Ok(writer)
+        });
+        Ok(LazyCoverTree {
+            reader,
+            loading,
+            handle,
+        })
+    }
+
+    /// A reader of the tree. Accessing a layer that hasn't been loaded blocks until it is.
+    pub fn reader(&self) -> CoverTreeReader<D> {
+        self.reader.clone()
+    }
+
+    /// The number of layers that have been loaded, counting from the layer that holds the root.
+    pub fn loaded_layers(&self) -> usize {
+        self.loading.loaded()
+    }
+
+    /// If all the layers are loaded.
+    pub fn is_loaded(&self) -> bool {
+        self.loading.loaded() >= self.loading.len
+    }
+
+    /// Blocks until the layer at this scale index is loaded, or returns the error the loading stopped with.
+    pub fn wait_for_layer(&self, scale_index: i32) -> GokoResult<()> {
+        self.reader.wait_for_layer(scale_index)
+    }
+
+    /// Waits for the rest of the tree to load and returns it with the saved plugins attached.
+    pub fn finish(self) -> GokoResult<CoverTreeWriter<D>> {
+        match self.handle.join() {
+            Ok(result) => result,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
+
+impl<D: PointCloud> CoverTreeWriter<D> {
+    /// Fills in the layer at this internal index and the final addresses of the points on it.
+    fn load_layer(&mut self, internal_index: usize, layer_proto: &LayerProto) -> GokoResult<()> {
+        let layer = &mut self.layers[internal_index];
+        if layer.scale_index() != layer_proto.get_scale_index() {
+            return Err(GokoError::CorruptTreeFile(format!(
+                "layer {} has scale index {}, expected {}",
+                internal_index,
+                layer_proto.get_scale_index(),
+                layer.scale_index()
+            )));
+        }
+        for node_proto in layer_proto.get_nodes() {
+            let node: CoverNode<D> = CoverNode::load(node_proto);
+            let address = node.address();
+            for singleton in node.singletons() {
+                self.final_addresses.insert(*singleton, address);
+            }
+            if node.is_leaf() {
+                self.final_addresses.insert(address.point_index(), address);
+            }
+            layer.insert_raw(address.point_index(), node);
+        }
+        layer.refresh();
+        layer.refresh();
+        self.final_addresses.refresh();
+        self.final_addresses.refresh();
+        Ok(())
+    }
+}
+
+/// Splits the encoded layers off an encoded `CoreProto`. Returns the rest of the message, which decodes to a
+/// `CoreProto` without any layers, and where each layer is in the message.
+fn split_layers(proto: &[u8]) -> GokoResult<(Vec<u8>, Vec<Range<usize>>)> {
+    let mut rest = Vec::new();
+    let mut layer_ranges = Vec::new();
+    let mut input = CodedInputStream::from_bytes(proto);
+    while !input.eof()? {
+        let start = input.pos() as usize;
+        let (field_number, wire_type) = input.read_tag_unpack()?;
+        if field_number == LAYERS_FIELD && wire_type == WireType::WireTypeLengthDelimited {
+            let len = input.read_raw_varint64()?;
+            // The protobuf reader can't skip more than this in one go
+            if len > u32::MAX as u64 {
+                return Err(GokoError::CorruptTreeFile(format!(
+                    "layer {} is {} bytes long, more than a protobuf can hold",
+                    layer_ranges.len(),
+                    len
+                )));
+            }
+            let len = len as usize;
+            let layer_start = input.pos() as usize;
+            input.skip_raw_bytes(len as u32)?;
+            layer_ranges.push(layer_start..layer_start + len);
+        } else {
+            input.skip_field(wire_type)?;
+            rest.extend_from_slice(&proto[start..input.pos() as usize]);
+        }
+    }
+    Ok((rest, layer_ranges))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::random_data;
+    use crate::covertree::CoverTreeBuilder;
+    use crate::utils::{load_tree, load_tree_lazy, save_tree};
+
+    #[test]
+    fn lazy_load_matches_eager_load() {
+        let data = random_data(1000, 3, 0);
+        let labels: Vec<i64> = (0..1000).map(|i| i % 3).collect();
+        let point_cloud = Arc::new(DefaultLabeledCloud::<L2>::new_simple(data, 3, labels));
+        let mut builder = CoverTreeBuilder::new();
+        builder
+            .set_scale_base(1.5)
+            .set_leaf_cutoff(5)
+            .set_min_res_index(-5)
+            .set_rng_seed(0);
+        let mut tree = builder.build(Arc::clone(&point_cloud)).unwrap();
+        tree.generate_summaries();
+
+        let dir = tempdir::TempDir::new("lazy_load").unwrap();
+        let path = dir.path().join("lazy.tree");
+        save_tree(&path, &tree).unwrap();
+        let eager_tree = load_tree(&path, Arc::clone(&point_cloud)).unwrap();
+        let eager = eager_tree.reader();
+        let lazy_tree = load_tree_lazy(&path, Arc::clone(&point_cloud)).unwrap();
+        let lazy = lazy_tree.reader();
+
+        assert_eq!(lazy.len(), eager.len());
+        let root_address = lazy.root_address();
+        assert_eq!(root_address, eager.root_address());
+        assert_eq!(
+            lazy.layer_fractal_dim(root_address.scale_index()),
+            eager.layer_fractal_dim(root_address.scale_index())
+        );
+        for pi in [0, 17, 999].iter() {
+            let point = point_cloud.point(*pi).unwrap();
+            assert_eq!(lazy.path(&point).unwrap(), eager.path(&point).unwrap());
+            assert_eq!(
+                lazy.known_path(*pi).unwrap(),
+                eager.known_path(*pi).unwrap()
+            );
+        }
+
+        let loaded = lazy_tree.finish().unwrap();
+        assert_eq!(lazy.node_count(), eager.node_count());
+        assert_eq!(loaded.reader().node_count(), eager.node_count());
+        assert!(loaded.loading.is_none());
+        let summary = loaded
+            .reader()
+            .get_node_label_summary(root_address)
+            .unwrap();
+        assert_eq!(summary.count(), 1000);
+    }
+
+    #[test]
+    fn lazy_load_failure() {
+        let (point_cloud, tree) = crate::covertree::tests::build_random_tree();
+        let mut proto = tree.save();
+        // The bottom layer is loaded last, give it the wrong scale index
+        proto.mut_layers()[0].set_scale_index(1000);
+        let lazy_tree = LazyCoverTree::load(
+            proto.write_to_bytes().unwrap(),
+            HashMap::new(),
+            Arc::clone(&point_cloud),
+            None,
+        )
+        .unwrap();
+        let reader = lazy_tree.reader();
+
+        let root_address = reader.root_address();
+        assert!(reader.wait_for_layer(root_address.scale_index()).is_ok());
+        let bottom = reader.scale_range().start - 1;
+        assert!(matches!(
+            reader.wait_for_layer(bottom),
+            Err(GokoError::CorruptTreeFile(_))
+        ));
+        assert!(reader.get_node_and((bottom, 0).into(), |_| ()).is_none());
+        assert!(matches!(
+            reader.known_path(0),
+            Err(GokoError::CorruptTreeFile(_))
+        ));
+        assert!(matches!(
+            reader.knn(&&[0.5f32, 0.5, 0.5][..], 5),
+            Err(GokoError::CorruptTreeFile(_))
+        ));
+        let node_count =
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| reader.node_count()));
+        assert!(node_count.is_err());
+        assert!(matches!(
+            lazy_tree.finish(),
+            Err(GokoError::CorruptTreeFile(_))
+        ));
+    }
+}
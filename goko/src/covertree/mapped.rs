@@ -0,0 +1,678 @@
+//! # Memory Mapped Trees
+//!
+//! A second on-disk layout for trees that is read in place instead of being decoded. Each layer is stored as three
+//! flat arrays: fixed size node records sorted by center index, the addresses of the children and the indexes of the
+//! singletons. Opening a file maps it into memory and only reads a small JSON table of contents, so a tree of any size
+//! opens quickly, and processes that map the same file share its pages. The nodes and the final addresses of the
+//! points are read in place when they're queried.
+//!
+//! The opened tree is an ordinary [`CoverTreeWriter`], whose layers read their nodes straight out of the map, so it
+//! has all the queries of a [`CoverTreeReader`] and can be served like any other tree. Plugins can be added to it, the
+//! nodes they're attached to are copied into memory.
+//!
+//! The layout is:
+//!
+//! * The 8 magic bytes `GOKOFLAT`
+//! * The format version, a little endian `u32`, followed by 4 bytes of padding
+//! * The length of the table of contents, a little endian `u64`
+//! * The table of contents, as JSON padded with spaces to a multiple of 8 bytes
+//! * The arrays, little endian and 8 byte aligned
+//!
+//! The table of contents holds a [`TreeFileHeader`], which is checked against the point cloud when the tree is opened.
+//! Its checksum covers the arrays, but checking it reads the whole file so that's left to [`verify_mapped_tree`].
+//! Plugins aren't saved in this layout, it's for serving queries.
+
+use super::layer::{CoverLayerReader, CoverLayerWriter};
+use super::node::CoverNode;
+use super::{CoverTreeParameters, CoverTreeReader, CoverTreeWriter};
+use crate::errors::{GokoError, GokoResult};
+use crate::plugins::TreePluginSet;
+use crate::tree_header::{checksum, TreeFileHeader};
+use crate::*;
+use fxhash::FxHasher64;
+use pointcloud::data_sources::Mmapf32;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs::File;
+use std::hash::Hasher;
+use std::io::{self, Write};
+use std::mem::size_of;
+use std::path::Path;
+use std::sync::{atomic, Arc, RwLock};
+
+/// The bytes every memory mapped tree file starts with.
+pub const MAPPED_TREE_MAGIC: &[u8; 8] = b"GOKOFLAT";
+/// The current version of the layout. Files with a larger version are rejected.
+pub const MAPPED_TREE_VERSION: u32 = 1;
+/// The magic bytes, the version, the padding and the length of the table of contents.
+const PREAMBLE_LEN: usize = 24;
+
+/// A node as it's stored in the file. Leaves have no children, routing nodes always have their nested child.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct NodeRecord {
+    center_index: u64,
+    /// The raw parent address, `NodeAddress::SINGLETON` for the root
+    parent_address: u64,
+    coverage_count: u64,
+    /// The position of the first child in the layer's children array
+    children_start: u64,
+    /// The position of the first singleton in the layer's singletons array
+    singletons_start: u64,
+    radius: f32,
+    children_len: u32,
+    singletons_len: u32,
+    padding: u32,
+}
+
+/// An array in the file, the offset is in bytes from the start of the arrays, the length is in elements.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Section {
+    offset: u64,
+    len: u64,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct LayerTable {
+    scale_index: i32,
+    nodes: Section,
+    children: Section,
+    singletons: Section,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TableOfContents {
+    header: TreeFileHeader,
+    root_address: NodeAddress,
+    /// In the same order as the layers of a `CoverTreeReader`, from the bottom up
+    layers: Vec<LayerTable>,
+    /// The node each point is a singleton of or the center of a leaf of, indexed by point index. Points that aren't
+    /// in the tree have `NodeAddress::SINGLETON`.
+    final_addresses: Section,
+}
+
+fn unsupported_platform() -> GokoError {
+    GokoError::IoError(io::Error::other(
+        "memory mapped trees need a 64 bit little endian platform",
+    ))
+}
+
+fn platform_supported() -> bool {
+    cfg!(target_endian = "little") && size_of::<usize>() == 8
+}
+
+/// The bytes of the items, all the types we write are plain integers and floats without implicit padding.
+fn as_bytes<T: Copy>(items: &[T]) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(items.as_ptr() as *const u8, std::mem::size_of_val(items)) }
+}
+
+/// Lays the arrays out one after another and hashes them.
+#[derive(Default)]
+struct ArrayLayout {
+    len: u64,
+    hasher: FxHasher64,
+}
+
+impl ArrayLayout {
+    /// Places the items after the last array. All the types we write are a multiple of 8 bytes, so every array
+    /// stays aligned, and hashing them one at a time is the same as hashing all the arrays at once.
+    fn push<T: Copy>(&mut self, items: &[T]) -> Section {
+        let bytes = as_bytes(items);
+        self.hasher.write(bytes);
+        let offset = self.len;
+        self.len += bytes.len() as u64;
+        Section {
+            offset,
+            len: items.len() as u64,
+        }
+    }
+}
+
+/// The arrays of one layer.
+struct LayerArrays {
+    records: Vec<NodeRecord>,
+    children: Vec<NodeAddress>,
+    singletons: Vec<u64>,
+}
+
+impl LayerArrays {
+    /// Sorts the nodes of the layer into its arrays. If `final_addresses` is passed, the final address of every point
+    /// on the layer is set in it.
+    fn new<D: PointCloud>(
+        layer: &CoverLayerReader<D>,
+        mut final_addresses: Option<&mut Vec<NodeAddress>>,
+    ) -> LayerArrays {
+        let mut set_final = |point_index: usize, address: NodeAddress| {
+            if let Some(final_addresses) = final_addresses.as_mut() {
+                if final_addresses.len() <= point_index {
+                    final_addresses.resize(point_index + 1, NodeAddress::SINGLETON);
+                }
+                final_addresses[point_index] = address;
+            }
+        };
+
+        let mut nodes: Vec<(usize, NodeRecord, Vec<NodeAddress>, Vec<usize>)> =
+            layer.map_nodes(|pi, n| {
+                let record = NodeRecord {
+                    center_index: *pi as u64,
+                    parent_address: n.parent_address().unwrap_or(NodeAddress::SINGLETON).raw(),
+                    coverage_count: n.coverage_count() as u64,
+                    children_start: 0,
+                    singletons_start: 0,
+                    radius: n.radius(),
+                    children_len: n.children_len() as u32,
+                    singletons_len: n.singletons_len() as u32,
+                    padding: 0,
+                };
+                let children = n.children().map(|c| c.to_vec()).unwrap_or_default();
+                (*pi, record, children, n.singletons().to_vec())
+            });
+        nodes.sort_unstable_by_key(|n| n.0);
+
+        let mut arrays = LayerArrays {
+            records: Vec::with_capacity(nodes.len()),
+            children: Vec::new(),
+            singletons: Vec::new(),
+        };
+        for (pi, mut record, node_children, node_singletons) in nodes {
+            let address = NodeAddress::from((layer.scale_index(), pi));
+            record.children_start = arrays.children.len() as u64;
+            record.singletons_start = arrays.singletons.len() as u64;
+            if node_children.is_empty() {
+                set_final(pi, address);
+            }
+            for singleton in &node_singletons {
+                set_final(*singleton, address);
+            }
+            arrays.children.extend(node_children);
+            arrays
+                .singletons
+                .extend(node_singletons.iter().map(|s| *s as u64));
+            arrays.records.push(record);
+        }
+        arrays
+    }
+}
+
+/// Writes the tree in the memory mapped layout. The layers are gone through twice, once to lay out the file and once
+/// to write it, so only one layer's arrays are held in memory at a time. See `utils::save_mapped_tree` for writing it
+/// to a file.
+pub fn encode_mapped_tree<D: PointCloud, W: Write>(
+    reader: &CoverTreeReader<D>,
+    out: &mut W,
+) -> GokoResult<()> {
+    if !platform_supported() {
+        return Err(unsupported_platform());
+    }
+    let mut layout = ArrayLayout::default();
+    let mut layers = Vec::with_capacity(reader.len());
+    let mut final_addresses: Vec<NodeAddress> = Vec::new();
+    for (_si, layer) in reader.layers().rev() {
+        let arrays = LayerArrays::new(layer, Some(&mut final_addresses));
+        layers.push(LayerTable {
+            scale_index: layer.scale_index(),
+            nodes: layout.push(&arrays.records),
+            children: layout.push(&arrays.children),
+            singletons: layout.push(&arrays.singletons),
+        });
+    }
+    let final_addresses_section = layout.push(&final_addresses);
+
+    let mut header = TreeFileHeader::new::<D>(reader.parameters(), &[], &[])?;
+    header.checksum = layout.hasher.finish();
+    let toc = TableOfContents {
+        header,
+        root_address: reader.root_address(),
+        layers,
+        final_addresses: final_addresses_section,
+    };
+    let mut toc = serde_json::to_vec(&toc)?;
+    while toc.len() % 8 != 0 {
+        toc.push(b' ');
+    }
+
+    out.write_all(MAPPED_TREE_MAGIC)?;
+    out.write_all(&MAPPED_TREE_VERSION.to_le_bytes())?;
+    out.write_all(&[0; 4])?;
+    out.write_all(&(toc.len() as u64).to_le_bytes())?;
+    out.write_all(&toc)?;
+    for (_si, layer) in reader.layers().rev() {
+        let arrays = LayerArrays::new(layer, None);
+        out.write_all(as_bytes(&arrays.records))?;
+        out.write_all(as_bytes(&arrays.children))?;
+        out.write_all(as_bytes(&arrays.singletons))?;
+    }
+    out.write_all(as_bytes(&final_addresses))?;
+    Ok(())
+}
+
+/// The map of a tree file, shared by the layers of the tree.
+pub(crate) struct MappedTreeFile {
+    map: Mmapf32,
+    data_start: usize,
+}
+
+impl MappedTreeFile {
+    /// Maps the file and reads the table of contents.
+    fn open(path: &Path) -> GokoResult<(MappedTreeFile, TableOfContents)> {
+        let file = File::open(path)?;
+        // Empty files can't be mapped
+        if file.metadata()?.len() < PREAMBLE_LEN as u64 {
+            return Err(GokoError::CorruptTreeFile(
+                "this isn't a memory mapped tree".to_string(),
+            ));
+        }
+        let map = unsafe { Mmapf32::map(&file)? };
+        let raw = map.as_bytes();
+        if raw.len() < PREAMBLE_LEN || &raw[..8] != MAPPED_TREE_MAGIC {
+            return Err(GokoError::CorruptTreeFile(
+                "this isn't a memory mapped tree".to_string(),
+            ));
+        }
+        let version = u32::from_le_bytes(raw[8..12].try_into().unwrap());
+        if version > MAPPED_TREE_VERSION {
+            return Err(GokoError::UnsupportedTreeFileVersion(version));
+        }
+        let toc_len = u64::from_le_bytes(raw[16..24].try_into().unwrap()) as usize;
+        if raw.len() - PREAMBLE_LEN < toc_len || toc_len % 8 != 0 {
+            return Err(GokoError::CorruptTreeFile(
+                "the table of contents is truncated".to_string(),
+            ));
+        }
+        let data_start = PREAMBLE_LEN + toc_len;
+        let toc: TableOfContents = serde_json::from_slice(&raw[PREAMBLE_LEN..data_start])
+            .map_err(|e| GokoError::CorruptTreeFile(e.to_string()))?;
+        Ok((MappedTreeFile { map, data_start }, toc))
+    }
+
+    /// The arrays, everything after the table of contents.
+    fn data(&self) -> &[u8] {
+        &self.map.as_bytes()[self.data_start..]
+    }
+
+    /// The sections were bounds and alignment checked when the file was opened.
+    fn array<T: Copy>(&self, section: Section) -> &[T] {
+        let start = section.offset as usize;
+        let bytes = &self.data()[start..start + section.len as usize * size_of::<T>()];
+        // Every bit pattern is valid for the record fields, addresses and indexes
+        unsafe { std::slice::from_raw_parts(bytes.as_ptr() as *const T, section.len as usize) }
+    }
+}
+
+/// A layer of a mapped tree file, the storage behind the layers of a tree opened with [`open_mapped_tree`].
+#[derive(Clone)]
+pub(crate) struct MappedLayer {
+    file: Arc<MappedTreeFile>,
+    table: LayerTable,
+}
+
+impl MappedLayer {
+    /// The scale index of the layer
+    pub(crate) fn scale_index(&self) -> i32 {
+        self.table.scale_index
+    }
+
+    /// Total number of nodes on this layer
+    pub(crate) fn len(&self) -> usize {
+        self.table.nodes.len as usize
+    }
+
+    fn records(&self) -> &[NodeRecord] {
+        self.file.array(self.table.nodes)
+    }
+
+    /// The centers of the nodes on this layer, in order.
+    pub(crate) fn center_indexes(&self) -> impl Iterator<Item = usize> + '_ {
+        self.records().iter().map(|r| r.center_index as usize)
+    }
+
+    fn record(&self, pi: usize) -> Option<&NodeRecord> {
+        let records = self.records();
+        records
+            .binary_search_by_key(&(pi as u64), |r| r.center_index)
+            .ok()
+            .map(|i| &records[i])
+    }
+
+    /// Read only access to the node with this center, found with a binary search of the records. The node's children
+    /// and singletons are read in place. The record's child and singleton ranges aren't checked when the file is
+    /// opened, a node whose ranges are out of bounds is treated as missing.
+    pub(crate) fn get_node_and<D, F, T>(&self, pi: usize, f: F) -> Option<T>
+    where
+        D: PointCloud,
+        F: FnOnce(&CoverNode<D>) -> T,
+    {
+        let record = self.record(pi)?;
+        let children_start = record.children_start as usize;
+        let children: &[NodeAddress] = self.file.array(self.table.children);
+        let children = children
+            .get(children_start..children_start.checked_add(record.children_len as usize)?)?;
+        let singletons_start = record.singletons_start as usize;
+        let singletons: &[usize] = self.file.array(self.table.singletons);
+        let singletons = singletons
+            .get(singletons_start..singletons_start.checked_add(record.singletons_len as usize)?)?;
+        let parent_address = NodeAddress::from(record.parent_address);
+        // The node is dropped before the borrow of the map ends
+        let node = unsafe {
+            CoverNode::from_mapped(
+                (self.table.scale_index, pi).into(),
+                Some(parent_address).filter(|p| *p != NodeAddress::SINGLETON),
+                record.radius,
+                record.coverage_count as usize,
+                Some(children).filter(|c| !c.is_empty()),
+                singletons,
+            )
+        };
+        Some(f(&node))
+    }
+}
+
+/// The final addresses of the points of a mapped tree, read straight out of the map.
+#[derive(Clone)]
+pub(crate) struct MappedAddresses {
+    file: Arc<MappedTreeFile>,
+    section: Section,
+}
+
+impl MappedAddresses {
+    fn addresses(&self) -> &[NodeAddress] {
+        self.file.array(self.section)
+    }
+
+    /// The address of the node the point is a singleton of or the center of a leaf of.
+    pub(crate) fn get(&self, point_index: usize) -> Option<NodeAddress> {
+        self.addresses()
+            .get(point_index)
+            .copied()
+            .filter(|a| *a != NodeAddress::SINGLETON)
+    }
+
+    /// The indexes of the points in the tree.
+    pub(crate) fn point_indexes(&self) -> impl Iterator<Item = usize> + '_ {
+        self.addresses()
+            .iter()
+            .enumerate()
+            .filter(|(_, a)| **a != NodeAddress::SINGLETON)
+            .map(|(pi, _)| pi)
+    }
+}
+
+/// Maps the file and reads its table of contents. The tree is checked against the point cloud's fingerprint and
+/// metric, but the nodes and the final addresses of the points aren't read until they're queried. See
+/// `utils::load_mapped_tree`.
+pub fn open_mapped_tree<P: AsRef<Path>, D: PointCloud>(
+    path: P,
+    point_cloud: Arc<D>,
+) -> GokoResult<CoverTreeWriter<D>> {
+    if !platform_supported() {
+        return Err(unsupported_platform());
+    }
+    let (file, toc) = MappedTreeFile::open(path.as_ref())?;
+    toc.header.check(point_cloud.as_ref())?;
+
+    let data_len = file.data().len();
+    let in_bounds = |section: &Section, size: usize| {
+        section.offset % 8 == 0
+            && section
+                .len
+                .checked_mul(size as u64)
+                .and_then(|l| l.checked_add(section.offset))
+                .map(|end| end <= data_len as u64)
+                .unwrap_or(false)
+    };
+    let build = &toc.header.parameters;
+    for (i, layer) in toc.layers.iter().enumerate() {
+        if layer.scale_index != build.min_res_index - 1 + i as i32
+            || !in_bounds(&layer.nodes, size_of::<NodeRecord>())
+            || !in_bounds(&layer.children, size_of::<NodeAddress>())
+            || !in_bounds(&layer.singletons, size_of::<usize>())
+        {
+            return Err(GokoError::CorruptTreeFile(format!(
+                "the arrays of layer {} are out of bounds",
+                i
+            )));
+        }
+    }
+    if !in_bounds(&toc.final_addresses, size_of::<NodeAddress>()) {
+        return Err(GokoError::CorruptTreeFile(
+            "the final addresses are out of bounds".to_string(),
+        ));
+    }
+    let root = toc.root_address;
+    let root_layer = (root.scale_index() as i64 - build.min_res_index as i64 + 1)
+        .try_into()
+        .ok()
+        .and_then(|i: usize| toc.layers.get(i));
+    let root_record = root_layer.and_then(|table| {
+        let records: &[NodeRecord] = file.array(table.nodes);
+        records
+            .binary_search_by_key(&(root.point_index() as u64), |r| r.center_index)
+            .ok()
+    });
+    if root_record.is_none() {
+        return Err(GokoError::CorruptTreeFile(format!(
+            "the root {:?} isn't in the tree",
+            root
+        )));
+    }
+
+    let node_count = toc.layers.iter().map(|l| l.nodes.len as usize).sum();
+    let parameters = Arc::new(CoverTreeParameters {
+        total_nodes: atomic::AtomicUsize::new(node_count),
+        scale_base: build.scale_base,
+        leaf_cutoff: build.leaf_cutoff,
+        min_res_index: build.min_res_index,
+        use_singletons: build.use_singletons,
+        partition_type: build.partition_type,
+        verbosity: 2,
+        rng_seed: build.rng_seed,
+        point_cloud,
+        plugins: RwLock::new(TreePluginSet::new()),
+    });
+
+    let (_final_addresses_reader, final_addresses) = monomap::new();
+    let file = Arc::new(file);
+    let mapped_addresses = MappedAddresses {
+        file: Arc::clone(&file),
+        section: toc.final_addresses,
+    };
+    let layers = toc
+        .layers
+        .iter()
+        .map(|table| {
+            CoverLayerWriter::mapped(MappedLayer {
+                file: Arc::clone(&file),
+                table: *table,
+            })
+        })
+        .collect();
+    Ok(CoverTreeWriter {
+        parameters,
+        layers,
+        root_address: toc.root_address,
+        final_addresses,
+        mapped_addresses: Some(mapped_addresses),
+        loading: None,
+        saved_plugins: HashMap::new(),
+        plugin_encoders: Vec::new(),
+    })
+}
+
+/// Reads the whole file and checks it against the checksum in the header.
+pub fn verify_mapped_tree<P: AsRef<Path>>(path: P) -> GokoResult<()> {
+    let (file, toc) = MappedTreeFile::open(path.as_ref())?;
+    if checksum(std::iter::once(file.data())) != toc.header.checksum {
+        return Err(GokoError::CorruptTreeFile(
+            "the checksum doesn't match".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::random_data;
+    use crate::covertree::CoverTreeBuilder;
+    use crate::plugins::gaussians::{DiagGaussian, GokoDiagGaussian};
+    use crate::utils::{load_mapped_tree, save_mapped_tree, save_tree};
+
+    #[test]
+    fn mapped_tree_matches_reader() {
+        let data = random_data(2000, 3, 0);
+        let labels: Vec<i64> = (0..2000).map(|i| i % 3).collect();
+        let point_cloud = Arc::new(DefaultLabeledCloud::<L2>::new_simple(data, 3, labels));
+        let mut builder = CoverTreeBuilder::new();
+        builder
+            .set_scale_base(1.5)
+            .set_leaf_cutoff(5)
+            .set_min_res_index(-6)
+            .set_rng_seed(0);
+        let tree = builder.build(Arc::clone(&point_cloud)).unwrap();
+        let reader = tree.reader();
+
+        let dir = tempdir::TempDir::new("mapped_tree").unwrap();
+        let path = dir.path().join("tree.flat");
+        save_mapped_tree(&path, &tree).unwrap();
+        verify_mapped_tree(&path).unwrap();
+        let mut mapped_tree = load_mapped_tree(&path, Arc::clone(&point_cloud)).unwrap();
+        let mapped = mapped_tree.reader();
+
+        assert_eq!(mapped.node_count(), reader.node_count());
+        assert_eq!(mapped.len(), reader.len());
+        for ((si, layer), (mapped_si, mapped_layer)) in reader.layers().zip(mapped.layers()) {
+            assert_eq!(si, mapped_si);
+            assert_eq!(layer.len(), mapped_layer.len());
+            layer.for_each_node(|pi, n| {
+                mapped_layer
+                    .get_node_and(*pi, |m| {
+                        assert_eq!(m.address(), n.address());
+                        assert_eq!(m.parent_address(), n.parent_address());
+                        assert_eq!(m.radius(), n.radius());
+                        assert_eq!(m.coverage_count(), n.coverage_count());
+                        assert_eq!(m.children(), n.children());
+                        assert_eq!(m.singletons(), n.singletons());
+                    })
+                    .unwrap();
+            });
+        }
+
+        for point in random_data(20, 3, 1).chunks(3).map(|p| p.to_vec()) {
+            let point = &point[..];
+            assert_eq!(
+                mapped.knn(&point, 5).unwrap(),
+                reader.knn(&point, 5).unwrap()
+            );
+            assert_eq!(
+                mapped.routing_knn(&point, 5).unwrap(),
+                reader.routing_knn(&point, 5).unwrap()
+            );
+            assert_eq!(mapped.path(&point).unwrap(), reader.path(&point).unwrap());
+        }
+        for i in (0..2000).step_by(97) {
+            assert_eq!(mapped.known_path(i).unwrap(), reader.known_path(i).unwrap());
+        }
+
+        // Plugins are attached to copies of the mapped nodes
+        mapped_tree.generate_summaries();
+        mapped_tree.add_plugin::<GokoDiagGaussian>(GokoDiagGaussian::recursive());
+        let mapped = mapped_tree.reader();
+        let root = mapped.root_address();
+        let summary = mapped.get_node_label_summary(root).unwrap();
+        assert_eq!(summary.summary.items.len(), 3);
+        assert_eq!(
+            mapped.get_node_plugin_and::<DiagGaussian, _, _>(root, |p| p.count()),
+            Some(2000)
+        );
+        assert_eq!(mapped.node_count(), reader.node_count());
+        assert_eq!(
+            mapped.knn(&&[0.5f32, 0.5, 0.5][..], 5).unwrap(),
+            reader.knn(&&[0.5f32, 0.5, 0.5][..], 5).unwrap()
+        );
+    }
+
+    #[test]
+    fn mapped_tree_errors() {
+        let tree = crate::covertree::tests::build_basic_tree();
+        let point_cloud = Arc::clone(&tree.parameters.point_cloud);
+        let dir = tempdir::TempDir::new("mapped_tree").unwrap();
+
+        // A protobuf tree file isn't a mapped tree
+        let path = dir.path().join("basic.tree");
+        save_tree(&path, &tree).unwrap();
+        assert!(matches!(
+            load_mapped_tree(&path, Arc::clone(&point_cloud)),
+            Err(GokoError::CorruptTreeFile(_))
+        ));
+
+        let path = dir.path().join("basic.flat");
+        save_mapped_tree(&path, &tree).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        let mut flipped = bytes.clone();
+        *flipped.last_mut().unwrap() ^= 1;
+        std::fs::write(&path, &flipped).unwrap();
+        assert!(matches!(
+            verify_mapped_tree(&path),
+            Err(GokoError::CorruptTreeFile(_))
+        ));
+        let mapped = load_mapped_tree(&path, Arc::clone(&point_cloud)).unwrap();
+        assert!(matches!(
+            mapped.reader().known_path(100),
+            Err(GokoError::IndexNotInTree(100))
+        ));
+
+        // Make the root its own parent, walking up from a leaf has to stop instead of going around the loop
+        let (file, toc) = MappedTreeFile::open(&path).unwrap();
+        let root = toc.root_address;
+        let table = toc
+            .layers
+            .iter()
+            .find(|l| l.scale_index == root.scale_index())
+            .unwrap();
+        let records: &[NodeRecord] = file.array(table.nodes);
+        let i = records
+            .iter()
+            .position(|r| r.center_index == root.point_index() as u64)
+            .unwrap();
+        let parent_offset = file.data_start
+            + table.nodes.offset as usize
+            + i * size_of::<NodeRecord>()
+            + size_of::<u64>();
+        drop(file);
+        let mut looped = bytes;
+        looped[parent_offset..parent_offset + 8].copy_from_slice(&root.raw().to_le_bytes());
+        std::fs::write(&path, &looped).unwrap();
+        let mapped = load_mapped_tree(&path, Arc::clone(&point_cloud)).unwrap();
+        assert!(matches!(
+            mapped.reader().known_path(0),
+            Err(GokoError::CorruptTreeFile(_))
+        ));
+
+        // A root that's above the top layer, or isn't a node of its layer, is rejected when the tree is opened
+        let data = looped
+            [u64::from_le_bytes(looped[16..24].try_into().unwrap()) as usize + PREAMBLE_LEN..]
+            .to_vec();
+        let bad_roots = [
+            (root.scale_index() + 1, root.point_index()),
+            (root.scale_index(), root.point_index() + 1),
+        ];
+        for bad_root in bad_roots.iter() {
+            let mut bad_toc = toc.clone();
+            bad_toc.root_address = NodeAddress::from(*bad_root);
+            let mut json = serde_json::to_vec(&bad_toc).unwrap();
+            while json.len() % 8 != 0 {
+                json.push(b' ');
+            }
+            let mut rewritten = looped[..16].to_vec();
+            rewritten.extend_from_slice(&(json.len() as u64).to_le_bytes());
+            rewritten.extend(json);
+            rewritten.extend_from_slice(&data);
+            std::fs::write(&path, &rewritten).unwrap();
+            assert!(matches!(
+                load_mapped_tree(&path, Arc::clone(&point_cloud)),
+                Err(GokoError::CorruptTreeFile(_))
+            ));
+        }
+    }
+}
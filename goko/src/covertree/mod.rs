@@ -1,10 +1,15 @@
 pub(crate) mod builders;
 pub(crate) mod data_caches;
+pub mod export;
 pub mod layer;
+pub mod lazy;
+pub mod mapped;
 pub mod node;
 pub mod query_tools;
+pub mod stats;
+pub mod validation;
 
 mod tree;
 
-pub use builders::CoverTreeBuilder;
+pub use builders::{BuildProgress, CancellationToken, CoverTreeBuilder, ProgressObserver};
 pub use tree::*;
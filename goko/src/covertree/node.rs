@@ -32,10 +32,80 @@ use smallvec::smallvec;
 use std::ops::Deref;
 
 use pointcloud::*;
-use smallvec::SmallVec;
+use smallvec::{Array, SmallVec};
+use std::fmt;
 use std::marker::PhantomData;
 use std::sync::Arc;
 
+/// The children or the singletons of a node. The nodes of a memory mapped tree, see `covertree::mapped`, point into
+/// the map instead of copying their lists out of it. Those nodes are only lent out for the duration of a closure, and
+/// their lists are copied into memory if they're cloned or changed.
+enum NodeList<A: Array> {
+    Owned(SmallVec<A>),
+    Mapped(*const A::Item, usize),
+}
+
+// The mapped lists are immutable and the map outlives the nodes that point into it
+unsafe impl<A: Array> Send for NodeList<A> where A::Item: Send {}
+unsafe impl<A: Array> Sync for NodeList<A> where A::Item: Sync {}
+
+impl<A: Array> NodeList<A>
+where
+    A::Item: Copy,
+{
+    /// The list, copied into memory if it's mapped.
+    fn to_mut(&mut self) -> &mut SmallVec<A> {
+        if let NodeList::Mapped(..) = self {
+            *self = NodeList::Owned(SmallVec::from_slice(self));
+        }
+        match self {
+            NodeList::Owned(list) => list,
+            NodeList::Mapped(..) => unreachable!(),
+        }
+    }
+
+    fn into_owned(self) -> SmallVec<A> {
+        match self {
+            NodeList::Owned(list) => list,
+            NodeList::Mapped(..) => SmallVec::from_slice(&self),
+        }
+    }
+}
+
+impl<A: Array> Deref for NodeList<A> {
+    type Target = [A::Item];
+    fn deref(&self) -> &[A::Item] {
+        match self {
+            NodeList::Owned(list) => list,
+            NodeList::Mapped(ptr, len) => unsafe { std::slice::from_raw_parts(*ptr, *len) },
+        }
+    }
+}
+
+impl<A: Array> Clone for NodeList<A>
+where
+    A::Item: Copy,
+{
+    fn clone(&self) -> Self {
+        NodeList::Owned(SmallVec::from_slice(self))
+    }
+}
+
+impl<A: Array> fmt::Debug for NodeList<A>
+where
+    A::Item: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<A: Array> From<SmallVec<A>> for NodeList<A> {
+    fn from(list: SmallVec<A>) -> Self {
+        NodeList::Owned(list)
+    }
+}
+
 /// The actual cover node. The fields can be separated into three piles. The first two consist of node `address` for testing and reference
 /// when working and the `radius`, `coverage_count`, and `singles_summary` for a query various properties of the node.
 /// Finally we have the children and singleton pile. The singletons are saved in a `SmallVec` directly attached to the node. This saves a
@@ -51,8 +121,8 @@ pub struct CoverNode<D: PointCloud> {
     radius: f32,
     coverage_count: usize,
     /// Children
-    children: Option<SmallVec<[NodeAddress; 2]>>,
-    singles_indexes: SmallVec<[usize; 5]>,
+    children: Option<NodeList<[NodeAddress; 2]>>,
+    singles_indexes: NodeList<[usize; 5]>,
     plugins: NodePluginSet,
     metic: PhantomData<D>,
 }
@@ -81,7 +151,7 @@ impl<D: PointCloud> CoverNode<D> {
             radius: 0.0,
             coverage_count: 1,
             children: None,
-            singles_indexes: SmallVec::new(),
+            singles_indexes: SmallVec::new().into(),
             plugins: NodePluginSet::new(),
             metic: PhantomData,
         }
@@ -127,7 +197,7 @@ impl<D: PointCloud> CoverNode<D> {
 
     /// Removes all children and returns them to us.
     pub(crate) fn remove_children(&mut self) -> Option<SmallVec<[NodeAddress; 2]>> {
-        self.children.take()
+        self.children.take().map(NodeList::into_owned)
     }
 
     /// The number of singleton points attached to the node
@@ -305,10 +375,9 @@ impl<D: PointCloud> CoverNode<D> {
         if self.children.is_some() {
             Err(GokoError::DoubleNest)
         } else {
-            self.children = Some(smallvec![NodeAddress::from((
-                scale_index,
-                self.address.point_index()
-            ))]);
+            self.children = Some(
+                smallvec![NodeAddress::from((scale_index, self.address.point_index()))].into(),
+            );
             Ok(())
         }
     }
@@ -317,7 +386,7 @@ impl<D: PointCloud> CoverNode<D> {
     pub(crate) fn insert_child(&mut self, address: NodeAddress, coverage: usize) -> GokoResult<()> {
         self.coverage_count += coverage;
         if let Some(children) = &mut self.children {
-            children.push(address);
+            children.to_mut().push(address);
             Ok(())
         } else {
             Err(GokoError::InsertBeforeNest)
@@ -327,12 +396,12 @@ impl<D: PointCloud> CoverNode<D> {
     /// Inserts a `vec` of singleton children into the node.
     pub(crate) fn insert_singletons(&mut self, addresses: Vec<usize>) {
         self.coverage_count += addresses.len();
-        self.singles_indexes.extend(addresses);
+        self.singles_indexes.to_mut().extend(addresses);
     }
     /// Inserts a single singleton child into the node.
     pub(crate) fn insert_singleton(&mut self, pi: usize) {
         self.coverage_count += 1;
-        self.singles_indexes.push(pi);
+        self.singles_indexes.to_mut().push(pi);
     }
 
     /// Inserts a single singleton child into the node.
@@ -345,12 +414,39 @@ impl<D: PointCloud> CoverNode<D> {
         self.radius = radius;
     }
 
+    /// A node read out of a memory mapped tree, see `covertree::mapped`. It has no plugins, and its children and
+    /// singletons point into the map.
+    ///
+    /// # Safety
+    /// The node mustn't outlive the slices, clone it to keep it.
+    pub(crate) unsafe fn from_mapped(
+        address: NodeAddress,
+        parent_address: Option<NodeAddress>,
+        radius: f32,
+        coverage_count: usize,
+        children: Option<&[NodeAddress]>,
+        singletons: &[usize],
+    ) -> CoverNode<D> {
+        CoverNode {
+            parent_address,
+            address,
+            radius,
+            coverage_count,
+            children: children.map(|c| NodeList::Mapped(c.as_ptr(), c.len())),
+            singles_indexes: NodeList::Mapped(singletons.as_ptr(), singletons.len()),
+            plugins: NodePluginSet::new(),
+            metic: PhantomData,
+        }
+    }
+
     pub(crate) fn load(node_proto: &NodeProto) -> CoverNode<D> {
-        let singles_indexes = node_proto
-            .outlier_point_indexes
-            .iter()
-            .map(|i| *i as usize)
-            .collect();
+        let singles_indexes = NodeList::Owned(
+            node_proto
+                .outlier_point_indexes
+                .iter()
+                .map(|i| *i as usize)
+                .collect(),
+        );
         let radius = node_proto.get_radius();
         let address = NodeAddress::from((
             node_proto.get_scale_index(),
@@ -371,14 +467,14 @@ impl<D: PointCloud> CoverNode<D> {
         let children = if node_proto.get_is_leaf() {
             None
         } else {
-            Some(
+            Some(NodeList::Owned(
                 node_proto
                     .get_children_scale_indexes()
                     .iter()
                     .zip(node_proto.get_children_point_indexes())
                     .map(|(si, pi)| (*si as i32, *pi as usize).into())
                     .collect(),
-            )
+            ))
         };
         CoverNode {
             parent_address,
@@ -453,12 +549,15 @@ mod tests {
     use crate::query_tools::KnnQueryHeap;
 
     fn create_test_node<D: PointCloud>() -> CoverNode<D> {
-        let children = Some(smallvec![
-            (-4, 0).into(),
-            (-4, 1).into(),
-            (-4, 2).into(),
-            (-4, 3).into()
-        ]);
+        let children = Some(
+            smallvec![
+                (-4, 0).into(),
+                (-4, 1).into(),
+                (-4, 2).into(),
+                (-4, 3).into()
+            ]
+            .into(),
+        );
 
         CoverNode {
             parent_address: None,
@@ -466,7 +565,7 @@ mod tests {
             radius: 1.0,
             coverage_count: 8,
             children,
-            singles_indexes: smallvec![4, 5, 6],
+            singles_indexes: smallvec![4, 5, 6].into(),
             plugins: NodePluginSet::new(),
             metic: PhantomData,
         }
@@ -479,7 +578,7 @@ mod tests {
             radius: 1.0,
             coverage_count: 8,
             children: None,
-            singles_indexes: smallvec![1, 2, 3, 4, 5, 6],
+            singles_indexes: smallvec![1, 2, 3, 4, 5, 6].into(),
             plugins: NodePluginSet::new(),
             metic: PhantomData,
         }
@@ -0,0 +1,141 @@
+//! Records of how a query went, for debugging queries that return something unexpected. See
+//! `CoverTreeReader::knn_explain` and `CoverTreeReader::path_explain`.
+
+use super::QueryStats;
+use crate::NodeAddress;
+use serde::{Deserialize, Serialize};
+
+/// What was checked when a node was visited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VisitKind {
+    /// The distances to the node's children were computed
+    Children,
+    /// The distances to the node's singletons were computed
+    Singletons,
+}
+
+/// A node the query visited, in the order they were visited.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct NodeVisit {
+    /// The node
+    pub address: NodeAddress,
+    /// The distance from the query point to the node's center
+    pub dist_to_center: f32,
+    /// What was checked
+    pub kind: VisitKind,
+}
+
+/// A child node whose distance was computed, but that the query didn't go into.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SkippedNode {
+    /// The node
+    pub address: NodeAddress,
+    /// The distance from the query point to the node's center
+    pub dist_to_center: f32,
+    /// The smallest distance a point covered by the node could have to the query point, this is the distance to the
+    /// center less the node's covering radius.
+    pub min_dist: f32,
+    /// The distance the query had to beat to go into the node. For a knn query the node is pruned because `min_dist`
+    /// isn't less than this, it's the distance to the kth nearest neighbor found when the node was skipped. For a path
+    /// the node is skipped because `dist_to_center` isn't less than this, it's the node's covering radius, or with
+    /// `PartitionType::Nearest` the distance to the nearest child when that's smaller.
+    pub bound: f32,
+}
+
+/// A node that was still waiting to be visited.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct UnvisitedNode {
+    /// The node
+    pub address: NodeAddress,
+    /// The distance from the query point to the node's center
+    pub dist_to_center: f32,
+    /// The smallest distance a point covered by the node could have to the query point
+    pub min_dist: f32,
+}
+
+/// A snapshot of a `KnnQueryHeap`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HeapState {
+    /// The nearest neighbors found, nearest first
+    pub knn: Vec<(usize, f32)>,
+    /// The distance to the kth nearest neighbor, `f32::MAX` if there aren't k yet
+    pub max_dist: f32,
+    /// The nodes whose children haven't been checked, nearest first
+    pub unvisited_children: Vec<UnvisitedNode>,
+    /// The nodes whose singletons haven't been checked, nearest first
+    pub unvisited_singletons: Vec<UnvisitedNode>,
+}
+
+/// How a knn query went.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KnnExplanation {
+    /// The result of the query, the same as `knn` would return
+    pub knn: Vec<(usize, f32)>,
+    /// The work the query did
+    pub stats: QueryStats,
+    /// Every node that was visited, in order
+    pub visited: Vec<NodeVisit>,
+    /// Every child that was pruned, in order
+    pub skipped: Vec<SkippedNode>,
+    /// The query heap when the query stopped
+    pub final_heap: HeapState,
+}
+
+/// How a path query went.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PathExplanation {
+    /// The result of the query, the same as `path` would return
+    pub path: Vec<(NodeAddress, f32)>,
+    /// The work the query did
+    pub stats: QueryStats,
+    /// Every node that was visited, in order. These are the nodes on the path that have children.
+    pub visited: Vec<NodeVisit>,
+    /// The children of the visited nodes that were considered but not picked, in order
+    pub skipped: Vec<SkippedNode>,
+}
+
+/// Collects the stats of a query, and the nodes it visits if it's being explained.
+#[derive(Debug, Default)]
+pub(crate) struct QueryRecorder {
+    pub(crate) stats: QueryStats,
+    pub(crate) visited: Option<Vec<NodeVisit>>,
+}
+
+impl QueryRecorder {
+    pub(crate) fn explaining() -> QueryRecorder {
+        QueryRecorder {
+            stats: QueryStats::default(),
+            visited: Some(Vec::new()),
+        }
+    }
+
+    pub(crate) fn check_children(
+        &mut self,
+        address: NodeAddress,
+        dist_to_center: f32,
+        children_len: usize,
+    ) {
+        self.stats.check_children(children_len);
+        self.visit(address, dist_to_center, VisitKind::Children);
+    }
+
+    pub(crate) fn check_singletons(
+        &mut self,
+        address: NodeAddress,
+        dist_to_center: f32,
+        singletons_len: usize,
+    ) {
+        self.stats.check_singletons(singletons_len);
+        self.visit(address, dist_to_center, VisitKind::Singletons);
+    }
+
+    pub(crate) fn visit(&mut self, address: NodeAddress, dist_to_center: f32, kind: VisitKind) {
+        if let Some(visited) = &mut self.visited {
+            visited.push(NodeVisit {
+                address,
+                dist_to_center,
+                kind,
+            });
+        }
+    }
+}
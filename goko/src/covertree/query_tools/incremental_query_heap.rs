@@ -0,0 +1,124 @@
+//! The frontier of an incremental nearest neighbor search.
+
+use crate::NodeAddress;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashSet};
+
+use super::*;
+
+use super::query_items::{QueryAddress, QuerySingleton};
+
+/// The heaps for a nearest neighbor search that doesn't know `k`, see `CoverTreeReader::knn_iter`. Unlike the
+/// `KnnQueryHeap` nothing is pruned. There's a min-heap of nodes, ordered by the minimum distance a point covered by
+/// the node could have to the query point, and a min-heap of points ordered by their distance. A point is the next
+/// nearest neighbor once it's nearer than every node left on the node heap.
+///
+/// As with the `KnnQueryHeap`, a node's central point is treated as a point when the node is pushed, and a point is
+/// only pushed once.
+#[derive(Debug)]
+pub struct IncrementalQueryHeap {
+    node_heap: BinaryHeap<QueryAddress>,
+    point_heap: BinaryHeap<Reverse<QuerySingleton>>,
+    known_indexes: HashSet<usize>,
+    scale_base: f32,
+}
+
+impl RoutingQueryHeap for IncrementalQueryHeap {
+    /// Shove a bunch of nodes onto the heap, and their centers onto the point heap. The parent isn't used.
+    fn push_nodes(
+        &mut self,
+        indexes: &[NodeAddress],
+        dists: &[f32],
+        _parent_address: Option<NodeAddress>,
+    ) {
+        for (na, d) in indexes.iter().zip(dists) {
+            self.node_heap.push(QueryAddress {
+                address: *na,
+                dist_to_center: *d,
+                min_dist: (d - self.scale_base.powi(na.scale_index())).max(0.0),
+            });
+            if self.known_indexes.insert(na.point_index()) {
+                self.point_heap
+                    .push(Reverse(QuerySingleton::new(na.point_index(), *d)));
+            }
+        }
+    }
+}
+
+impl SingletonQueryHeap for IncrementalQueryHeap {
+    /// Shove a bunch of single points onto the heap
+    fn push_outliers(&mut self, indexes: &[usize], dists: &[f32]) {
+        for (i, d) in indexes.iter().zip(dists) {
+            if self.known_indexes.insert(*i) {
+                self.point_heap.push(Reverse(QuerySingleton::new(*i, *d)));
+            }
+        }
+    }
+}
+
+impl IncrementalQueryHeap {
+    /// Creates a new heap. The `scale_base` is for the minimum distance from our query point to potential covered
+    /// points of a node.
+    pub fn new(scale_base: f32) -> IncrementalQueryHeap {
+        IncrementalQueryHeap {
+            node_heap: BinaryHeap::new(),
+            point_heap: BinaryHeap::new(),
+            known_indexes: HashSet::new(),
+            scale_base,
+        }
+    }
+
+    /// Pops the nearest point on the heap, if there's no node that could cover a nearer one.
+    pub fn pop_point(&mut self) -> Option<(usize, f32)> {
+        let nearest = self.point_heap.peek()?.0;
+        match self.node_heap.peek() {
+            Some(node) if node.min_dist < nearest.dist => None,
+            _ => {
+                self.point_heap.pop();
+                Some((nearest.index, nearest.dist))
+            }
+        }
+    }
+
+    /// Pops the node that could cover the nearest point, with the distance to its center.
+    pub fn pop_node(&mut self) -> Option<(NodeAddress, f32)> {
+        self.node_heap
+            .pop()
+            .map(|node| (node.address, node.dist_to_center))
+    }
+
+    /// The number of points on the heap that haven't been popped
+    pub fn len(&self) -> usize {
+        self.point_heap.len()
+    }
+
+    /// If there are no points on the heap
+    pub fn is_empty(&self) -> bool {
+        self.point_heap.is_empty()
+    }
+
+    /// The number of nodes on the heap that haven't been popped
+    pub fn node_len(&self) -> usize {
+        self.node_heap.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn points_wait_for_nearer_nodes() {
+        let mut heap = IncrementalQueryHeap::new(2.0);
+        heap.push_outliers(&[2, 4], &[0.2, 3.0]);
+        // This covers everything within 1.0 of its center, which is 2.5 away
+        heap.push_nodes(&[(0, 1).into()], &[2.5], None);
+
+        assert_eq!(heap.pop_point(), Some((2, 0.2)));
+        assert_eq!(heap.pop_point(), None);
+        assert_eq!(heap.pop_node(), Some(((0, 1).into(), 2.5)));
+        assert_eq!(heap.pop_point(), Some((1, 2.5)));
+        assert_eq!(heap.pop_point(), Some((4, 3.0)));
+        assert!(heap.is_empty());
+    }
+}
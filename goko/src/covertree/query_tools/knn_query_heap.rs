@@ -25,6 +25,7 @@ use std::f32;
 
 use super::*;
 
+use super::explain::{HeapState, SkippedNode, UnvisitedNode};
 use super::query_items::{QueryAddress, QuerySingleton};
 
 /// The heaps for doing a fairly efficient KNN query. There are 3 heaps, the child min-heap, singleton min-heap, and distance max-heap.
@@ -49,6 +50,9 @@ pub struct KnnQueryHeap {
     dist_heap: BinaryHeap<QuerySingleton>,
     k: usize,
     scale_base: f32,
+    skipped: Option<Vec<SkippedNode>>,
+    /// The nodes pushed onto the child heap and the singletons pushed onto the distance heap
+    pushes: usize,
 }
 
 impl RoutingQueryHeap for KnnQueryHeap {
@@ -66,11 +70,19 @@ impl RoutingQueryHeap for KnnQueryHeap {
             let emd = (d - self.scale_base.powi(na.scale_index())).max(0.0);
             parent_est_dist_update = emd.max(parent_est_dist_update);
             if emd < max_dist {
+                self.pushes += 1;
                 self.child_heap.push(QueryAddress {
                     address: *na,
                     dist_to_center: *d,
                     min_dist: emd,
                 });
+            } else if let Some(skipped) = &mut self.skipped {
+                skipped.push(SkippedNode {
+                    address: *na,
+                    dist_to_center: *d,
+                    min_dist: emd,
+                    bound: max_dist,
+                });
             }
             if !self.known_indexes.contains(&pi) {
                 self.known_indexes.insert(pi);
@@ -104,10 +116,14 @@ impl SingletonQueryHeap for KnnQueryHeap {
                 match self.dist_heap.peek() {
                     Some(my_dist) => {
                         if !(my_dist.dist < *d && self.dist_heap.len() >= self.k) {
+                            self.pushes += 1;
                             self.dist_heap.push(QuerySingleton::new(*i, *d));
                         }
                     }
-                    None => self.dist_heap.push(QuerySingleton::new(*i, *d)),
+                    None => {
+                        self.pushes += 1;
+                        self.dist_heap.push(QuerySingleton::new(*i, *d));
+                    }
                 };
                 while self.dist_heap.len() > self.k {
                     self.dist_heap.pop();
@@ -129,6 +145,48 @@ impl KnnQueryHeap {
             known_indexes: HashSet::new(),
             k,
             scale_base,
+            skipped: None,
+            pushes: 0,
+        }
+    }
+
+    /// The number of nodes and singletons that made it onto the heap, the ones pruned on the way in aren't counted.
+    pub(crate) fn pushes(&self) -> usize {
+        self.pushes
+    }
+
+    /// Starts keeping the nodes that are pruned when they're pushed, for explaining a query.
+    pub(crate) fn record_skipped(&mut self) {
+        self.skipped = Some(Vec::new());
+    }
+
+    /// The nodes that were pruned since `record_skipped` was called.
+    pub(crate) fn take_skipped(&mut self) -> Vec<SkippedNode> {
+        self.skipped.take().unwrap_or_default()
+    }
+
+    /// A snapshot of the heap, for explaining a query.
+    pub fn state(&self) -> HeapState {
+        let unvisited = |heap: &BinaryHeap<QueryAddress>| {
+            let mut nodes: Vec<QueryAddress> = heap.iter().cloned().collect();
+            // `QueryAddress` orders backwards so that the heap gives the closest node first
+            nodes.sort_by(|a, b| b.cmp(a));
+            nodes
+                .iter()
+                .map(|n| UnvisitedNode {
+                    address: n.address,
+                    dist_to_center: n.dist_to_center,
+                    min_dist: n.min_dist,
+                })
+                .collect()
+        };
+        let mut knn: Vec<(usize, f32)> = self.dist_heap.iter().map(|s| (s.index, s.dist)).collect();
+        knn.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+        HeapState {
+            knn,
+            max_dist: self.max_dist(),
+            unvisited_children: unvisited(&self.child_heap),
+            unvisited_singletons: unvisited(&self.singleton_heap),
         }
     }
 
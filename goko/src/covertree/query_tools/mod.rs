@@ -20,12 +20,25 @@
 //! Tools and data structures for assisting cover tree queries.
 
 use crate::NodeAddress;
+use serde::{Deserialize, Serialize};
+use std::ops::{Add, AddAssign};
 
 pub(crate) mod query_items;
 
 pub(crate) mod knn_query_heap;
 pub use knn_query_heap::KnnQueryHeap;
 
+pub(crate) mod incremental_query_heap;
+pub use incremental_query_heap::IncrementalQueryHeap;
+
+pub(crate) mod point_filter;
+pub use point_filter::{AllPoints, LabelFilter, PointFilter};
+
+pub(crate) mod explain;
+pub use explain::{
+    HeapState, KnnExplanation, NodeVisit, PathExplanation, SkippedNode, UnvisitedNode, VisitKind,
+};
+
 /// If you have a algorithm that does local brute force KNN on just the children,
 /// implement this to use the node fn
 pub trait RoutingQueryHeap {
@@ -44,3 +57,50 @@ pub trait SingletonQueryHeap {
     /// Shove a bunch of single points onto the heap
     fn push_outliers(&mut self, indexes: &[usize], dists: &[f32]);
 }
+
+/// Counts of the work done by a build or a query, for comparing parameter choices. See
+/// `CoverTreeBuilder::build_with_stats` and the `_with_stats` queries on the reader.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueryStats {
+    /// The number of distances computed
+    pub distance_evaluations: usize,
+    /// The number of times a node's children or singletons were checked. For a build this is the number of nodes split.
+    pub nodes_visited: usize,
+    /// The number of nodes and points pushed onto the query heap. Nodes that are pruned before they're pushed, and
+    /// singletons that are further than the current `k` nearest, aren't counted.
+    pub heap_pushes: usize,
+    /// The number of singletons whose distance to the query was computed
+    pub singletons_scanned: usize,
+}
+
+impl QueryStats {
+    /// Records checking the children of a node, the nested child's distance is the distance to the node's center.
+    pub(crate) fn check_children(&mut self, children_len: usize) {
+        self.nodes_visited += 1;
+        self.distance_evaluations += children_len.saturating_sub(1);
+    }
+
+    /// Records checking the singletons of a node.
+    pub(crate) fn check_singletons(&mut self, singletons_len: usize) {
+        self.nodes_visited += 1;
+        self.distance_evaluations += singletons_len;
+        self.singletons_scanned += singletons_len;
+    }
+}
+
+impl AddAssign for QueryStats {
+    fn add_assign(&mut self, other: QueryStats) {
+        self.distance_evaluations += other.distance_evaluations;
+        self.nodes_visited += other.nodes_visited;
+        self.heap_pushes += other.heap_pushes;
+        self.singletons_scanned += other.singletons_scanned;
+    }
+}
+
+impl Add for QueryStats {
+    type Output = QueryStats;
+    fn add(mut self, other: QueryStats) -> QueryStats {
+        self += other;
+        self
+    }
+}
@@ -0,0 +1,63 @@
+//! Filters that restrict a nearest neighbor query to some of the points, see `CoverTreeReader::filtered_knn`.
+
+use crate::covertree::node::CoverNode;
+use pointcloud::*;
+
+/// Decides which points a filtered query can return. Any `Fn(usize) -> bool` over point indexes is one.
+pub trait PointFilter<D: PointCloud> {
+    /// If the query can return this point.
+    fn accepts_point(&self, point_index: usize) -> bool;
+    /// If the node could cover a point the filter accepts. The query skips the nodes this rejects along with
+    /// everything under them, so only return `false` when you're sure. By default every node is searched.
+    fn accepts_node(&self, _node: &CoverNode<D>) -> bool {
+        true
+    }
+}
+
+impl<D: PointCloud, F: Fn(usize) -> bool> PointFilter<D> for F {
+    fn accepts_point(&self, point_index: usize) -> bool {
+        self(point_index)
+    }
+}
+
+/// Accepts every point, this is the filter of an unfiltered query.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AllPoints;
+
+impl<D: PointCloud> PointFilter<D> for AllPoints {
+    fn accepts_point(&self, _point_index: usize) -> bool {
+        true
+    }
+}
+
+/// Accepts the points with a label. If the tree has label summaries, from the `LabelSummaryPlugin`, the query
+/// skips the nodes whose summary doesn't have the label.
+pub struct LabelFilter<'a, D: PointCloud> {
+    point_cloud: &'a D,
+    label: &'a D::Label,
+}
+
+impl<'a, D: PointCloud> LabelFilter<'a, D> {
+    /// Filters for points in the point cloud with this label.
+    pub fn new(point_cloud: &'a D, label: &'a D::Label) -> LabelFilter<'a, D> {
+        LabelFilter { point_cloud, label }
+    }
+}
+
+impl<'a, D: PointCloud> PointFilter<D> for LabelFilter<'a, D>
+where
+    D::Label: PartialEq,
+{
+    fn accepts_point(&self, point_index: usize) -> bool {
+        match self.point_cloud.label(point_index) {
+            Ok(Some(label)) => label == self.label,
+            _ => false,
+        }
+    }
+
+    fn accepts_node(&self, node: &CoverNode<D>) -> bool {
+        node.label_summary()
+            .and_then(|summary| summary.summary.contains(self.label))
+            .unwrap_or(true)
+    }
+}
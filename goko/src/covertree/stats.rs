@@ -0,0 +1,197 @@
+//! # Tree Statistics
+//!
+//! A summary of the shape of a tree, for tuning the `scale_base`, `leaf_cutoff` and `min_res_index` of a build.
+//! Everything here serializes, so you can dump it to JSON and compare builds.
+
+use super::CoverTreeReader;
+use crate::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// The spread of some per-node value.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DistributionSummary {
+    /// Number of values
+    pub count: usize,
+    /// Smallest value
+    pub min: f32,
+    /// First quartile
+    pub lower_quartile: f32,
+    /// Median
+    pub median: f32,
+    /// Third quartile
+    pub upper_quartile: f32,
+    /// Largest value
+    pub max: f32,
+    /// Mean
+    pub mean: f32,
+}
+
+impl DistributionSummary {
+    /// Summarizes the values, `None` if there aren't any. Non-finite values are ignored.
+    pub fn new(values: &[f32]) -> Option<DistributionSummary> {
+        let mut values: Vec<f32> = values.iter().copied().filter(|v| v.is_finite()).collect();
+        if values.is_empty() {
+            return None;
+        }
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let quantile = |q: f32| values[((values.len() - 1) as f32 * q).round() as usize];
+        Some(DistributionSummary {
+            count: values.len(),
+            min: values[0],
+            lower_quartile: quantile(0.25),
+            median: quantile(0.5),
+            upper_quartile: quantile(0.75),
+            max: values[values.len() - 1],
+            mean: values.iter().sum::<f32>() / values.len() as f32,
+        })
+    }
+}
+
+/// The statistics of a single layer.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LayerStats {
+    /// The scale index of the layer
+    pub scale_index: i32,
+    /// Number of nodes on the layer
+    pub node_count: usize,
+    /// Number of those nodes that are leaves
+    pub leaf_count: usize,
+    /// Number of singletons attached to nodes on this layer
+    pub singleton_count: usize,
+    /// The radii of the nodes on this layer
+    pub radius: Option<DistributionSummary>,
+    /// For the routing nodes on this layer, the fraction of the node's coverage that is in its largest child.
+    /// Close to 1 means the points aren't being split up.
+    pub coverage_imbalance: Option<DistributionSummary>,
+    /// See [`CoverTreeReader::layer_fractal_dim`], `None` for an empty layer
+    pub fractal_dim: Option<f32>,
+    /// See [`CoverTreeReader::layer_weighted_fractal_dim`], `None` for an empty layer
+    pub weighted_fractal_dim: Option<f32>,
+}
+
+/// The statistics of a whole tree, see [`CoverTreeReader::stats`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TreeStats {
+    /// Total number of nodes
+    pub node_count: usize,
+    /// Total number of leaves
+    pub leaf_count: usize,
+    /// Total number of singletons
+    pub singleton_count: usize,
+    /// Number of routing nodes with each branching factor, the number of children plus the number of singletons
+    pub branching_factors: BTreeMap<usize, usize>,
+    /// Number of leaves at each depth, the root is at depth 0
+    pub leaf_depths: BTreeMap<usize, usize>,
+    /// The per-layer statistics, starting with the layer that holds the root
+    pub layers: Vec<LayerStats>,
+}
+
+impl<D: PointCloud> CoverTreeReader<D> {
+    /// Gathers statistics about the shape of the tree. This visits every node once.
+    pub fn stats(&self) -> TreeStats {
+        let mut branching_factors = BTreeMap::new();
+        let mut layers = Vec::with_capacity(self.len());
+        for (si, layer) in self.layers() {
+            let mut leaf_count = 0;
+            let mut singleton_count = 0;
+            let mut radii = Vec::with_capacity(layer.len());
+            let mut imbalances = Vec::new();
+            layer.for_each_node(|_pi, n| {
+                singleton_count += n.singletons_len();
+                // Single point leaves have a radius of -inf, which the summary ignores
+                radii.push(n.radius());
+                match n.children() {
+                    None => leaf_count += 1,
+                    Some(children) => {
+                        *branching_factors
+                            .entry(children.len() + n.singletons_len())
+                            .or_insert(0) += 1;
+                        let largest = children
+                            .iter()
+                            .filter_map(|ca| self.get_node_and(*ca, |c| c.coverage_count()))
+                            .max()
+                            .unwrap_or(0);
+                        imbalances.push(largest as f32 / n.coverage_count() as f32);
+                    }
+                }
+            });
+            let (fractal_dim, weighted_fractal_dim) = if layer.is_empty() {
+                (None, None)
+            } else {
+                (
+                    Some(self.layer_fractal_dim(si)).filter(|d| d.is_finite()),
+                    Some(self.layer_weighted_fractal_dim(si)).filter(|d| d.is_finite()),
+                )
+            };
+            layers.push(LayerStats {
+                scale_index: si,
+                node_count: layer.len(),
+                leaf_count,
+                singleton_count,
+                radius: DistributionSummary::new(&radii),
+                coverage_imbalance: DistributionSummary::new(&imbalances),
+                fractal_dim,
+                weighted_fractal_dim,
+            });
+        }
+
+        let mut leaf_depths = BTreeMap::new();
+        let mut unvisited = vec![(self.root_address(), 0)];
+        while let Some((address, depth)) = unvisited.pop() {
+            self.get_node_and(address, |n| match n.children() {
+                None => *leaf_depths.entry(depth).or_insert(0) += 1,
+                Some(children) => unvisited.extend(children.iter().map(|c| (*c, depth + 1))),
+            });
+        }
+
+        TreeStats {
+            node_count: layers.iter().map(|l| l.node_count).sum(),
+            leaf_count: layers.iter().map(|l| l.leaf_count).sum(),
+            singleton_count: layers.iter().map(|l| l.singleton_count).sum(),
+            branching_factors,
+            leaf_depths,
+            layers,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::build_basic_tree;
+
+    #[test]
+    fn basic_tree_stats() {
+        let tree = build_basic_tree();
+        let reader = tree.reader();
+        let stats = reader.stats();
+        assert_eq!(stats.node_count, reader.node_count());
+        assert_eq!(stats.layers.len(), reader.len());
+        assert_eq!(stats.layers[0].node_count, 1);
+        assert_eq!(stats.leaf_depths.values().sum::<usize>(), stats.leaf_count);
+        assert_eq!(
+            stats.branching_factors.values().sum::<usize>(),
+            stats.node_count - stats.leaf_count
+        );
+        // Every point is a leaf center or a singleton
+        assert_eq!(stats.leaf_count + stats.singleton_count, 5);
+
+        let json = serde_json::to_string(&stats).unwrap();
+        let reloaded: TreeStats = serde_json::from_str(&json).unwrap();
+        assert_eq!(reloaded, stats);
+    }
+
+    #[test]
+    fn distribution_summary() {
+        let summary =
+            DistributionSummary::new(&[4.0, f32::NEG_INFINITY, 0.0, 2.0, 1.0, 3.0]).unwrap();
+        assert_eq!(summary.count, 5);
+        assert_eq!(summary.min, 0.0);
+        assert_eq!(summary.lower_quartile, 1.0);
+        assert_eq!(summary.median, 2.0);
+        assert_eq!(summary.max, 4.0);
+        assert_eq!(summary.mean, 2.0);
+        assert!(DistributionSummary::new(&[]).is_none());
+    }
+}
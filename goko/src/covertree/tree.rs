@@ -34,18 +34,25 @@
 //! The hashmap pair idea is in `layer` and originally comes from Jon Gjengset.
 
 use super::layer::*;
+use super::lazy::LayerLoading;
 use super::node::*;
 use crate::*;
 //use pointcloud::*;
 
+use super::mapped::MappedAddresses;
 use crate::monomap::{MonoReadHandle, MonoWriteHandle};
 use crate::tree_file_format::*;
 use std::sync::{atomic, Arc, RwLock};
 
-use super::query_tools::{KnnQueryHeap, RoutingQueryHeap};
-use crate::plugins::{GokoPlugin, TreePluginSet};
+use super::query_tools::explain::QueryRecorder;
+use super::query_tools::{
+    AllPoints, IncrementalQueryHeap, KnnExplanation, KnnQueryHeap, PathExplanation, PointFilter,
+    QueryStats, RoutingQueryHeap, SkippedNode, VisitKind,
+};
+use crate::plugins::{GokoPlugin, PersistentPlugin, PluginSection, TreePluginSet};
 use errors::{GokoError, GokoResult};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::iter::Iterator;
 use std::iter::Rev;
 use std::ops::Deref;
@@ -57,7 +64,7 @@ use plugins::labels::*;
 /// When 2 spheres overlap under a node, and there is a point in the overlap we have to decide
 /// to which sphere it belongs. As we create the nodes in a particular sequence, we can assign them
 /// to the first to be created or we can assign it to the nearest.
-#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub enum PartitionType {
     /// Conflicts assigning a point to several eligible nodes are assigned to the nearest node.
     Nearest,
@@ -105,8 +112,47 @@ impl<D: PointCloud> CoverTreeParameters<D> {
     }
 }
 
-/// Helper struct for iterating thru the reader's of the the layers.
-pub type LayerIter<'a, D> = Rev<std::iter::Zip<Range<i32>, Iter<'a, CoverLayerReader<D>>>>;
+/// Helper struct for iterating thru the reader's of the the layers. On a lazily loaded tree each layer is waited for
+/// as the iterator gets to it.
+///
+/// # Panics
+/// If the tree is lazily loaded and the layer fails to load.
+pub struct LayerIter<'a, D: PointCloud> {
+    layers: Rev<std::iter::Zip<Range<i32>, Iter<'a, CoverLayerReader<D>>>>,
+    parameters: &'a CoverTreeParameters<D>,
+    loading: Option<&'a LayerLoading>,
+}
+
+impl<'a, D: PointCloud> LayerIter<'a, D> {
+    fn wait(&self, layer: (i32, &'a CoverLayerReader<D>)) -> (i32, &'a CoverLayerReader<D>) {
+        if let Some(loading) = self.loading {
+            loading
+                .wait_for(self.parameters.internal_index(layer.0))
+                .expect("The tree failed to load");
+        }
+        layer
+    }
+}
+
+impl<'a, D: PointCloud> Iterator for LayerIter<'a, D> {
+    type Item = (i32, &'a CoverLayerReader<D>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.layers.next().map(|layer| self.wait(layer))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.layers.size_hint()
+    }
+}
+
+impl<'a, D: PointCloud> DoubleEndedIterator for LayerIter<'a, D> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.layers.next_back().map(|layer| self.wait(layer))
+    }
+}
+
+impl<'a, D: PointCloud> ExactSizeIterator for LayerIter<'a, D> {}
 
 /// # Cover Tree Reader Head
 ///
@@ -122,6 +168,9 @@ pub struct CoverTreeReader<D: PointCloud> {
     layers: Vec<CoverLayerReader<D>>,
     root_address: NodeAddress,
     final_addresses: MonoReadHandle<usize, NodeAddress>,
+    /// Set for trees opened from a memory mapped file, whose final addresses are read out of the file
+    mapped_addresses: Option<MappedAddresses>,
+    loading: Option<Arc<LayerLoading>>,
 }
 
 impl<D: PointCloud> Clone for CoverTreeReader<D> {
@@ -131,6 +180,8 @@ impl<D: PointCloud> Clone for CoverTreeReader<D> {
             layers: self.layers.clone(),
             root_address: self.root_address,
             final_addresses: self.final_addresses.clone(),
+            mapped_addresses: self.mapped_addresses.clone(),
+            loading: self.loading.clone(),
         }
     }
 }
@@ -147,6 +198,7 @@ impl<D: PointCloud> CoverTreeReader<D> {
         &self,
         node_address: NodeAddress,
     ) -> Option<Arc<SummaryCounter<D::LabelSummary>>> {
+        self.wait_for_layer(node_address.scale_index()).ok()?;
         self.layers[self.parameters.internal_index(node_address.scale_index())]
             .get_node_and(node_address.point_index(), |n| n.label_summary())
             .flatten()
@@ -158,6 +210,7 @@ impl<D: PointCloud> CoverTreeReader<D> {
         &self,
         node_address: NodeAddress,
     ) -> Option<Arc<SummaryCounter<D::MetaSummary>>> {
+        self.wait_for_layer(node_address.scale_index()).ok()?;
         self.layers[self.parameters.internal_index(node_address.scale_index())]
             .get_node_and(node_address.point_index(), |n| n.metasummary())
             .flatten()
@@ -165,10 +218,32 @@ impl<D: PointCloud> CoverTreeReader<D> {
 
     /// Returns a borrowed reader for a cover layer.
     ///
+    /// # Panics
+    /// If the tree is lazily loaded and the layer fails to load.
     pub fn layer(&self, scale_index: i32) -> &CoverLayerReader<D> {
+        self.wait_for_layer(scale_index)
+            .expect("The tree failed to load");
         &self.layers[self.parameters.internal_index(scale_index)]
     }
 
+    /// If the tree is being loaded in the background, this blocks until the layer is loaded. The layers are loaded
+    /// from the top down, so this also waits for the coarser layers. Accessing a node or a layer calls this for you.
+    /// Returns the error if the layer failed to load.
+    pub fn wait_for_layer(&self, scale_index: i32) -> GokoResult<()> {
+        match &self.loading {
+            Some(loading) => loading.wait_for(self.parameters.internal_index(scale_index)),
+            None => Ok(()),
+        }
+    }
+
+    /// The error of a failed lazy load. Nodes on the layers that didn't load are missing, so the queries check this.
+    fn check_loaded(&self) -> GokoResult<()> {
+        match &self.loading {
+            Some(loading) => loading.failure(),
+            None => Ok(()),
+        }
+    }
+
     /// simple helper to get the scale from the scale index and the scale base, this is just `b^i`
     pub fn scale(&self, scale_index: i32) -> f32 {
         self.parameters.scale_base.powi(scale_index)
@@ -179,6 +254,7 @@ impl<D: PointCloud> CoverTreeReader<D> {
     where
         F: FnOnce(&CoverNode<D>) -> T,
     {
+        self.wait_for_layer(node_address.scale_index()).ok()?;
         self.layers[self.parameters.internal_index(node_address.scale_index())]
             .get_node_and(node_address.point_index(), |n| f(n))
     }
@@ -189,6 +265,7 @@ impl<D: PointCloud> CoverTreeReader<D> {
     where
         F: FnOnce(&[NodeAddress]) -> T,
     {
+        self.wait_for_layer(node_address.scale_index()).ok()?;
         self.layers[self.parameters.internal_index(node_address.scale_index())]
             .get_node_children_and(node_address.point_index(), f)
     }
@@ -198,12 +275,17 @@ impl<D: PointCloud> CoverTreeReader<D> {
         self.root_address
     }
 
-    /// An iterator for accessing the layers starting from the layer who holds the root.
+    /// An iterator for accessing the layers starting from the layer who holds the root. On a lazily loaded tree this
+    /// blocks on each layer until it's loaded.
     pub fn layers(&self) -> LayerIter<D> {
-        ((self.parameters.min_res_index - 1)
-            ..(self.layers.len() as i32 + self.parameters.min_res_index - 1))
-            .zip(self.layers.iter())
-            .rev()
+        LayerIter {
+            layers: ((self.parameters.min_res_index - 1)
+                ..(self.layers.len() as i32 + self.parameters.min_res_index - 1))
+                .zip(self.layers.iter())
+                .rev(),
+            parameters: &self.parameters,
+            loading: self.loading.as_deref(),
+        }
     }
 
     /// Returns the number of layers in the tree. This is _not_ the number of non-zero layers.
@@ -255,6 +337,7 @@ impl<D: PointCloud> CoverTreeReader<D> {
     where
         F: FnOnce(&T) -> S,
     {
+        self.wait_for_layer(node_address.scale_index()).ok()?;
         self.layers[self.parameters.internal_index(node_address.scale_index())]
             .get_node_and(node_address.point_index(), |n| {
                 n.get_plugin_and(transform_fn)
@@ -284,25 +367,142 @@ impl<D: PointCloud> CoverTreeReader<D> {
         point: &P,
         k: usize,
     ) -> GokoResult<Vec<(usize, f32)>> {
-        let mut query_heap = KnnQueryHeap::new(k, self.parameters.scale_base);
+        self.knn_with_stats(point, k).map(|(knn, _stats)| knn)
+    }
+
+    /// Same as knn, but also counts the work the query did.
+    pub fn knn_with_stats<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+        k: usize,
+    ) -> GokoResult<(Vec<(usize, f32)>, QueryStats)> {
+        let mut recorder = QueryRecorder::default();
+        let query_heap = self.knn_query(point, k, true, &mut recorder)?;
+        Ok((query_heap.unpack(), recorder.stats))
+    }
 
+    /// Same as knn, but records how the query went: every node visited, every child that was pruned and the bound
+    /// it was pruned with, and the query heap at the end.
+    pub fn knn_explain<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+        k: usize,
+    ) -> GokoResult<KnnExplanation> {
+        let mut recorder = QueryRecorder::explaining();
+        let mut query_heap = self.knn_query(point, k, true, &mut recorder)?;
+        let skipped = query_heap.take_skipped();
+        let final_heap = query_heap.state();
+        Ok(KnnExplanation {
+            knn: query_heap.unpack(),
+            stats: recorder.stats,
+            visited: recorder.visited.unwrap_or_default(),
+            skipped,
+            final_heap,
+        })
+    }
+
+    /// An iterator over the points of the tree in order of their distance to the query point, nearest first. The
+    /// search only goes as far into the tree as it needs to for the next point, and picks up where it left off, so
+    /// this is for when you don't know `k` in advance. It owns the point and a clone of the reader, so it can be kept
+    /// around between pages.
+    ///
+    /// ```rust,ignore
+    /// let mut nbrs = reader.knn_iter(point)?;
+    /// let first_page = nbrs.by_ref().take(10).collect::<GokoResult<Vec<_>>>()?;
+    /// let second_page = nbrs.take(10).collect::<GokoResult<Vec<_>>>()?;
+    /// ```
+    pub fn knn_iter<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: P,
+    ) -> GokoResult<KnnIter<D, P>> {
+        self.filtered_knn_iter(point, AllPoints)
+    }
+
+    /// Same as knn_iter, but only returns the points the filter accepts.
+    pub fn filtered_knn_iter<P: Deref<Target = D::Point> + Send + Sync, F: PointFilter<D>>(
+        &self,
+        point: P,
+        filter: F,
+    ) -> GokoResult<KnnIter<D, P, F>> {
+        let query_heap = self.incremental_query_heap(&point)?;
+        Ok(KnnIter {
+            reader: self.clone(),
+            point,
+            query_heap,
+            filter,
+        })
+    }
+
+    /// The nearest `k` points that the filter accepts, nearest first. There may be fewer than `k` if the filter
+    /// doesn't accept enough points. Any `Fn(usize) -> bool` over point indexes is a filter, and a `LabelFilter` uses
+    /// the label summaries to skip the nodes that don't have the label.
+    ///
+    /// This searches the nodes in order of how near a point they cover could be, and stops once it has `k` points.
+    /// So it only goes as far as the `k`th accepted point, but a filter that accepts few of the points near the query
+    /// point makes it search a lot of the tree.
+    pub fn filtered_knn<P: Deref<Target = D::Point> + Send + Sync, F: PointFilter<D>>(
+        &self,
+        point: &P,
+        k: usize,
+        filter: &F,
+    ) -> GokoResult<Vec<(usize, f32)>> {
+        let mut query_heap = self.incremental_query_heap(point)?;
+        let mut knn = Vec::with_capacity(k);
+        while knn.len() < k {
+            match self.next_nearest(point, &mut query_heap, filter) {
+                Some(nearest) => knn.push(nearest?),
+                None => break,
+            }
+        }
+        Ok(knn)
+    }
+
+    fn incremental_query_heap<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+    ) -> GokoResult<IncrementalQueryHeap> {
+        let mut query_heap = IncrementalQueryHeap::new(self.parameters.scale_base);
         let root_center = self
             .parameters
             .point_cloud
             .point(self.root_address.point_index())?;
-        let dist_to_root = D::Metric::dist(&root_center, &point);
+        let dist_to_root = D::Metric::dist(&root_center, point);
         query_heap.push_nodes(&[self.root_address], &[dist_to_root], None);
-        self.greedy_knn_nodes(point, &mut query_heap);
+        Ok(query_heap)
+    }
 
-        while let Some((address, _dist)) = query_heap.closest_unvisited_singleton_covering_address()
-        {
-            self.get_node_and(address, |n| {
-                n.singleton_knn(point, &self.parameters.point_cloud, &mut query_heap)
+    /// Searches until it knows the nearest point on the heap that the filter accepts.
+    fn next_nearest<P: Deref<Target = D::Point> + Send + Sync, F: PointFilter<D>>(
+        &self,
+        point: &P,
+        query_heap: &mut IncrementalQueryHeap,
+        filter: &F,
+    ) -> Option<GokoResult<(usize, f32)>> {
+        loop {
+            if let Some(nearest) = query_heap.pop_point() {
+                if filter.accepts_point(nearest.0) {
+                    return Some(Ok(nearest));
+                }
+                continue;
+            }
+            let (address, dist) = query_heap.pop_node()?;
+            let visited = self.get_node_and(address, |n| {
+                if !filter.accepts_node(n) {
+                    return Ok(());
+                }
+                n.singleton_knn(point, &self.parameters.point_cloud, query_heap)?;
+                n.child_knn(Some(dist), point, &self.parameters.point_cloud, query_heap)
             });
-            self.greedy_knn_nodes(point, &mut query_heap);
+            match visited {
+                Some(Err(e)) => return Some(Err(e)),
+                None => {
+                    if let Err(e) = self.check_loaded() {
+                        return Some(Err(e));
+                    }
+                }
+                Some(Ok(())) => {}
+            }
         }
-
-        Ok(query_heap.unpack())
     }
 
     /// Same as knn, but only deals with non-singleton points
@@ -311,24 +511,66 @@ impl<D: PointCloud> CoverTreeReader<D> {
         point: &P,
         k: usize,
     ) -> GokoResult<Vec<(usize, f32)>> {
+        self.routing_knn_with_stats(point, k)
+            .map(|(knn, _stats)| knn)
+    }
+
+    /// Same as routing_knn, but also counts the work the query did.
+    pub fn routing_knn_with_stats<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+        k: usize,
+    ) -> GokoResult<(Vec<(usize, f32)>, QueryStats)> {
+        let mut recorder = QueryRecorder::default();
+        let query_heap = self.knn_query(point, k, false, &mut recorder)?;
+        Ok((query_heap.unpack(), recorder.stats))
+    }
+
+    /// The body of the knn queries. This returns the query heap at the end so that it can be unpacked or explained.
+    fn knn_query<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+        k: usize,
+        singletons: bool,
+        recorder: &mut QueryRecorder,
+    ) -> GokoResult<KnnQueryHeap> {
         let mut query_heap = KnnQueryHeap::new(k, self.parameters.scale_base);
+        if recorder.visited.is_some() {
+            query_heap.record_skipped();
+        }
 
         let root_center = self
             .parameters
             .point_cloud
             .point(self.root_address.point_index())?;
         let dist_to_root = D::Metric::dist(&root_center, &point);
+        recorder.stats.distance_evaluations += 1;
         query_heap.push_nodes(&[self.root_address], &[dist_to_root], None);
-        self.greedy_knn_nodes(point, &mut query_heap);
+        self.greedy_knn_nodes(point, &mut query_heap, recorder);
 
-        while self.greedy_knn_nodes(point, &mut query_heap) {}
-        Ok(query_heap.unpack())
+        if singletons {
+            while let Some((address, dist)) =
+                query_heap.closest_unvisited_singleton_covering_address()
+            {
+                self.get_node_and(address, |n| {
+                    recorder.check_singletons(address, dist, n.singletons_len());
+                    n.singleton_knn(point, &self.parameters.point_cloud, &mut query_heap)
+                });
+                self.greedy_knn_nodes(point, &mut query_heap, recorder);
+            }
+        } else {
+            while self.greedy_knn_nodes(point, &mut query_heap, recorder) {}
+        }
+        recorder.stats.heap_pushes += query_heap.pushes();
+        self.check_loaded()?;
+        Ok(query_heap)
     }
 
     fn greedy_knn_nodes<P: Deref<Target = D::Point> + Send + Sync>(
         &self,
         point: &P,
         query_heap: &mut KnnQueryHeap,
+        recorder: &mut QueryRecorder,
     ) -> bool {
         let mut did_something = false;
         while let Some((nearest_address, dist)) =
@@ -341,6 +583,7 @@ impl<D: PointCloud> CoverTreeReader<D> {
                 break;
             } else {
                 self.get_node_and(nearest_address, |n| {
+                    recorder.check_children(nearest_address, dist, n.children_len());
                     n.child_knn(Some(dist), point, &self.parameters.point_cloud, query_heap)
                 });
             }
@@ -353,16 +596,63 @@ impl<D: PointCloud> CoverTreeReader<D> {
     pub fn path<P: Deref<Target = D::Point> + Send + Sync>(
         &self,
         point: &P,
+    ) -> GokoResult<Vec<(NodeAddress, f32)>> {
+        self.path_with_stats(point).map(|(trace, _stats)| trace)
+    }
+
+    /// Same as path, but also counts the work the query did.
+    pub fn path_with_stats<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+    ) -> GokoResult<(Vec<(NodeAddress, f32)>, QueryStats)> {
+        let mut recorder = QueryRecorder::default();
+        let trace = self.path_query(point, &mut recorder, None)?;
+        Ok((trace, recorder.stats))
+    }
+
+    /// Same as path, but records how the query went: every node visited, and the children of those nodes that were
+    /// considered but not picked. This computes the distances to those children again, they aren't in the stats.
+    pub fn path_explain<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+    ) -> GokoResult<PathExplanation> {
+        let mut recorder = QueryRecorder::explaining();
+        let mut skipped = Vec::new();
+        let path = self.path_query(point, &mut recorder, Some(&mut skipped))?;
+        Ok(PathExplanation {
+            path,
+            stats: recorder.stats,
+            visited: recorder.visited.unwrap_or_default(),
+            skipped,
+        })
+    }
+
+    /// The body of the path queries.
+    fn path_query<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+        recorder: &mut QueryRecorder,
+        mut skipped: Option<&mut Vec<SkippedNode>>,
     ) -> GokoResult<Vec<(NodeAddress, f32)>> {
         let root_center = self
             .parameters
             .point_cloud
             .point(self.root_address.point_index())?;
         let mut current_distance = D::Metric::dist(&root_center, &point);
+        recorder.stats.distance_evaluations += 1;
         let mut current_address = self.root_address;
         let mut trace = vec![(current_address, current_distance)];
-        while let Some(nearest) =
-            self.get_node_and(current_address, |n| match self.parameters.partition_type {
+        while let Some(nearest) = self.get_node_and(current_address, |n| {
+            if let Some(children) = n.children() {
+                recorder.stats.nodes_visited += 1;
+                recorder.visit(current_address, current_distance, VisitKind::Children);
+                // The first covering child can stop at the nested child, which is as far as the center
+                let nested_covers = current_distance < self.scale(children[0].scale_index());
+                if self.parameters.partition_type == PartitionType::Nearest || !nested_covers {
+                    recorder.stats.distance_evaluations += children.len() - 1;
+                }
+            }
+            match self.parameters.partition_type {
                 PartitionType::Nearest => n.nearest_covering_child(
                     self.parameters.scale_base,
                     current_distance,
@@ -375,9 +665,18 @@ impl<D: PointCloud> CoverTreeReader<D> {
                     point,
                     &self.parameters.point_cloud,
                 ),
-            })
-        {
-            if let Some(nearest) = nearest? {
+            }
+        }) {
+            let nearest = nearest?;
+            if let Some(skipped) = skipped.as_mut() {
+                skipped.extend(self.skipped_path_children(
+                    point,
+                    current_address,
+                    current_distance,
+                    nearest,
+                )?);
+            }
+            if let Some(nearest) = nearest {
                 trace.push(nearest);
                 current_distance = nearest.1;
                 current_address = nearest.0;
@@ -385,31 +684,95 @@ impl<D: PointCloud> CoverTreeReader<D> {
                 break;
             }
         }
+        self.check_loaded()?;
         Ok(trace)
     }
 
-    ///
-    pub fn known_path(&self, point_index: usize) -> GokoResult<Vec<(NodeAddress, f32)>> {
-        self.final_addresses
-            .get_and(&point_index, |addr| {
-                let mut path = Vec::with_capacity(
-                    (self.root_address().scale_index() - addr.scale_index()) as usize,
-                );
-                let mut parent = Some(*addr);
-                while let Some(addr) = parent {
-                    path.push(addr);
-                    parent = self.get_node_and(addr, |n| n.parent_address()).flatten();
+    /// The children of a node on a path that were considered, but not picked.
+    fn skipped_path_children<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+        address: NodeAddress,
+        dist_to_center: f32,
+        picked: Option<(NodeAddress, f32)>,
+    ) -> GokoResult<Vec<SkippedNode>> {
+        let children = match self.get_node_children_and(address, |c| c.to_vec()) {
+            Some(children) => children,
+            None => return Ok(Vec::new()),
+        };
+        let children_indexes: Vec<usize> =
+            children[1..].iter().map(|na| na.point_index()).collect();
+        let mut distances = vec![dist_to_center];
+        distances.extend(
+            self.parameters
+                .point_cloud
+                .distances_to_point(point, &children_indexes[..])?,
+        );
+        // The nearest covering child only looks at the nearest child
+        let nearest_dist = distances.iter().cloned().fold(f32::MAX, f32::min);
+        // The first covering child doesn't look past the child it picks
+        let considered = match (self.parameters.partition_type, picked) {
+            (PartitionType::First, Some((picked_address, _))) => children
+                .iter()
+                .position(|na| *na == picked_address)
+                .unwrap_or(children.len()),
+            _ => children.len(),
+        };
+        Ok(children
+            .iter()
+            .zip(distances)
+            .take(considered)
+            .filter(|(na, _)| Some(**na) != picked.map(|(picked_address, _)| picked_address))
+            .map(|(na, d)| {
+                let radius = self.scale(na.scale_index());
+                let bound = match self.parameters.partition_type {
+                    PartitionType::Nearest => radius.min(nearest_dist),
+                    PartitionType::First => radius,
+                };
+                SkippedNode {
+                    address: *na,
+                    dist_to_center: d,
+                    min_dist: (d - radius).max(0.0),
+                    bound,
                 }
-                (&mut path[..]).reverse();
-                let point_indexes: Vec<usize> = path.iter().map(|na| na.point_index()).collect();
-                let dists = self
-                    .parameters
-                    .point_cloud
-                    .distances_to_point_index(point_index, &point_indexes[..])
-                    .unwrap();
-                dists.iter().zip(path).map(|(d, a)| (a, *d)).collect()
             })
-            .ok_or(GokoError::IndexNotInTree(point_index))
+            .collect())
+    }
+
+    ///
+    pub fn known_path(&self, point_index: usize) -> GokoResult<Vec<(NodeAddress, f32)>> {
+        if let Some(loading) = &self.loading {
+            // The point's node may be on a layer that isn't loaded yet
+            if !self.final_addresses.contains_key(&point_index) {
+                loading.wait_for_all()?;
+            }
+        }
+        let final_address = match &self.mapped_addresses {
+            Some(mapped) => mapped.get(point_index),
+            None => self.final_addresses.get_and(&point_index, |addr| *addr),
+        }
+        .ok_or(GokoError::IndexNotInTree(point_index))?;
+        // Each step goes up at least one layer, so a longer walk means the parents of a corrupt file loop
+        let mut path = Vec::with_capacity(self.layers.len());
+        let mut parent = Some(final_address);
+        while let Some(addr) = parent {
+            if path.len() == self.layers.len() {
+                return Err(GokoError::CorruptTreeFile(format!(
+                    "the parents of {:?} don't lead to the root",
+                    final_address
+                )));
+            }
+            path.push(addr);
+            parent = self.get_node_and(addr, |n| n.parent_address()).flatten();
+        }
+        self.check_loaded()?;
+        path.reverse();
+        let point_indexes: Vec<usize> = path.iter().map(|na| na.point_index()).collect();
+        let dists = self
+            .parameters
+            .point_cloud
+            .distances_to_point_index(point_index, &point_indexes[..])?;
+        Ok(dists.iter().zip(path).map(|(d, a)| (a, *d)).collect())
     }
 
     ///Computes the fractal dimension of a node
@@ -514,23 +877,163 @@ impl<D: PointCloud> CoverTreeReader<D> {
     }
 }
 
+/// The nearest neighbors of a point in order, see [`CoverTreeReader::knn_iter`] and
+/// [`CoverTreeReader::filtered_knn_iter`].
+pub struct KnnIter<D: PointCloud, P, F = AllPoints> {
+    reader: CoverTreeReader<D>,
+    point: P,
+    query_heap: IncrementalQueryHeap,
+    filter: F,
+}
+
+impl<D: PointCloud, P: Deref<Target = D::Point> + Send + Sync, F: PointFilter<D>> Iterator
+    for KnnIter<D, P, F>
+{
+    type Item = GokoResult<(usize, f32)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.reader
+            .next_nearest(&self.point, &mut self.query_heap, &self.filter)
+    }
+}
+
+/// Encodes a persistent plugin's section of a tree file.
+pub(crate) type PluginEncoder<D> =
+    Box<dyn Fn(&CoverTreeReader<D>) -> GokoResult<Vec<u8>> + Send + Sync>;
+
 ///
 pub struct CoverTreeWriter<D: PointCloud> {
     pub(crate) parameters: Arc<CoverTreeParameters<D>>,
     pub(crate) layers: Vec<CoverLayerWriter<D>>,
     pub(crate) root_address: NodeAddress,
     pub(crate) final_addresses: MonoWriteHandle<usize, NodeAddress>,
+    /// Set for trees opened from a memory mapped file, see `covertree::mapped`.
+    pub(crate) mapped_addresses: Option<MappedAddresses>,
+    /// Set while the layers are being loaded in the background, see `utils::load_tree_lazy`.
+    pub(crate) loading: Option<Arc<LayerLoading>>,
+    /// Plugin sections of the file this tree was loaded from that haven't been restored yet, by section name.
+    pub(crate) saved_plugins: HashMap<String, Vec<u8>>,
+    /// The encoders of the persistent plugins attached to this tree, by section name.
+    pub(crate) plugin_encoders: Vec<(String, PluginEncoder<D>)>,
 }
 
 impl<D: PointCloud> CoverTreeWriter<D> {
-    ///
+    /// Attaches the metadata summaries, restoring them if the tree was loaded from a file that has them.
     pub fn generate_meta_summaries(&mut self) {
-        self.add_plugin::<MetaSummaryPlugin>(MetaSummaryPlugin::default())
+        self.add_persistent_plugin::<MetaSummaryPlugin>(MetaSummaryPlugin::default())
     }
 
-    ///
+    /// Attaches the label summaries, restoring them if the tree was loaded from a file that has them.
     pub fn generate_summaries(&mut self) {
-        self.add_plugin::<LabelSummaryPlugin>(LabelSummaryPlugin::default())
+        self.add_persistent_plugin::<LabelSummaryPlugin>(LabelSummaryPlugin::default())
+    }
+
+    /// Attaches a plugin that is saved with the tree. If the tree was loaded from a file with a section for this
+    /// plugin, saved with the same parameters, the node components are read from it and nothing is recomputed.
+    /// Otherwise this is `add_plugin`. Adding a persistent plugin that's already attached with the same parameters
+    /// does nothing.
+    pub fn add_persistent_plugin<P: PersistentPlugin<D>>(&mut self, plug_in: P) {
+        let name = P::section_name();
+        let parameters = serde_json::to_value(&plug_in).ok();
+        let attached = self.plugin_encoders.iter().any(|(n, _)| n == &name)
+            && self
+                .reader()
+                .get_plugin_and::<P, _, _>(|p| serde_json::to_value(p).ok())
+                .flatten()
+                == parameters;
+        if attached {
+            return;
+        }
+
+        let restored = match self.saved_plugins.remove(&name) {
+            Some(bytes) => self.restore_plugin::<P>(&bytes, &parameters),
+            None => false,
+        };
+        if !restored {
+            self.add_plugin::<P>(plug_in);
+        }
+
+        let encoder: PluginEncoder<D> = Box::new(|reader: &CoverTreeReader<D>| {
+            let parameters = reader
+                .get_plugin_and::<P, _, _>(|p| p.clone())
+                .expect("The plugin was removed from the tree");
+            let mut components = Vec::new();
+            for (_si, layer) in reader.layers() {
+                layer.for_each_node(|_pi, n| {
+                    if let Some(c) = n.get_plugin_and::<P::NodeComponent, _, _>(|c| c.clone()) {
+                        components.push((n.address(), c));
+                    }
+                });
+            }
+            Ok(serde_json::to_vec(&PluginSection {
+                parameters,
+                components,
+            })?)
+        });
+        self.plugin_encoders.retain(|(n, _)| n != &name);
+        self.plugin_encoders.push((name, encoder));
+    }
+
+    /// Reads a saved section back onto the nodes. Returns false if the section can't be read, or was saved with
+    /// different parameters, in which case the plugin has to be recomputed. `prepare_tree` isn't called.
+    fn restore_plugin<P: PersistentPlugin<D>>(
+        &mut self,
+        bytes: &[u8],
+        parameters: &Option<serde_json::Value>,
+    ) -> bool {
+        let section: PluginSection<P, P::NodeComponent> = match serde_json::from_slice(bytes) {
+            Ok(section) => section,
+            Err(_) => return false,
+        };
+        if parameters.is_none() || serde_json::to_value(&section.parameters).ok() != *parameters {
+            return false;
+        }
+        for (address, component) in section.components {
+            unsafe { self.update_node(address, move |n| n.insert_plugin(component.clone())) }
+        }
+        self.refresh();
+        self.parameters
+            .plugins
+            .write()
+            .unwrap()
+            .insert(section.parameters);
+        true
+    }
+
+    /// Encodes the sections of the persistent plugins for a tree file. Sections loaded from a file that were never
+    /// restored are passed through unchanged.
+    pub(crate) fn plugin_sections(&self) -> GokoResult<Vec<(String, Vec<u8>)>> {
+        let reader = self.reader();
+        let mut sections = self
+            .plugin_encoders
+            .iter()
+            .map(|(name, encoder)| Ok((name.clone(), encoder(&reader)?)))
+            .collect::<GokoResult<Vec<_>>>()?;
+        sections.extend(
+            self.saved_plugins
+                .iter()
+                .map(|(name, bytes)| (name.clone(), bytes.clone())),
+        );
+        sections.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(sections)
+    }
+
+    /// Sets the plugin sections of the file this tree was loaded from, and restores the label and metadata summaries
+    /// if they were saved. Other plugins are restored when they're added with `add_persistent_plugin`.
+    pub(crate) fn set_saved_plugins(&mut self, sections: HashMap<String, Vec<u8>>) {
+        self.saved_plugins = sections;
+        if self
+            .saved_plugins
+            .contains_key(&<LabelSummaryPlugin as PersistentPlugin<D>>::section_name())
+        {
+            self.generate_summaries();
+        }
+        if self
+            .saved_plugins
+            .contains_key(&<MetaSummaryPlugin as PersistentPlugin<D>>::section_name())
+        {
+            self.generate_meta_summaries();
+        }
     }
 
     ///
@@ -570,6 +1073,8 @@ impl<D: PointCloud> CoverTreeWriter<D> {
             layers: self.layers.iter().map(|l| l.reader()).collect(),
             root_address: self.root_address,
             final_addresses: self.final_addresses.factory().handle(),
+            mapped_addresses: self.mapped_addresses.clone(),
+            loading: self.loading.clone(),
         }
     }
 
@@ -580,24 +1085,20 @@ impl<D: PointCloud> CoverTreeWriter<D> {
 
     /// Loads a tree from a protobuf. There's a `load_tree` in `utils` that handles loading from a path to a protobuf file.
     pub fn load(cover_proto: &CoreProto, point_cloud: Arc<D>) -> GokoResult<CoverTreeWriter<D>> {
-        let partition_type = if cover_proto.partition_type == "first" {
-            PartitionType::First
-        } else {
-            PartitionType::Nearest
-        };
+        CoverTreeWriter::load_with_rng_seed(cover_proto, point_cloud, None)
+    }
 
-        let parameters = Arc::new(CoverTreeParameters {
-            total_nodes: atomic::AtomicUsize::new(0),
-            use_singletons: cover_proto.use_singletons,
-            scale_base: cover_proto.scale_base as f32,
-            leaf_cutoff: cover_proto.cutoff as usize,
-            min_res_index: cover_proto.resolution as i32,
+    /// The protobuf doesn't have the seed, so the file header passes it in here.
+    pub(crate) fn load_with_rng_seed(
+        cover_proto: &CoreProto,
+        point_cloud: Arc<D>,
+        rng_seed: Option<u64>,
+    ) -> GokoResult<CoverTreeWriter<D>> {
+        let parameters = Arc::new(CoverTreeWriter::parameters_from_proto(
+            cover_proto,
             point_cloud,
-            verbosity: 2,
-            partition_type,
-            plugins: RwLock::new(TreePluginSet::new()),
-            rng_seed: None,
-        });
+            rng_seed,
+        ));
         let root_address: NodeAddress = (
             cover_proto.get_root_scale(),
             cover_proto.get_root_index() as usize,
@@ -616,6 +1117,10 @@ impl<D: PointCloud> CoverTreeWriter<D> {
             layers,
             root_address,
             final_addresses,
+            mapped_addresses: None,
+            loading: None,
+            saved_plugins: HashMap::new(),
+            plugin_encoders: Vec::new(),
         };
 
         tree.refresh_final_indexes();
@@ -623,6 +1128,31 @@ impl<D: PointCloud> CoverTreeWriter<D> {
         Ok(tree)
     }
 
+    /// The parameters saved in the protobuf, which doesn't have the seed.
+    pub(crate) fn parameters_from_proto(
+        cover_proto: &CoreProto,
+        point_cloud: Arc<D>,
+        rng_seed: Option<u64>,
+    ) -> CoverTreeParameters<D> {
+        let partition_type = if cover_proto.partition_type == "first" {
+            PartitionType::First
+        } else {
+            PartitionType::Nearest
+        };
+        CoverTreeParameters {
+            total_nodes: atomic::AtomicUsize::new(0),
+            use_singletons: cover_proto.use_singletons,
+            scale_base: cover_proto.scale_base as f32,
+            leaf_cutoff: cover_proto.cutoff as usize,
+            min_res_index: cover_proto.resolution as i32,
+            point_cloud,
+            verbosity: 2,
+            partition_type,
+            plugins: RwLock::new(TreePluginSet::new()),
+            rng_seed,
+        }
+    }
+
     /// Completely redoes the final index map.
     pub fn refresh_final_indexes(&mut self) {
         let reader = self.reader();
@@ -663,13 +1193,16 @@ impl<D: PointCloud> CoverTreeWriter<D> {
         cover_proto.set_root_scale(self.root_address.scale_index());
         cover_proto.set_root_index(self.root_address.point_index() as u64);
         cover_proto.set_layers(self.layers.iter().map(|l| l.save()).collect());
-        let name_map: std::collections::HashMap<String, u64> =
-            self.final_addresses.map_into(|k, _v| {
-                (
-                    self.parameters.point_cloud.name(*k).unwrap().to_string(),
-                    *k as u64,
-                )
-            });
+        let name = |k: usize| {
+            (
+                self.parameters.point_cloud.name(k).unwrap().to_string(),
+                k as u64,
+            )
+        };
+        let name_map: std::collections::HashMap<String, u64> = match &self.mapped_addresses {
+            Some(mapped) => mapped.point_indexes().map(name).collect(),
+            None => self.final_addresses.map_into(|k, _v| name(*k)),
+        };
         cover_proto.set_name_map(name_map);
         cover_proto
     }
@@ -685,7 +1218,12 @@ impl<D: PointCloud> CoverTreeWriter<D> {
 pub(crate) mod tests {
     use super::*;
 
+    use crate::query_tools::LabelFilter;
     use crate::utils::cover_tree_from_labeled_yaml;
+    use pointcloud::data_sources::{DataCompressed, DataRam};
+    use pointcloud::label_sources::StringLabels;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
     use std::path::Path;
 
     pub(crate) fn build_mnist_tree() -> CoverTreeWriter<DefaultLabeledCloud<L2>> {
@@ -711,10 +1249,33 @@ pub(crate) mod tests {
             partition_type: PartitionType::Nearest,
             verbosity: 0,
             rng_seed: Some(0),
+            ..Default::default()
         };
         builder.build(Arc::new(point_cloud)).unwrap()
     }
 
+    /// `count` uniform random points in the unit cube of dimension `dim`. It's seeded so failures can be reproduced.
+    pub(crate) fn random_data(count: usize, dim: usize, seed: u64) -> Vec<f32> {
+        let mut rng = SmallRng::seed_from_u64(seed);
+        (0..count * dim).map(|_| rng.gen::<f32>()).collect()
+    }
+
+    /// The builder the random trees are built with.
+    pub(crate) fn random_tree_builder() -> CoverTreeBuilder {
+        let mut builder = CoverTreeBuilder::new();
+        builder.set_leaf_cutoff(5).set_rng_seed(0);
+        builder
+    }
+
+    /// A tree over 1000 random points in 3 dimensions, along with the cloud for brute force checks.
+    pub(crate) fn build_random_tree() -> (Arc<DataRam<L2>>, CoverTreeWriter<DataRam<L2>>) {
+        let point_cloud = Arc::new(DataRam::<L2>::new(random_data(1000, 3, 0), 3).unwrap());
+        let tree = random_tree_builder()
+            .build(Arc::clone(&point_cloud))
+            .unwrap();
+        (point_cloud, tree)
+    }
+
     #[test]
     fn len_is_num_layers() {
         let tree = build_basic_tree();
@@ -758,6 +1319,7 @@ pub(crate) mod tests {
             partition_type: PartitionType::Nearest,
             verbosity: 0,
             rng_seed: Some(0),
+            ..Default::default()
         };
         let tree = builder.build(Arc::new(point_cloud)).unwrap();
         let reader = tree.reader();
@@ -780,7 +1342,11 @@ pub(crate) mod tests {
                 .0
         );
 
-        reader.greedy_knn_nodes(&point.as_ref(), &mut query_heap);
+        reader.greedy_knn_nodes(
+            &point.as_ref(),
+            &mut query_heap,
+            &mut QueryRecorder::default(),
+        );
         println!("{:#?}", query_heap);
         println!(
             "{:#?}",
@@ -865,6 +1431,7 @@ pub(crate) mod tests {
             partition_type: PartitionType::Nearest,
             verbosity: 0,
             rng_seed: Some(0),
+            ..Default::default()
         };
         let mut tree = builder.build(Arc::new(point_cloud)).unwrap();
         tree.generate_summaries();
@@ -896,6 +1463,7 @@ pub(crate) mod tests {
             partition_type: PartitionType::Nearest,
             verbosity: 0,
             rng_seed: Some(0),
+            ..Default::default()
         };
         let tree = builder.build(Arc::new(point_cloud)).unwrap();
         let reader = tree.reader();
@@ -921,6 +1489,7 @@ pub(crate) mod tests {
             partition_type: PartitionType::Nearest,
             verbosity: 0,
             rng_seed: Some(0),
+            ..Default::default()
         };
         let tree = builder.build(Arc::clone(&point_cloud)).unwrap();
         let reader = tree.reader();
@@ -935,6 +1504,7 @@ pub(crate) mod tests {
         let reconstructed_tree_writer =
             CoverTreeWriter::load(&proto, Arc::clone(&point_cloud)).unwrap();
         let reconstructed_tree = reconstructed_tree_writer.reader();
+        assert_eq!(reconstructed_tree.validate().unwrap(), vec![]);
 
         assert_eq!(reader.layers.len(), reconstructed_tree.layers.len());
         for (layer, reconstructed_layer) in reader.layers.iter().zip(reconstructed_tree.layers) {
@@ -951,4 +1521,240 @@ pub(crate) mod tests {
             })
         }
     }
+
+    #[test]
+    fn test_save_load_string_labeled_tree() {
+        let data = vec![0.499, 0.49, 0.48, -0.49, 0.0];
+        let labels = StringLabels::new(&["benign", "benign", "benign", "trojan", "trojan"], None);
+
+        let point_cloud = Arc::new(SimpleLabeledCloud::new(
+            DataRam::<L2>::new(data, 1).unwrap(),
+            labels,
+        ));
+        let builder = CoverTreeBuilder {
+            scale_base: 2.0,
+            leaf_cutoff: 1,
+            min_res_index: -9,
+            use_singletons: false,
+            partition_type: PartitionType::Nearest,
+            verbosity: 0,
+            rng_seed: Some(0),
+            ..Default::default()
+        };
+        let mut tree = builder.build(Arc::clone(&point_cloud)).unwrap();
+        tree.generate_summaries();
+        let proto = tree.save();
+
+        let mut reconstructed_tree =
+            CoverTreeWriter::load(&proto, Arc::clone(&point_cloud)).unwrap();
+        reconstructed_tree.generate_summaries();
+
+        let reader = tree.reader();
+        let reconstructed_reader = reconstructed_tree.reader();
+        for (_, layer) in reader.layers() {
+            layer.for_each_node(|pi, n| {
+                let summary = n.label_summary().unwrap();
+                let reconstructed_summary = reconstructed_reader
+                    .get_node_label_summary((n.scale_index(), *pi).into())
+                    .unwrap();
+                assert_eq!(summary.summary.items, reconstructed_summary.summary.items);
+            })
+        }
+        let root_summary = reconstructed_reader
+            .get_node_label_summary(reconstructed_reader.root_address())
+            .unwrap();
+        assert!(root_summary
+            .summary
+            .items
+            .contains(&("trojan".to_string(), 2)));
+    }
+
+    #[test]
+    fn compressed_tree_matches_ram() {
+        let data: Vec<f32> = (0..200).map(|i| ((i * 37) % 101) as f32 / 10.0).collect();
+        let ram_cloud = Arc::new(DataRam::<L2>::new(data.clone(), 2).unwrap());
+        let compressed_cloud = Arc::new(DataCompressed::<L2>::new(&data, 2, 8).unwrap());
+
+        let mut builder = CoverTreeBuilder::new();
+        builder.set_leaf_cutoff(1).set_rng_seed(0);
+        let ram_tree = builder.build(ram_cloud).unwrap();
+        let compressed_tree = builder.build(compressed_cloud).unwrap();
+
+        let point = [3.3, 4.4];
+        let ram_knn = ram_tree.reader().knn(&&point[..], 5).unwrap();
+        let compressed_knn = compressed_tree.reader().knn(&&point[..], 5).unwrap();
+        assert_eq!(ram_knn, compressed_knn);
+    }
+
+    #[test]
+    fn knn_matches_brute_force() {
+        let (point_cloud, tree) = build_random_tree();
+        let reader = tree.reader();
+
+        for point in random_data(20, 3, 1).chunks(3).map(|p| p.to_vec()) {
+            let tree_knn = reader.knn(&&point[..], 10).unwrap();
+            let brute_knn = point_cloud.brute_knn(&&point[..], 10).unwrap();
+            assert_eq!(tree_knn.len(), brute_knn.len());
+            for (t, b) in tree_knn.iter().zip(&brute_knn) {
+                assert_approx_eq!(t.1, b.1);
+            }
+        }
+    }
+
+    #[test]
+    fn query_stats() {
+        let (_, tree) = build_random_tree();
+        let reader = tree.reader();
+
+        for point in random_data(20, 3, 1).chunks(3).map(|p| p.to_vec()) {
+            let (knn, stats) = reader.knn_with_stats(&&point[..], 10).unwrap();
+            assert_eq!(knn, reader.knn(&&point[..], 10).unwrap());
+            assert!(stats.nodes_visited > 0);
+            assert!(stats.singletons_scanned <= stats.distance_evaluations);
+            // Every push needs a distance, apart from the nested children that reuse their parent's
+            assert!(stats.heap_pushes > 0);
+            assert!(stats.heap_pushes <= stats.distance_evaluations + stats.nodes_visited);
+            assert!(stats.distance_evaluations < 1000);
+
+            let (routing_knn, routing_stats) =
+                reader.routing_knn_with_stats(&&point[..], 10).unwrap();
+            assert_eq!(routing_knn, reader.routing_knn(&&point[..], 10).unwrap());
+            assert_eq!(routing_stats.singletons_scanned, 0);
+
+            let (trace, path_stats) = reader.path_with_stats(&&point[..]).unwrap();
+            assert_eq!(trace, reader.path(&&point[..]).unwrap());
+            assert!(path_stats.nodes_visited + 1 >= trace.len());
+            assert!(path_stats.distance_evaluations >= path_stats.nodes_visited);
+            assert_eq!(path_stats.heap_pushes, 0);
+        }
+    }
+
+    #[test]
+    fn explained_queries_match() {
+        let point_cloud = Arc::new(DataRam::<L2>::new(random_data(1000, 3, 0), 3).unwrap());
+        for partition_type in &[PartitionType::Nearest, PartitionType::First] {
+            let mut builder = random_tree_builder();
+            builder.partition_type = *partition_type;
+            let tree = builder.build(Arc::clone(&point_cloud)).unwrap();
+            let reader = tree.reader();
+
+            for point in random_data(10, 3, 1).chunks(3).map(|p| p.to_vec()) {
+                let (knn, stats) = reader.knn_with_stats(&&point[..], 10).unwrap();
+                let explanation = reader.knn_explain(&&point[..], 10).unwrap();
+                assert_eq!(explanation.knn, knn);
+                assert_eq!(explanation.stats, stats);
+                assert_eq!(explanation.visited.len(), stats.nodes_visited);
+                assert_eq!(explanation.visited[0].address, reader.root_address());
+                assert!(explanation
+                    .skipped
+                    .iter()
+                    .all(|s| s.min_dist >= s.bound && s.bound >= knn[9].1));
+                assert_eq!(explanation.final_heap.knn.len(), 10);
+                assert_approx_eq!(explanation.final_heap.max_dist, knn[9].1);
+                let json = serde_json::to_string(&explanation).unwrap();
+                let decoded: KnnExplanation = serde_json::from_str(&json).unwrap();
+                assert_eq!(decoded, explanation);
+
+                let (path, stats) = reader.path_with_stats(&&point[..]).unwrap();
+                let explanation = reader.path_explain(&&point[..]).unwrap();
+                assert_eq!(explanation.path, path);
+                assert_eq!(explanation.stats, stats);
+                assert_eq!(explanation.visited.len(), stats.nodes_visited);
+                assert!(explanation
+                    .skipped
+                    .iter()
+                    .all(|s| s.dist_to_center >= s.bound));
+                assert!(explanation
+                    .skipped
+                    .iter()
+                    .all(|s| path.iter().all(|(na, _)| *na != s.address)));
+            }
+        }
+    }
+
+    #[test]
+    fn knn_iter_matches_brute_force() {
+        let (point_cloud, tree) = build_random_tree();
+        let reader = tree.reader();
+
+        for point in random_data(10, 3, 1).chunks(3).map(|p| p.to_vec()) {
+            let brute_knn = point_cloud.brute_knn(&&point[..], 30).unwrap();
+            let mut nbrs = reader.knn_iter(point.clone()).unwrap();
+            let first_page: Vec<(usize, f32)> =
+                nbrs.by_ref().take(10).map(|r| r.unwrap()).collect();
+            let second_page: Vec<(usize, f32)> =
+                nbrs.by_ref().take(20).map(|r| r.unwrap()).collect();
+            for (t, b) in first_page.iter().chain(&second_page).zip(&brute_knn) {
+                assert_approx_eq!(t.1, b.1);
+            }
+
+            let rest: Vec<(usize, f32)> = nbrs.map(|r| r.unwrap()).collect();
+            let mut all: Vec<usize> = first_page
+                .iter()
+                .chain(&second_page)
+                .chain(&rest)
+                .map(|(pi, _)| *pi)
+                .collect();
+            assert!(rest.windows(2).all(|w| w[0].1 <= w[1].1));
+            all.sort_unstable();
+            assert_eq!(all, (0..1000).collect::<Vec<usize>>());
+        }
+    }
+
+    #[test]
+    fn filtered_knn_matches_brute_force() {
+        let labels: Vec<i64> = (0..1000).map(|i| i % 3).collect();
+        let point_cloud = Arc::new(DefaultLabeledCloud::<L2>::new_simple(
+            random_data(1000, 3, 0),
+            3,
+            labels,
+        ));
+        let mut tree = random_tree_builder()
+            .build(Arc::clone(&point_cloud))
+            .unwrap();
+        tree.generate_summaries();
+        let reader = tree.reader();
+        let label_filter = LabelFilter::new(point_cloud.as_ref(), &2);
+
+        // With the summaries some nodes only cover points with other labels
+        let mut pruned_nodes = 0;
+        for (_, layer) in reader.layers() {
+            layer.for_each_node(|_, n| {
+                if !label_filter.accepts_node(n) {
+                    pruned_nodes += 1;
+                }
+            });
+        }
+        assert!(pruned_nodes > 0);
+
+        for point in random_data(10, 3, 1).chunks(3).map(|p| p.to_vec()) {
+            let brute_knn = point_cloud.brute_knn(&&point[..], 1000).unwrap();
+
+            let even = |pi: usize| pi % 2 == 0;
+            let knn = reader.filtered_knn(&&point[..], 20, &even).unwrap();
+            let brute_even: Vec<&(usize, f32)> =
+                brute_knn.iter().filter(|(pi, _)| even(*pi)).collect();
+            assert_eq!(knn.len(), 20);
+            for (t, b) in knn.iter().zip(&brute_even) {
+                assert!(even(t.0));
+                assert_approx_eq!(t.1, b.1);
+            }
+
+            let knn = reader.filtered_knn(&&point[..], 20, &label_filter).unwrap();
+            let brute_labeled: Vec<&(usize, f32)> =
+                brute_knn.iter().filter(|(pi, _)| pi % 3 == 2).collect();
+            assert_eq!(knn.len(), 20);
+            for (t, b) in knn.iter().zip(&brute_labeled) {
+                assert_eq!(t.0 % 3, 2);
+                assert_approx_eq!(t.1, b.1);
+            }
+
+            let all_labeled: Vec<(usize, f32)> = reader
+                .filtered_knn_iter(point.clone(), LabelFilter::new(point_cloud.as_ref(), &2))
+                .unwrap()
+                .map(|r| r.unwrap())
+                .collect();
+            assert_eq!(all_labeled.len(), brute_labeled.len());
+        }
+    }
 }
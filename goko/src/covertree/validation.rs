@@ -0,0 +1,349 @@
+//! # Tree Validation
+//!
+//! Walks a whole tree and checks the invariants the builder is supposed to guarantee. This is for checking trees
+//! after they've been loaded from disk, or built with custom parameters or point clouds, it's not fast.
+//!
+//! The checks are:
+//! * Nesting: each routing node has a nested child with its own center, and all children are on the same, lower layer.
+//! * Covering: every point covered by a node is within the node's radius and within `b^i` of its center.
+//! * Separation: the centers of a routing node's children and its singletons are at least `b^(i-1)` apart.
+//! * Coverage counts: each node's `coverage_count` is the number of points under it.
+//! * Addresses: every child exists, records the node that lists it as its parent and is reachable from the root.
+//! * Points: every point in the point cloud is a singleton or the center of a leaf exactly once.
+
+use super::CoverTreeReader;
+use crate::*;
+use errors::GokoResult;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Relative slack for the distance comparisons, the distances are recomputed so they may be off by a few ulps.
+const TOLERANCE: f32 = 1.0e-5;
+
+/// A single broken invariant, found by [`CoverTreeReader::validate`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum TreeViolation {
+    /// A node lists a child that isn't in the tree.
+    MissingNode {
+        /// The node that lists the child
+        parent: NodeAddress,
+        /// The address that has no node
+        child: NodeAddress,
+    },
+    /// A node is listed as a child, but records a different parent.
+    ParentMismatch {
+        /// The child node
+        node: NodeAddress,
+        /// The parent the node records
+        recorded: Option<NodeAddress>,
+        /// The node that actually lists it, `None` for the root
+        expected: Option<NodeAddress>,
+    },
+    /// A node that can't be reached from the root.
+    Unreachable {
+        /// The orphaned node
+        node: NodeAddress,
+    },
+    /// A routing node without a child that shares its center.
+    MissingNestedChild {
+        /// The routing node
+        node: NodeAddress,
+    },
+    /// A child that isn't on the same layer as its siblings, or isn't below its parent.
+    ChildScale {
+        /// The parent node
+        node: NodeAddress,
+        /// The misplaced child
+        child: NodeAddress,
+    },
+    /// A covered point further from the center than the node's radius or scale allows. Only the furthest point is reported.
+    NotCovered {
+        /// The covering node
+        node: NodeAddress,
+        /// The furthest point
+        point_index: usize,
+        /// The distance from the node's center to the point
+        distance: f32,
+        /// The smaller of the node's radius and its scale
+        bound: f32,
+    },
+    /// Two of a node's children or singletons that are closer than the children's scale.
+    NotSeparated {
+        /// The parent node
+        node: NodeAddress,
+        /// The first center
+        a: usize,
+        /// The second center
+        b: usize,
+        /// The distance between the centers
+        distance: f32,
+        /// The scale of the children
+        bound: f32,
+    },
+    /// The node's recorded coverage count doesn't match the number of points under it.
+    CoverageCount {
+        /// The node
+        node: NodeAddress,
+        /// The node's `coverage_count`
+        recorded: usize,
+        /// The number of points under the node
+        actual: usize,
+    },
+    /// A point of the point cloud that isn't in the tree. Points dropped by a `ValidationPolicy` are reported here.
+    PointMissing {
+        /// The missing point
+        point_index: usize,
+    },
+    /// A point that is in the tree more than once.
+    PointDuplicated {
+        /// The duplicated point
+        point_index: usize,
+        /// The number of times it's a singleton or the center of a leaf
+        count: usize,
+    },
+}
+
+/// The parts of a node the checks need, so that we don't hold the layer's read guard while computing distances.
+struct NodeView {
+    address: NodeAddress,
+    radius: f32,
+    coverage_count: usize,
+    children: Option<Vec<NodeAddress>>,
+    singletons: Vec<usize>,
+}
+
+impl<D: PointCloud> CoverTreeReader<D> {
+    /// Checks every invariant of the tree, returning the violations it finds. An empty list means the tree is valid.
+    /// See the [module docs](crate::covertree::validation) for what is checked.
+    ///
+    /// This computes the distance from every node to every point it covers, so it's expensive on large trees.
+    pub fn validate(&self) -> GokoResult<Vec<TreeViolation>> {
+        let point_cloud = self.point_cloud();
+        let mut violations = Vec::new();
+        // The points under each node we've checked but whose parent we haven't reached yet.
+        let mut covered: HashMap<NodeAddress, Vec<usize>> = HashMap::new();
+        let mut appearances: HashMap<usize, usize> = HashMap::new();
+
+        // Bottom up, so that the children are all done before their parents.
+        for (_si, layer) in self.layers().rev() {
+            let nodes: Vec<NodeView> = layer.map_nodes(|_pi, n| NodeView {
+                address: n.address(),
+                radius: n.radius(),
+                coverage_count: n.coverage_count(),
+                children: n.children().map(|c| c.to_vec()),
+                singletons: n.singletons().to_vec(),
+            });
+            for node in nodes {
+                let address = node.address;
+                let mut points = node.singletons.clone();
+                for pi in &node.singletons {
+                    *appearances.entry(*pi).or_insert(0) += 1;
+                }
+                match &node.children {
+                    None => {
+                        points.push(address.point_index());
+                        *appearances.entry(address.point_index()).or_insert(0) += 1;
+                    }
+                    Some(children) => {
+                        self.check_children(
+                            address,
+                            children,
+                            &mut covered,
+                            &mut points,
+                            &mut violations,
+                        );
+                        let child_scale_index = children[0].scale_index();
+                        let mut centers: Vec<usize> =
+                            children.iter().map(|c| c.point_index()).collect();
+                        centers.extend(&node.singletons);
+                        violations.extend(self.check_separation(
+                            address,
+                            &centers,
+                            self.scale(child_scale_index),
+                        )?);
+                    }
+                }
+
+                let dists = point_cloud.distances_to_point_index(address.point_index(), &points)?;
+                let furthest = points
+                    .iter()
+                    .zip(&dists)
+                    .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+                if let Some((point_index, distance)) = furthest {
+                    // A leaf without singletons has a radius of -inf
+                    let bound = node.radius.max(0.0).min(self.scale(address.scale_index()));
+                    if distance.is_nan() || *distance > bound * (1.0 + TOLERANCE) {
+                        violations.push(TreeViolation::NotCovered {
+                            node: address,
+                            point_index: *point_index,
+                            distance: *distance,
+                            bound,
+                        });
+                    }
+                }
+
+                if node.coverage_count != points.len() {
+                    violations.push(TreeViolation::CoverageCount {
+                        node: address,
+                        recorded: node.coverage_count,
+                        actual: points.len(),
+                    });
+                }
+                covered.insert(address, points);
+            }
+        }
+
+        let root_address = self.root_address();
+        if let Some(Some(recorded)) = self.get_node_and(root_address, |n| n.parent_address()) {
+            violations.push(TreeViolation::ParentMismatch {
+                node: root_address,
+                recorded: Some(recorded),
+                expected: None,
+            });
+        }
+        let mut orphans: Vec<NodeAddress> = covered
+            .keys()
+            .filter(|na| **na != root_address)
+            .copied()
+            .collect();
+        orphans.sort();
+        violations.extend(
+            orphans
+                .into_iter()
+                .map(|node| TreeViolation::Unreachable { node }),
+        );
+
+        for point_index in point_cloud.reference_indexes() {
+            match appearances.get(&point_index) {
+                None => violations.push(TreeViolation::PointMissing { point_index }),
+                Some(count) if *count > 1 => violations.push(TreeViolation::PointDuplicated {
+                    point_index,
+                    count: *count,
+                }),
+                _ => (),
+            }
+        }
+        Ok(violations)
+    }
+
+    /// Checks the nesting and the addresses of the children, and collects the points under them.
+    fn check_children(
+        &self,
+        address: NodeAddress,
+        children: &[NodeAddress],
+        covered: &mut HashMap<NodeAddress, Vec<usize>>,
+        points: &mut Vec<usize>,
+        violations: &mut Vec<TreeViolation>,
+    ) {
+        let child_scale_index = children[0].scale_index();
+        if !children.contains(&(child_scale_index, address.point_index()).into()) {
+            violations.push(TreeViolation::MissingNestedChild { node: address });
+        }
+        for child in children {
+            if child.scale_index() != child_scale_index
+                || child.scale_index() >= address.scale_index()
+            {
+                violations.push(TreeViolation::ChildScale {
+                    node: address,
+                    child: *child,
+                });
+            }
+            match self.get_node_and(*child, |c| c.parent_address()) {
+                None => violations.push(TreeViolation::MissingNode {
+                    parent: address,
+                    child: *child,
+                }),
+                Some(recorded) => {
+                    if recorded != Some(address) {
+                        violations.push(TreeViolation::ParentMismatch {
+                            node: *child,
+                            recorded,
+                            expected: Some(address),
+                        });
+                    }
+                    // A child that is listed twice only has its points counted under the first parent.
+                    if let Some(child_points) = covered.remove(child) {
+                        points.extend(child_points);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Checks that the centers are at least `scale` apart.
+    fn check_separation(
+        &self,
+        address: NodeAddress,
+        centers: &[usize],
+        scale: f32,
+    ) -> GokoResult<Vec<TreeViolation>> {
+        let mut violations = Vec::new();
+        if centers.len() < 2 {
+            return Ok(violations);
+        }
+        let dists = self.point_cloud().condensed_distance_matrix(centers)?;
+        let mut k = 0;
+        for i in 0..centers.len() {
+            for j in (i + 1)..centers.len() {
+                if dists[k] < scale * (1.0 - TOLERANCE) {
+                    violations.push(TreeViolation::NotSeparated {
+                        node: address,
+                        a: centers[i],
+                        b: centers[j],
+                        distance: dists[k],
+                        bound: scale,
+                    });
+                }
+                k += 1;
+            }
+        }
+        Ok(violations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::{build_basic_tree, random_data};
+    use crate::covertree::{CoverTreeBuilder, PartitionType};
+    use std::sync::Arc;
+
+    #[test]
+    fn built_trees_are_valid() {
+        let tree = build_basic_tree();
+        assert_eq!(tree.reader().validate().unwrap(), vec![]);
+
+        let data = random_data(1000, 3, 0);
+        let point_cloud = Arc::new(DefaultCloud::<L2>::new(data, 3).unwrap());
+        for partition_type in &[PartitionType::Nearest, PartitionType::First] {
+            for use_singletons in &[true, false] {
+                let mut builder = CoverTreeBuilder::new();
+                builder
+                    .set_scale_base(1.5)
+                    .set_leaf_cutoff(5)
+                    .set_min_res_index(-5)
+                    .set_use_singletons(*use_singletons)
+                    .set_rng_seed(0);
+                builder.partition_type = *partition_type;
+                let tree = builder.build(Arc::clone(&point_cloud)).unwrap();
+                let violations = tree.reader().validate().unwrap();
+                assert!(violations.is_empty(), "{:?}", violations);
+            }
+        }
+    }
+
+    #[test]
+    fn missing_points_are_reported() {
+        let data = vec![0.5, 1.0, f32::NAN, 3.0];
+        let point_cloud = Arc::new(DefaultCloud::<L2>::new(data, 1).unwrap());
+        let mut builder = CoverTreeBuilder::new();
+        builder
+            .set_validation(pointcloud::validation::ValidationPolicy::Drop)
+            .set_rng_seed(0);
+        let tree = builder.build(point_cloud).unwrap();
+        assert_eq!(
+            tree.reader().validate().unwrap(),
+            vec![TreeViolation::PointMissing { point_index: 2 }]
+        );
+    }
+}
@@ -41,12 +41,31 @@ pub enum GokoError {
     ProtobufError(ProtobufError),
     /// Parsing error when loading a CSV file
     IoError(io::Error),
+    /// Error encoding or decoding the JSON parts of a tree file
+    JsonError(serde_json::Error),
     /// The probability distribution you are trying to sample from is invalid, probably because it was infered from 0 points.
     InvalidProbDistro,
     /// Inserted a nested node into a node that already had a nested child
     DoubleNest,
     /// Inserted a node before you changed it from a leaf node into a normal node. Insert the nested child first.
     InsertBeforeNest,
+    /// The tree file was written by a newer version of goko
+    UnsupportedTreeFileVersion(u32),
+    /// The tree file's header is unreadable or its checksum doesn't match the tree
+    CorruptTreeFile(String),
+    /// The tree file was built against a different point cloud or metric than the one it's being loaded with
+    TreeFileMismatch {
+        /// Which part of the header didn't match
+        field: &'static str,
+        /// The value saved in the file
+        saved: String,
+        /// The value for the point cloud we're loading with
+        found: String,
+    },
+    /// The build was cancelled with its `CancellationToken`
+    BuildCancelled,
+    /// There were no points to cover, for example when validation dropped every point
+    EmptyCoverSet,
 }
 
 impl fmt::Display for GokoError {
@@ -55,6 +74,7 @@ impl fmt::Display for GokoError {
             GokoError::PointCloudError(ref e) => write!(f, "{}", e),
             GokoError::ProtobufError(ref e) => write!(f, "{}", e),
             GokoError::IoError(ref e) => write!(f, "{}", e),
+            GokoError::JsonError(ref e) => write!(f, "{}", e),
             GokoError::IndexNotInTree { .. } => {
                 write!(f, "there was an issue grabbing a name from the known names")
             }
@@ -70,6 +90,25 @@ impl fmt::Display for GokoError {
                 f,
                 "Inserted a node into a node that does not have a nested child"
             ),
+            GokoError::UnsupportedTreeFileVersion(version) => write!(
+                f,
+                "The tree file has version {}, which is newer than this version of goko supports",
+                version
+            ),
+            GokoError::CorruptTreeFile(ref reason) => {
+                write!(f, "The tree file is corrupt: {}", reason)
+            }
+            GokoError::TreeFileMismatch {
+                field,
+                ref saved,
+                ref found,
+            } => write!(
+                f,
+                "The tree file was saved with {} {}, but it's being loaded with {}",
+                field, saved, found
+            ),
+            GokoError::BuildCancelled => write!(f, "The build was cancelled"),
+            GokoError::EmptyCoverSet => write!(f, "There were no points to cover"),
         }
     }
 }
@@ -81,6 +120,7 @@ impl Error for GokoError {
             GokoError::PointCloudError(ref e) => e.description(),
             GokoError::ProtobufError(ref e) => e.description(),
             GokoError::IoError(ref e) => e.description(),
+            GokoError::JsonError(ref e) => e.description(),
             GokoError::IndexNotInTree { .. } => {
                 "there was an issue grabbing a name from the known names"
             }
@@ -93,6 +133,15 @@ impl Error for GokoError {
             GokoError::InvalidProbDistro => {
                 "The probability distribution you are trying to sample from is invalid, probably because it was infered from 0 points."
             }
+            GokoError::UnsupportedTreeFileVersion(..) => {
+                "The tree file is newer than this version of goko supports"
+            }
+            GokoError::CorruptTreeFile(..) => "The tree file is corrupt",
+            GokoError::TreeFileMismatch { .. } => {
+                "The tree file was saved with a different point cloud or metric"
+            }
+            GokoError::BuildCancelled => "The build was cancelled",
+            GokoError::EmptyCoverSet => "There were no points to cover",
         }
     }
 
@@ -101,10 +150,16 @@ impl Error for GokoError {
             GokoError::PointCloudError(ref e) => Some(e),
             GokoError::ProtobufError(ref e) => Some(e),
             GokoError::IoError(ref e) => Some(e),
+            GokoError::JsonError(ref e) => Some(e),
             GokoError::IndexNotInTree { .. } => None,
             GokoError::DoubleNest => None,
             GokoError::InsertBeforeNest => None,
             GokoError::InvalidProbDistro => None,
+            GokoError::UnsupportedTreeFileVersion(..) => None,
+            GokoError::CorruptTreeFile(..) => None,
+            GokoError::TreeFileMismatch { .. } => None,
+            GokoError::BuildCancelled => None,
+            GokoError::EmptyCoverSet => None,
         }
     }
 }
@@ -126,3 +181,9 @@ impl From<io::Error> for GokoError {
         GokoError::IoError(err)
     }
 }
+
+impl From<serde_json::Error> for GokoError {
+    fn from(err: serde_json::Error) -> Self {
+        GokoError::JsonError(err)
+    }
+}
@@ -22,6 +22,7 @@
 #![doc(test(attr(allow(unused_variables), deny(warnings))))]
 #![feature(binary_heap_into_iter_sorted)]
 #![feature(associated_type_defaults)]
+#![feature(associated_type_bounds)]
 
 //! # Goko
 //! This is an lock-free efficient implementation of a covertree for data science. The traditional
@@ -69,7 +70,9 @@ pub use covertree::*;
 
 pub mod query_interface;
 
+pub mod bundle;
 mod tree_file_format;
+pub mod tree_header;
 pub mod utils;
 
 pub mod plugins;
@@ -13,6 +13,7 @@
 use crate::covertree::node::CoverNode;
 use crate::covertree::CoverTreeReader;
 use crate::plugins::*;
+use serde::{Deserialize, Serialize};
 pub use stats_goko::discrete::{Dirichlet, DiscreteData};
 
 /// Simple probability density function for where things go by count
@@ -23,12 +24,14 @@ impl<D: PointCloud> NodePlugin<D> for Dirichlet {}
 /// Stores the log probabilities for each node in the tree.
 ///
 /// This is the probability that when you sample from the tree you end up at a particular node.
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct GokoDirichlet {
     // probability that you'd pass thru this node.
-//pub cond_ln_probs: HashMap<NodeAddress,f64>,
+    //pub cond_ln_probs: HashMap<NodeAddress,f64>,
 }
 
+impl<D: PointCloud> PersistentPlugin<D> for GokoDirichlet {}
+
 /// Parent trait that make this all work. Ideally this should be included in the `TreePlugin` but rust doesn't like it.
 impl<D: PointCloud> GokoPlugin<D> for GokoDirichlet {
     type NodeComponent = Dirichlet;
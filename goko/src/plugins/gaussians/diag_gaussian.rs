@@ -7,6 +7,7 @@
 use super::*;
 use crate::covertree::node::CoverNode;
 use crate::covertree::CoverTreeReader;
+use pointcloud::moments::Moments;
 use rand::prelude::*;
 use rand_distr::StandardNormal;
 use std::f32::consts::PI;
@@ -14,35 +15,14 @@ use std::f32::consts::PI;
 /// Node component, coded in such a way that it can be efficiently, recursively computed.
 #[derive(Debug, Clone, Default)]
 pub struct DiagGaussian {
-    /// First Moment
-    pub moment1: Vec<f32>,
-    /// Second Moment
-    pub moment2: Vec<f32>,
-    /// Cover count, divide the first moment by this to get the mean.
-    pub count: usize,
-}
-
-macro_rules! internal_mean {
-    ( $m1:expr,$c:expr ) => {{
-        $m1.iter().map(|x| x / ($c as f32))
-    }};
-}
-macro_rules! internal_var {
-    ( $m1:expr,$m2:expr,$c:expr ) => {{
-        $m2.iter()
-            .map(|x| x / ($c as f32))
-            .zip($m1.iter().map(|x| x / ($c as f32)))
-            .map(|(x, u)| x - u * u)
-    }};
+    /// The numerically stable moments of the covered points, these merge exactly so the recursive computation is
+    /// the same as the direct one.
+    pub moments: Moments,
 }
 
 impl ContinousDistribution for DiagGaussian {
     fn ln_pdf<T: PointRef>(&self, point: &T) -> Option<f64> {
-        let mean_vars = internal_mean!(self.moment1, self.count).zip(internal_var!(
-            self.moment1,
-            self.moment2,
-            self.count
-        ));
+        let mean_vars = self.mean().into_iter().zip(self.var());
 
         let (exponent, det) = point
             .dense_iter()
@@ -54,29 +34,20 @@ impl ContinousDistribution for DiagGaussian {
     }
 
     fn sample<R: Rng>(&self, rng: &mut R) -> Vec<f32> {
-        let mean_iter = internal_mean!(self.moment1, self.count);
-        let std_dev_iter = internal_var!(self.moment1, self.moment2, self.count).map(|f| f.sqrt());
+        let mean_iter = self.mean().into_iter();
+        let std_dev_iter = self.var().into_iter().map(|f| f.sqrt());
 
         StandardNormal
             .sample_iter(rng)
-            .take(self.moment1.len())
+            .take(self.dim())
             .zip(mean_iter.zip(std_dev_iter))
             .map(|(s, (a, b)): (f32, _)| s * b + a)
             .collect()
     }
 
     fn kl_divergence(&self, other: &DiagGaussian) -> Option<f64> {
-        let mean_vars = internal_mean!(self.moment1, self.count).zip(internal_var!(
-            self.moment1,
-            self.moment2,
-            self.count
-        ));
-
-        let other_mean_vars = internal_mean!(other.moment1, other.count).zip(internal_var!(
-            other.moment1,
-            other.moment2,
-            other.count
-        ));
+        let mean_vars = self.mean().into_iter().zip(self.var());
+        let other_mean_vars = other.mean().into_iter().zip(other.var());
 
         let (trace, mah_dist, ln_det) = mean_vars
             .zip(other_mean_vars)
@@ -90,7 +61,7 @@ impl ContinousDistribution for DiagGaussian {
                 (a + x, b + y, c + z)
             });
 
-        Some(((trace + mah_dist - (self.moment1.len() as f32) + ln_det) / 2.0) as f64)
+        Some(((trace + mah_dist - (self.dim() as f32) + ln_det) / 2.0) as f64)
     }
 }
 
@@ -98,77 +69,57 @@ impl DiagGaussian {
     /// Creates a new empty diagonal gaussian
     pub fn new(dim: usize) -> DiagGaussian {
         DiagGaussian {
-            moment1: vec![0.0; dim],
-            moment2: vec![0.0; dim],
-            count: 0,
+            moments: Moments::new(dim),
         }
     }
 
     /// Dimension for this
     pub fn dim(&self) -> usize {
-        self.moment1.len()
+        self.moments.dim()
     }
 
     /// adds a point to the Diagonal Gaussian
     pub fn add_point<T: PointRef>(&mut self, point: &T) {
-        self.moment1
-            .iter_mut()
-            .zip(point.dense_iter())
-            .for_each(|(m, p)| *m += p);
-        self.moment2
-            .iter_mut()
-            .zip(point.dense_iter())
-            .for_each(|(m, p)| *m += p * p);
-        self.count += 1;
+        self.moments.add(point);
     }
 
     /// removes a point from the Diagonal Gaussian
     pub fn remove_point<T: PointRef>(&mut self, point: &T) {
-        if self.count != 0 {
-            self.moment1
-                .iter_mut()
-                .zip(point.dense_iter())
-                .for_each(|(m, p)| *m -= p);
-            self.moment2
-                .iter_mut()
-                .zip(point.dense_iter())
-                .for_each(|(m, p)| *m -= p * p);
-            self.count += 1;
+        if self.count() != 0 {
+            self.moments.remove(point);
         }
     }
 
     /// Merges two diagonal gaussians together
     pub fn merge(&mut self, other: &DiagGaussian) {
-        self.moment1
-            .iter_mut()
-            .zip(other.moment1.iter())
-            .for_each(|(m, p)| *m += *p);
-        self.moment2
-            .iter_mut()
-            .zip(other.moment2.iter())
-            .for_each(|(m, p)| *m += *p);
-        self.count += other.count;
+        self.moments.merge(&other.moments);
     }
 
-    /// Mean: `moment1/count`
+    /// The mean of the covered points
     pub fn mean(&self) -> Vec<f32> {
-        if self.count > 0 {
-            internal_mean!(self.moment1, self.count).collect()
-        } else {
-            vec![0.0; self.moment1.len()]
-        }
+        self.moments.mean()
     }
-    /// Variance: `moment2/count - (moment1/count)^2`
+    /// The population variance of the covered points
     pub fn var(&self) -> Vec<f32> {
-        if self.count > 0 {
-            internal_var!(self.moment1, self.moment2, self.count).collect()
-        } else {
-            vec![0.0; self.moment1.len()]
-        }
+        self.moments.variance()
     }
-    /// The count used in the above equations
+    /// The number of points covered
     pub fn count(&self) -> usize {
-        self.count
+        self.moments.weight() as usize
+    }
+    /// First moment, the sum of the covered points
+    pub fn moment1(&self) -> Vec<f32> {
+        let count = self.moments.weight() as f32;
+        self.mean().iter().map(|m| m * count).collect()
+    }
+    /// Second moment, the sum of the squares of the covered points
+    pub fn moment2(&self) -> Vec<f32> {
+        let count = self.moments.weight() as f32;
+        self.mean()
+            .iter()
+            .zip(self.var())
+            .map(|(m, v)| (v + m * m) * count)
+            .collect()
     }
 }
 
@@ -199,22 +150,12 @@ impl<D: PointCloud> GokoPlugin<D> for GokoDiagGaussian {
         my_node: &CoverNode<D>,
         my_tree: &CoverTreeReader<D>,
     ) -> Option<Self::NodeComponent> {
-        let moment1 = my_tree
-            .parameters()
-            .point_cloud
-            .moment_1(my_node.singletons())
-            .unwrap();
-        let moment2 = my_tree
+        let moments = my_tree
             .parameters()
             .point_cloud
-            .moment_2(my_node.singletons())
+            .moments(my_node.singletons())
             .unwrap();
-        let count = my_node.singletons_len();
-        let mut my_dg = DiagGaussian {
-            moment1,
-            moment2,
-            count,
-        };
+        let mut my_dg = DiagGaussian { moments };
         // If we're a routing node then grab the childen's values
         if let Some(child_addresses) = my_node.children() {
             if parameters.recursive {
@@ -282,17 +223,21 @@ pub(crate) mod tests {
                         s_moment2 += basic_tree_data[n.center_index() as usize]
                             * basic_tree_data[n.center_index() as usize];
                         let s_count = singles.len() + 1;
+                        let s_mean = s_moment1 / s_count as f32;
+                        let s_var = s_moment2 / s_count as f32 - s_mean * s_mean;
                         println!(
-                            "First moment, expected: {:?}, calculated: {:?}",
-                            s_moment1, p.moment1[0]
+                            "Mean, expected: {:?}, calculated: {:?}",
+                            s_mean,
+                            p.mean()[0]
                         );
-                        assert_approx_eq!(s_moment1, p.moment1[0]);
+                        assert_approx_eq!(s_mean, p.mean()[0]);
                         println!(
-                            "Second moment, expected: {:?}, calculated: {:?}",
-                            s_moment2, p.moment2[0]
+                            "Variance, expected: {:?}, calculated: {:?}",
+                            s_var,
+                            p.var()[0]
                         );
-                        assert_approx_eq!(s_moment2, p.moment2[0]);
-                        assert_eq!(s_count, p.count);
+                        assert_approx_eq!(s_var, p.var()[0]);
+                        assert_eq!(s_count, p.count());
                         println!(
                             "=====</Leaf ({},{})>=====",
                             n.scale_index(),
@@ -318,17 +263,19 @@ pub(crate) mod tests {
         }
 
         reader.get_node_plugin_and::<DiagGaussian, _, _>(reader.root_address(), |p| {
+            let mean = moment1 / count as f32;
+            let var = moment2 / count as f32 - mean * mean;
+            println!("Mean, expected: {:?}, calculated: {:?}", mean, p.mean()[0]);
+            assert_approx_eq!(mean, p.mean()[0]);
             println!(
-                "First moment, expected: {:?}, calculated: {:?}",
-                moment1, p.moment1[0]
-            );
-            assert_approx_eq!(moment1, p.moment1[0]);
-            println!(
-                "Second moment, expected: {:?}, calculated: {:?}",
-                moment2, p.moment2[0]
+                "Variance, expected: {:?}, calculated: {:?}",
+                var,
+                p.var()[0]
             );
-            assert_approx_eq!(moment2, p.moment2[0]);
-            assert_eq!(count, p.count);
+            assert_approx_eq!(var, p.var()[0]);
+            assert_eq!(count, p.count());
+            assert_approx_eq!(moment1, p.moment1()[0], 1e-4);
+            assert_approx_eq!(moment2, p.moment2()[0], 1e-4);
         });
     }
 
@@ -347,7 +294,7 @@ pub(crate) mod tests {
                     p.var().iter().for_each(|f| {
                         assert!(f.is_finite(), "Variance: {}, at address {:?}", f, addr)
                     });
-                    p.count
+                    p.count()
                 })
                 .unwrap();
             ct_reader.get_node_and(addr, |n| {
@@ -4,6 +4,7 @@ use super::*;
 use crate::covertree::node::CoverNode;
 use crate::covertree::CoverTreeReader;
 //use pointcloud::*;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::sync::Arc;
 
 /// Wrapper around the summary found in the point cloud
@@ -21,12 +22,28 @@ impl<T: Summary + Clone> Clone for NodeLabelSummary<T> {
     }
 }
 
+impl<T: Summary + Clone> Serialize for NodeLabelSummary<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.summary.as_ref().serialize(serializer)
+    }
+}
+
+impl<'de, T: Summary + Clone> Deserialize<'de> for NodeLabelSummary<T> {
+    fn deserialize<De: Deserializer<'de>>(deserializer: De) -> Result<Self, De::Error> {
+        Ok(NodeLabelSummary {
+            summary: Arc::new(SummaryCounter::deserialize(deserializer)?),
+        })
+    }
+}
+
 impl<D: PointCloud> NodePlugin<D> for NodeLabelSummary<D::LabelSummary> {}
 
 /// Plug in that allows for summaries of labels to be attached to
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct LabelSummaryPlugin {}
 
+impl<D: PointCloud> PersistentPlugin<D> for LabelSummaryPlugin {}
+
 impl<D: PointCloud> GokoPlugin<D> for LabelSummaryPlugin {
     type NodeComponent = NodeLabelSummary<D::LabelSummary>;
     fn node_component(
@@ -75,12 +92,28 @@ impl<T: Summary + Clone> Clone for NodeMetaSummary<T> {
     }
 }
 
+impl<T: Summary + Clone> Serialize for NodeMetaSummary<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.summary.as_ref().serialize(serializer)
+    }
+}
+
+impl<'de, T: Summary + Clone> Deserialize<'de> for NodeMetaSummary<T> {
+    fn deserialize<De: Deserializer<'de>>(deserializer: De) -> Result<Self, De::Error> {
+        Ok(NodeMetaSummary {
+            summary: Arc::new(SummaryCounter::deserialize(deserializer)?),
+        })
+    }
+}
+
 impl<D: PointCloud> NodePlugin<D> for NodeMetaSummary<D::MetaSummary> {}
 
 /// Plug in that allows for summaries of Metas to be attached to
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct MetaSummaryPlugin {}
 
+impl<D: PointCloud> PersistentPlugin<D> for MetaSummaryPlugin {}
+
 impl<D: PointCloud> GokoPlugin<D> for MetaSummaryPlugin {
     type NodeComponent = NodeMetaSummary<D::MetaSummary>;
     fn node_component(
@@ -7,11 +7,17 @@
 //! attached to the tree. It can access the `TreePlugin` component, and the tree. These are created recursively, so you can access the
 //! plugin for the child nodes.
 //!
+//! Plugins that implement `PersistentPlugin` as well are saved with the tree by `utils::save_tree`. Attach them with
+//! `CoverTreeWriter::add_persistent_plugin`, which restores the saved node components of a loaded tree instead of
+//! recomputing them.
+//!
 //! None of this is parallelized. We need to move to Tokio to take advantage of the async computation there to || it.
 
 use crate::covertree::node::CoverNode;
 use crate::covertree::CoverTreeReader;
 use crate::*;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
 use type_map::concurrent::TypeMap;
 
@@ -37,6 +43,26 @@ pub trait GokoPlugin<D: PointCloud>: Send + Sync + Debug + Clone + 'static {
     ) -> Option<Self::NodeComponent>;
 }
 
+/// A plugin that can be saved with the tree. The plugin itself holds the tree level parameters, and is saved along
+/// with every node component. A saved section is only restored if the plugin it's restored for serializes to the
+/// same parameters.
+pub trait PersistentPlugin<D: PointCloud>:
+    GokoPlugin<D, NodeComponent: Serialize + DeserializeOwned> + Serialize + DeserializeOwned
+{
+    /// The name of the plugin's section in a tree file. Defaults to the type name, override this if the type is
+    /// renamed or moved and you still want to read old files.
+    fn section_name() -> String {
+        std::any::type_name::<Self>().to_string()
+    }
+}
+
+/// The saved form of a persistent plugin, the parameters and the component of every node that has one.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct PluginSection<P, C> {
+    pub(crate) parameters: P,
+    pub(crate) components: Vec<(NodeAddress, C)>,
+}
+
 pub(crate) type NodePluginSet = TypeMap;
 pub(crate) type TreePluginSet = TypeMap;
 
@@ -44,6 +70,11 @@ pub(crate) type TreePluginSet = TypeMap;
 pub(crate) mod tests {
     use super::*;
     use crate::covertree::tests::build_basic_tree;
+    use crate::plugins::discrete::prelude::*;
+    use crate::plugins::labels::{LabelSummaryPlugin, NodeLabelSummary};
+    use crate::utils::{load_tree, save_tree};
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
 
     #[derive(Debug, Clone)]
     struct DumbNode1 {
@@ -111,4 +142,56 @@ pub(crate) mod tests {
             });
         }
     }
+
+    type LabelComponent = NodeLabelSummary<<DefaultLabeledCloud<L2> as PointCloud>::LabelSummary>;
+
+    fn node_components<D: PointCloud, T: Serialize + Send + Sync + 'static>(
+        tree: &CoverTreeWriter<D>,
+    ) -> BTreeMap<NodeAddress, serde_json::Value> {
+        let mut components = BTreeMap::new();
+        for (_si, layer) in tree.reader().layers() {
+            layer.for_each_node(|_pi, n| {
+                if let Some(c) = n.get_plugin_and::<T, _, _>(|c| serde_json::to_value(c).unwrap()) {
+                    components.insert(n.address(), c);
+                }
+            });
+        }
+        components
+    }
+
+    #[test]
+    fn persistent_plugins_round_trip() {
+        let mut tree = build_basic_tree();
+        tree.generate_summaries();
+        tree.add_persistent_plugin(GokoDirichlet {});
+        let point_cloud = Arc::clone(&tree.parameters.point_cloud);
+        let summaries = node_components::<_, LabelComponent>(&tree);
+        let dirichlets = node_components::<_, Dirichlet>(&tree);
+        assert_eq!(summaries.len(), tree.reader().node_count());
+
+        let dir = tempdir::TempDir::new("persistent_plugins").unwrap();
+        let path = dir.path().join("plugins.tree");
+        save_tree(&path, &tree).unwrap();
+
+        // The summaries come back on load, the Dirichlet waits until it's added
+        let mut loaded = load_tree(&path, Arc::clone(&point_cloud)).unwrap();
+        assert_eq!(node_components::<_, LabelComponent>(&loaded), summaries);
+        assert!(loaded
+            .reader()
+            .get_plugin_and::<LabelSummaryPlugin, _, _>(|_| ())
+            .is_some());
+        assert!(node_components::<_, Dirichlet>(&loaded).is_empty());
+        assert_eq!(loaded.saved_plugins.len(), 1);
+
+        // Sections that weren't restored are saved again as they were
+        let resaved_path = dir.path().join("resaved.tree");
+        save_tree(&resaved_path, &loaded).unwrap();
+        let mut resaved = load_tree(&resaved_path, Arc::clone(&point_cloud)).unwrap();
+
+        for tree in [&mut loaded, &mut resaved] {
+            tree.add_persistent_plugin(GokoDirichlet {});
+            assert!(tree.saved_plugins.is_empty());
+            assert_eq!(node_components::<_, Dirichlet>(tree), dirichlets);
+        }
+    }
 }
@@ -0,0 +1,352 @@
+//! # Tree File Header
+//!
+//! Saved trees start with a small versioned header, followed by the protobuf encoded tree. The header records
+//! what the tree was built against, so that loading it with the wrong point cloud or metric is an error instead
+//! of a tree that silently gives wrong answers. The layout is:
+//!
+//! * The 8 magic bytes `GOKOTREE`
+//! * The format version, a little endian `u32`
+//! * The length of the header, a little endian `u64`
+//! * The header, as JSON
+//! * The tree's protobuf
+//! * The sections of the persistent plugins, in the order and with the lengths listed in the header
+//!
+//! Files without the magic bytes are from before the header existed, and are loaded without any checks.
+
+use crate::covertree::{CoverTreeParameters, PartitionType};
+use crate::errors::{GokoError, GokoResult};
+use fxhash::FxHasher64;
+use pointcloud::*;
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+use std::hash::Hasher;
+
+/// The bytes every tree file with a header starts with.
+pub const TREE_FILE_MAGIC: &[u8; 8] = b"GOKOTREE";
+/// The current version of the format. Files with a larger version are rejected.
+pub const TREE_FILE_VERSION: u32 = 1;
+/// The number of points hashed into the fingerprint.
+const FINGERPRINT_SAMPLES: usize = 64;
+
+/// Identifies a point cloud without storing it. Two clouds with the same fingerprint have the same number of
+/// points, the same dimension and the same values at a spread of sampled points.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PointCloudFingerprint {
+    /// The number of points
+    pub count: usize,
+    /// The dimension of the points
+    pub dim: usize,
+    /// A hash of evenly spaced points and their indexes
+    pub sample_hash: u64,
+}
+
+impl PointCloudFingerprint {
+    /// Fingerprints the point cloud, this reads at most 64 points.
+    pub fn new<D: PointCloud>(point_cloud: &D) -> GokoResult<PointCloudFingerprint> {
+        let indexes = point_cloud.reference_indexes();
+        let step = ((indexes.len() + FINGERPRINT_SAMPLES - 1) / FINGERPRINT_SAMPLES).max(1);
+        let mut hasher = FxHasher64::default();
+        for i in indexes.iter().step_by(step) {
+            hasher.write_u64(*i as u64);
+            for x in point_cloud.point(*i)?.dense_iter() {
+                hasher.write_u32(x.to_bits());
+            }
+        }
+        Ok(PointCloudFingerprint {
+            count: point_cloud.len(),
+            dim: point_cloud.dim(),
+            sample_hash: hasher.finish(),
+        })
+    }
+}
+
+/// The parameters the tree was built with. Unlike the protobuf, this includes the seed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BuildParameters {
+    /// See [`CoverTreeParameters::scale_base`]
+    pub scale_base: f32,
+    /// See [`CoverTreeParameters::leaf_cutoff`]
+    pub leaf_cutoff: usize,
+    /// See [`CoverTreeParameters::min_res_index`]
+    pub min_res_index: i32,
+    /// See [`CoverTreeParameters::use_singletons`]
+    pub use_singletons: bool,
+    /// See [`CoverTreeParameters::partition_type`]
+    pub partition_type: PartitionType,
+    /// See [`CoverTreeParameters::rng_seed`]
+    pub rng_seed: Option<u64>,
+}
+
+impl<D: PointCloud> From<&CoverTreeParameters<D>> for BuildParameters {
+    fn from(parameters: &CoverTreeParameters<D>) -> BuildParameters {
+        BuildParameters {
+            scale_base: parameters.scale_base,
+            leaf_cutoff: parameters.leaf_cutoff,
+            min_res_index: parameters.min_res_index,
+            use_singletons: parameters.use_singletons,
+            partition_type: parameters.partition_type,
+            rng_seed: parameters.rng_seed,
+        }
+    }
+}
+
+/// The header of a saved tree.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TreeFileHeader {
+    /// The format version the file was written with
+    pub version: u32,
+    /// The name of the metric, see [`Metric::NAME`]
+    pub metric: String,
+    /// The point cloud the tree was built on
+    pub fingerprint: PointCloudFingerprint,
+    /// The parameters the tree was built with
+    pub parameters: BuildParameters,
+    /// A hash of the protobuf and the plugin sections that follow the header
+    pub checksum: u64,
+    /// The names and lengths of the plugin sections after the protobuf
+    pub sections: Vec<(String, u64)>,
+}
+
+/// The parts of a tree file after the header.
+pub struct TreeFileBody<'a> {
+    /// The tree's protobuf
+    pub proto: &'a [u8],
+    /// The plugin sections, by name
+    pub sections: Vec<(String, &'a [u8])>,
+}
+
+/// The hash of the parts, in order.
+pub(crate) fn checksum<'a>(parts: impl Iterator<Item = &'a [u8]>) -> u64 {
+    let mut hasher = FxHasher64::default();
+    for part in parts {
+        hasher.write(part);
+    }
+    hasher.finish()
+}
+
+fn metric_name<D: PointCloud>() -> String {
+    <D::Metric as Metric<D::Point>>::NAME.to_string()
+}
+
+impl TreeFileHeader {
+    /// The header for a tree with these parameters, whose encoded protobuf is `proto`, followed by the plugin
+    /// `sections`.
+    pub fn new<D: PointCloud>(
+        parameters: &CoverTreeParameters<D>,
+        proto: &[u8],
+        sections: &[(String, Vec<u8>)],
+    ) -> GokoResult<TreeFileHeader> {
+        Ok(TreeFileHeader {
+            version: TREE_FILE_VERSION,
+            metric: metric_name::<D>(),
+            fingerprint: PointCloudFingerprint::new(parameters.point_cloud.as_ref())?,
+            parameters: parameters.into(),
+            checksum: checksum(
+                std::iter::once(proto).chain(sections.iter().map(|(_, s)| s.as_slice())),
+            ),
+            sections: sections
+                .iter()
+                .map(|(name, s)| (name.clone(), s.len() as u64))
+                .collect(),
+        })
+    }
+
+    /// Writes the header followed by the protobuf and the plugin sections, which have to be the ones the header was
+    /// made with.
+    pub fn encode(&self, proto: &[u8], sections: &[(String, Vec<u8>)]) -> Vec<u8> {
+        let header = serde_json::to_vec(self).unwrap();
+        let sections_len: usize = sections.iter().map(|(_, s)| s.len()).sum();
+        let mut bytes = Vec::with_capacity(20 + header.len() + proto.len() + sections_len);
+        bytes.extend_from_slice(TREE_FILE_MAGIC);
+        bytes.extend_from_slice(&self.version.to_le_bytes());
+        bytes.extend_from_slice(&(header.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(&header);
+        bytes.extend_from_slice(proto);
+        for (_, section) in sections {
+            bytes.extend_from_slice(section);
+        }
+        bytes
+    }
+
+    /// Splits a file into its header and body. Files from before the header existed have no header, and are all
+    /// protobuf.
+    pub fn decode(bytes: &[u8]) -> GokoResult<(Option<TreeFileHeader>, TreeFileBody<'_>)> {
+        if bytes.len() < 20 || &bytes[..8] != TREE_FILE_MAGIC {
+            let body = TreeFileBody {
+                proto: bytes,
+                sections: Vec::new(),
+            };
+            return Ok((None, body));
+        }
+        let version = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+        if version > TREE_FILE_VERSION {
+            return Err(GokoError::UnsupportedTreeFileVersion(version));
+        }
+        let header_len = u64::from_le_bytes(bytes[12..20].try_into().unwrap()) as usize;
+        if bytes.len() - 20 < header_len {
+            return Err(GokoError::CorruptTreeFile(
+                "the header is truncated".to_string(),
+            ));
+        }
+        let header: TreeFileHeader = serde_json::from_slice(&bytes[20..20 + header_len])
+            .map_err(|e| GokoError::CorruptTreeFile(e.to_string()))?;
+        let rest = &bytes[20 + header_len..];
+        let sections_len = header
+            .sections
+            .iter()
+            .fold(0u64, |total, (_, len)| total.saturating_add(*len));
+        if (rest.len() as u64) < sections_len {
+            return Err(GokoError::CorruptTreeFile(
+                "the plugin sections are truncated".to_string(),
+            ));
+        }
+        let (proto, mut remaining) = rest.split_at(rest.len() - sections_len as usize);
+        let mut sections = Vec::with_capacity(header.sections.len());
+        for (name, len) in &header.sections {
+            let (section, tail) = remaining.split_at(*len as usize);
+            sections.push((name.clone(), section));
+            remaining = tail;
+        }
+        let found = checksum(std::iter::once(proto).chain(sections.iter().map(|(_, s)| *s)));
+        if found != header.checksum {
+            return Err(GokoError::CorruptTreeFile(
+                "the checksum doesn't match".to_string(),
+            ));
+        }
+        Ok((Some(header), TreeFileBody { proto, sections }))
+    }
+
+    /// Checks that the tree was built with this point cloud and its metric.
+    pub fn check<D: PointCloud>(&self, point_cloud: &D) -> GokoResult<()> {
+        let metric = metric_name::<D>();
+        if self.metric != metric {
+            return Err(GokoError::TreeFileMismatch {
+                field: "metric",
+                saved: self.metric.clone(),
+                found: metric,
+            });
+        }
+        let fingerprint = PointCloudFingerprint::new(point_cloud)?;
+        if self.fingerprint.count != fingerprint.count {
+            return Err(GokoError::TreeFileMismatch {
+                field: "point count",
+                saved: self.fingerprint.count.to_string(),
+                found: fingerprint.count.to_string(),
+            });
+        }
+        if self.fingerprint.dim != fingerprint.dim {
+            return Err(GokoError::TreeFileMismatch {
+                field: "dimension",
+                saved: self.fingerprint.dim.to_string(),
+                found: fingerprint.dim.to_string(),
+            });
+        }
+        if self.fingerprint.sample_hash != fingerprint.sample_hash {
+            return Err(GokoError::TreeFileMismatch {
+                field: "point sample hash",
+                saved: format!("{:#x}", self.fingerprint.sample_hash),
+                found: format!("{:#x}", fingerprint.sample_hash),
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks that the parameters in the header are the ones the tree was loaded with. The protobuf has its own
+    /// copy of them, so a header that doesn't match its tree is caught here.
+    pub fn check_parameters<D: PointCloud>(
+        &self,
+        parameters: &CoverTreeParameters<D>,
+    ) -> GokoResult<()> {
+        let loaded = BuildParameters {
+            rng_seed: self.parameters.rng_seed,
+            ..parameters.into()
+        };
+        if self.parameters != loaded {
+            return Err(GokoError::CorruptTreeFile(format!(
+                "the header has the parameters {:?}, but the tree has {:?}",
+                self.parameters, loaded
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::build_basic_tree;
+    use crate::utils::{load_tree, save_tree};
+    use crate::CoverTreeWriter;
+    use protobuf::Message;
+    use std::sync::Arc;
+
+    #[test]
+    fn header_round_trip() {
+        let tree = build_basic_tree();
+        let point_cloud = Arc::clone(&tree.parameters.point_cloud);
+        let dir = tempdir::TempDir::new("tree_header").unwrap();
+        let path = dir.path().join("basic.tree");
+        save_tree(&path, &tree).unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        let (header, _body) = TreeFileHeader::decode(&bytes).unwrap();
+        let header = header.unwrap();
+        assert_eq!(header.version, TREE_FILE_VERSION);
+        assert_eq!(header.fingerprint.count, 5);
+        assert_eq!(header.parameters.rng_seed, Some(0));
+        assert_eq!(header.metric, "l2");
+
+        let loaded = load_tree(&path, Arc::clone(&point_cloud)).unwrap();
+        assert_eq!(loaded.parameters.rng_seed, Some(0));
+        assert_eq!(loaded.reader().node_count(), tree.reader().node_count());
+
+        // A different point cloud of the same size is caught by the sample hash
+        let other = Arc::new(DefaultLabeledCloud::<L2>::new_simple(
+            vec![0.5, 0.49, 0.48, -0.49, 0.0],
+            1,
+            vec![0, 0, 0, 1, 1],
+        ));
+        match load_tree(&path, other) {
+            Err(GokoError::TreeFileMismatch { field, .. }) => {
+                assert_eq!(field, "point sample hash")
+            }
+            _ => panic!("Loaded a tree against the wrong point cloud"),
+        }
+
+        // The header's parameters have to match the ones in the protobuf
+        let (header, body) = TreeFileHeader::decode(&bytes).unwrap();
+        let mut spliced = header.unwrap();
+        assert!(spliced.sections.is_empty());
+        spliced.parameters.leaf_cutoff += 1;
+        let spliced_path = dir.path().join("spliced.tree");
+        std::fs::write(&spliced_path, spliced.encode(body.proto, &[])).unwrap();
+        assert!(matches!(
+            load_tree(&spliced_path, Arc::clone(&point_cloud)),
+            Err(GokoError::CorruptTreeFile(_))
+        ));
+        assert!(matches!(
+            crate::utils::load_tree_lazy(&spliced_path, Arc::clone(&point_cloud)),
+            Err(GokoError::CorruptTreeFile(_))
+        ));
+
+        let mut corrupted = bytes.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            TreeFileHeader::decode(&corrupted),
+            Err(GokoError::CorruptTreeFile(_))
+        ));
+    }
+
+    #[test]
+    fn headerless_files_load() {
+        let tree = build_basic_tree();
+        let point_cloud = Arc::clone(&tree.parameters.point_cloud);
+        let dir = tempdir::TempDir::new("tree_header").unwrap();
+        let path = dir.path().join("legacy.tree");
+        std::fs::write(&path, tree.save().write_to_bytes().unwrap()).unwrap();
+
+        let loaded: CoverTreeWriter<_> = load_tree(&path, point_cloud).unwrap();
+        assert_eq!(loaded.parameters.rng_seed, None);
+        assert_eq!(loaded.reader().node_count(), tree.reader().node_count());
+    }
+}
@@ -21,15 +21,21 @@
 
 use crate::errors::{GokoError, GokoResult};
 use crate::tree_file_format::*;
-use protobuf::{CodedInputStream, CodedOutputStream, Message};
-use std::fs::File;
-use std::fs::{read_to_string, remove_file, OpenOptions};
+use crate::tree_header::TreeFileHeader;
+use protobuf::rt::{string_size, value_size};
+use protobuf::wire_format::WireType;
+use protobuf::{CodedOutputStream, Message};
+use std::fs::{read_to_string, remove_file, File};
+use std::io::{BufWriter, Write};
 use std::path::Path;
 use std::sync::Arc;
 use yaml_rust::YamlLoader;
 
 use crate::builders::CoverTreeBuilder;
 
+use crate::bundle::{decode_bundle, encode_bundle, BundleCloud};
+use crate::lazy::LazyCoverTree;
+use crate::mapped::{encode_mapped_tree, open_mapped_tree};
 use crate::CoverTreeWriter;
 
 use pointcloud::loaders::{labeled_ram_from_yaml, ram_from_yaml};
@@ -115,7 +121,8 @@ pub fn cover_tree_from_yaml<P: AsRef<Path>>(
     Ok(builder.build(Arc::new(point_cloud))?)
 }
 
-/// Helper function that handles the file I/O and protobuf decoding for you.
+/// Helper function that handles the file I/O and protobuf decoding for you. Label and metadata summaries that were
+/// saved with the tree are restored, other persistent plugins are restored when they're added.
 pub fn load_tree<P: AsRef<Path>, D: PointCloud>(
     tree_path: P,
     point_cloud: Arc<D>,
@@ -131,18 +138,72 @@ pub fn load_tree<P: AsRef<Path>, D: PointCloud>(
         panic!("{} does not exist\n", tree_path_str);
     }
 
-    let mut cover_proto = CoreProto::new();
+    let bytes = std::fs::read(tree_path_ref).map_err(GokoError::from)?;
+    decode_tree(&bytes, point_cloud)
+}
 
-    let mut file = File::open(&tree_path_ref).map_err(GokoError::from)?;
-    let mut cis = CodedInputStream::new(&mut file);
-    if let Err(e) = cover_proto.merge_from(&mut cis) {
-        panic!("Proto buff was unable to read {:#?}", e)
-    }
+/// Decodes a tree file that has been read into memory, see `load_tree`.
+pub(crate) fn decode_tree<D: PointCloud>(
+    bytes: &[u8],
+    point_cloud: Arc<D>,
+) -> GokoResult<CoverTreeWriter<D>> {
+    let (header, body) = TreeFileHeader::decode(bytes)?;
+    let cover_proto = CoreProto::parse_from_bytes(body.proto).map_err(GokoError::from)?;
 
-    CoverTreeWriter::load(&cover_proto, point_cloud)
+    let mut tree = match header {
+        Some(header) => {
+            header.check(point_cloud.as_ref())?;
+            let tree = CoverTreeWriter::load_with_rng_seed(
+                &cover_proto,
+                point_cloud,
+                header.parameters.rng_seed,
+            )?;
+            header.check_parameters(&tree.parameters)?;
+            tree
+        }
+        None => {
+            println!("\t \t The tree has no header, it can't be checked against the point cloud");
+            CoverTreeWriter::load(&cover_proto, point_cloud)?
+        }
+    };
+    tree.set_saved_plugins(
+        body.sections
+            .into_iter()
+            .map(|(name, section)| (name, section.to_vec()))
+            .collect(),
+    );
+    Ok(tree)
 }
 
-/// Helper function that handles the file I/O and protobuf encoding for you.
+/// Like `load_tree`, but the layers are loaded in a background thread starting from the root, see
+/// `covertree::lazy`. This returns as soon as the file is read and checked.
+pub fn load_tree_lazy<P: AsRef<Path>, D: PointCloud>(
+    tree_path: P,
+    point_cloud: Arc<D>,
+) -> GokoResult<LazyCoverTree<D>> {
+    let tree_path_ref: &Path = tree_path.as_ref();
+    println!(
+        "\nLazily loading tree from : {}",
+        tree_path_ref.to_string_lossy()
+    );
+
+    let bytes = std::fs::read(tree_path_ref).map_err(GokoError::from)?;
+    let (header, body) = TreeFileHeader::decode(&bytes)?;
+    match &header {
+        Some(header) => header.check(point_cloud.as_ref())?,
+        None => {
+            println!("\t \t The tree has no header, it can't be checked against the point cloud")
+        }
+    }
+    let sections = body
+        .sections
+        .into_iter()
+        .map(|(name, section)| (name, section.to_vec()))
+        .collect();
+    LazyCoverTree::load(body.proto.to_vec(), sections, point_cloud, header.as_ref())
+}
+
+/// Helper function that handles the file I/O and protobuf encoding for you. Persistent plugins are saved with the tree.
 pub fn save_tree<P: AsRef<Path>, D: PointCloud>(
     tree_path: P,
     cover_tree: &CoverTreeWriter<D>,
@@ -159,17 +220,89 @@ pub fn save_tree<P: AsRef<Path>, D: PointCloud>(
         remove_file(&tree_path).map_err(GokoError::from)?;
     }
 
-    let cover_proto = cover_tree.save();
+    std::fs::write(&tree_path, encode_tree(cover_tree)?).map_err(GokoError::from)?;
+    Ok(())
+}
+
+/// Encodes a tree the way `save_tree` writes it.
+pub(crate) fn encode_tree<D: PointCloud>(cover_tree: &CoverTreeWriter<D>) -> GokoResult<Vec<u8>> {
+    let proto = encode_proto(cover_tree)?;
+    let sections = cover_tree.plugin_sections()?;
+    let header = TreeFileHeader::new(&cover_tree.parameters, &proto, &sections)?;
+    Ok(header.encode(&proto, &sections))
+}
 
-    let mut core_file = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .create(true)
-        .open(&tree_path)
-        .unwrap();
+/// The field number of `name_map` in `CoreProto`.
+const NAME_MAP_FIELD: u32 = 12;
 
-    let mut cos = CodedOutputStream::new(&mut core_file);
-    cover_proto.write_to(&mut cos).map_err(GokoError::from)?;
-    cos.flush().map_err(GokoError::from)?;
+/// Encodes the protobuf of the tree. The generated code writes the name map in the order of its `HashMap`, so the map
+/// is written here instead, sorted by name, so that the same tree always gives the same bytes.
+fn encode_proto<D: PointCloud>(cover_tree: &CoverTreeWriter<D>) -> GokoResult<Vec<u8>> {
+    let mut cover_proto = cover_tree.save();
+    let mut name_map: Vec<(String, u64)> = cover_proto.take_name_map().into_iter().collect();
+    name_map.sort_unstable();
+    let mut bytes = cover_proto.write_to_bytes()?;
+    let mut output = CodedOutputStream::vec(&mut bytes);
+    for (name, index) in &name_map {
+        // Each entry is a message with the key in field 1 and the value in field 2
+        let entry_len = string_size(1, name) + value_size(2, *index, WireType::WireTypeVarint);
+        output.write_tag(NAME_MAP_FIELD, WireType::WireTypeLengthDelimited)?;
+        output.write_raw_varint32(entry_len)?;
+        output.write_string(1, name)?;
+        output.write_uint64(2, *index)?;
+    }
+    output.flush()?;
+    drop(output);
+    Ok(bytes)
+}
+
+/// Saves the tree together with its point cloud and the parameters it was built with, see `bundle`.
+pub fn save_bundle<P: AsRef<Path>, D: BundleCloud>(
+    bundle_path: P,
+    cover_tree: &CoverTreeWriter<D>,
+) -> GokoResult<()> {
+    let bundle_path_ref: &Path = bundle_path.as_ref();
+    println!("Saving bundle to : {}", bundle_path_ref.to_string_lossy());
+    let bytes = encode_bundle(cover_tree)?;
+    std::fs::write(&bundle_path, bytes).map_err(GokoError::from)?;
+    Ok(())
+}
+
+/// Loads a tree and its point cloud from a bundle written by `save_bundle`. The saved label and metadata summaries
+/// are restored like they are by `load_tree`.
+pub fn load_bundle<P: AsRef<Path>, D: BundleCloud>(
+    bundle_path: P,
+) -> GokoResult<CoverTreeWriter<D>> {
+    let bundle_path_ref: &Path = bundle_path.as_ref();
+    println!(
+        "\nLoading bundle from : {}",
+        bundle_path_ref.to_string_lossy()
+    );
+    let bytes = std::fs::read(bundle_path_ref).map_err(GokoError::from)?;
+    decode_bundle(&bytes)
+}
+
+/// Saves the tree in the memory mapped layout, see `covertree::mapped`. Plugins aren't saved.
+pub fn save_mapped_tree<P: AsRef<Path>, D: PointCloud>(
+    tree_path: P,
+    cover_tree: &CoverTreeWriter<D>,
+) -> GokoResult<()> {
+    let tree_path_ref: &Path = tree_path.as_ref();
+    println!(
+        "Saving mapped tree to : {}",
+        tree_path_ref.to_string_lossy()
+    );
+    let mut out = BufWriter::new(File::create(tree_path_ref)?);
+    encode_mapped_tree(&cover_tree.reader(), &mut out)?;
+    out.flush()?;
     Ok(())
 }
+
+/// Maps a tree saved with `save_mapped_tree`. This only reads the final addresses of the points, the nodes are read
+/// out of the map when they're queried, so it's fast for trees of any size.
+pub fn load_mapped_tree<P: AsRef<Path>, D: PointCloud>(
+    tree_path: P,
+    point_cloud: Arc<D>,
+) -> GokoResult<CoverTreeWriter<D>> {
+    open_mapped_tree(tree_path, point_cloud)
+}
@@ -137,7 +137,7 @@ fn gaussian_is_not_nan() {
                     p.var().iter().for_each(|f| {
                         assert!(f.is_finite(), "Variance: {}, at address {:?}", f, addr)
                     });
-                    p.count
+                    p.count()
                 })
                 .unwrap();
             ct_reader.get_node_and(addr, |n| assert_eq!(n.coverage_count(), count));
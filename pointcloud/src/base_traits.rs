@@ -6,7 +6,12 @@ use std::fmt::Debug;
 
 use std::ops::Deref;
 
+use crate::brute_force;
+use crate::distance_matrix;
+use crate::moments::{self, Covariance, Moments};
 use crate::pc_errors::*;
+use crate::validation::{validate_indexes, ValidationReport};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
 /// A trait to ensure that we can create matrices and statiscial vectors from your point reference.
@@ -29,6 +34,8 @@ pub trait PointRef: Send + Sync {
 ///
 /// Implement this then benchmark it to hell, this is the core loop of everything.
 pub trait Metric<T: ?Sized>: Send + Sync + 'static {
+    /// The name of the metric, saved trees record this to check they're loaded with the same metric.
+    const NAME: &'static str;
     /// Distance calculator. Optimize the hell out of this if you're implementing it.
     fn dist(x: &T, y: &T) -> f32;
     // Implemented, but the system that uses this isn't yet.
@@ -297,6 +304,97 @@ pub trait PointCloud: Send + Sync + 'static {
         }
         Ok(moment_vec)
     }
+    /// The exact k nearest neighbors of a point, found by checking every point. See [`crate::brute_force`].
+    fn brute_knn<T: Deref<Target = Self::Point> + Send + Sync>(
+        &self,
+        point: &T,
+        k: usize,
+    ) -> PointCloudResult<Vec<(usize, f32)>>
+    where
+        Self: Sized,
+    {
+        brute_force::brute_knn(self, point, k)
+    }
+
+    /// The exact k nearest neighbors of each point, blocking the queries against the data.
+    fn brute_knn_batch<T: Deref<Target = Self::Point> + Send + Sync>(
+        &self,
+        points: &[T],
+        k: usize,
+    ) -> PointCloudResult<Vec<Vec<(usize, f32)>>>
+    where
+        Self: Sized,
+    {
+        brute_force::brute_knn_batch(self, points, k)
+    }
+
+    /// Every point within `radius` of a point, found by checking every point.
+    fn brute_range<T: Deref<Target = Self::Point> + Send + Sync>(
+        &self,
+        point: &T,
+        radius: f32,
+    ) -> PointCloudResult<Vec<(usize, f32)>>
+    where
+        Self: Sized,
+    {
+        brute_force::brute_range(self, point, radius)
+    }
+
+    /// The dense, symmetric matrix of distances between the points. See [`crate::distance_matrix`].
+    fn distance_matrix(&self, indexes: &[usize]) -> PointCloudResult<Array2<f32>>
+    where
+        Self: Sized,
+    {
+        distance_matrix::distance_matrix(self, indexes)
+    }
+
+    /// The upper triangle of [`PointCloud::distance_matrix`], row by row. This is the same layout as scipy's `pdist`.
+    fn condensed_distance_matrix(&self, indexes: &[usize]) -> PointCloudResult<Vec<f32>>
+    where
+        Self: Sized,
+    {
+        distance_matrix::condensed_distance_matrix(self, indexes)
+    }
+
+    /// The distances between each point in `rows` and each point in `cols`.
+    fn cross_distance_matrix(&self, rows: &[usize], cols: &[usize]) -> PointCloudResult<Array2<f32>>
+    where
+        Self: Sized,
+    {
+        distance_matrix::cross_distance_matrix(self, rows, cols)
+    }
+
+    /// The numerically stable moments of the specified vectors, up to the 4th. See [`crate::moments`].
+    fn moments(&self, indexes: &[usize]) -> PointCloudResult<Moments>
+    where
+        Self: Sized,
+    {
+        moments::moments(self, indexes, None)
+    }
+
+    /// The moments of the specified vectors, with a weight per vector.
+    fn weighted_moments(&self, indexes: &[usize], weights: &[f32]) -> PointCloudResult<Moments>
+    where
+        Self: Sized,
+    {
+        moments::moments(self, indexes, Some(weights))
+    }
+
+    /// The full covariance of the specified vectors.
+    fn covariance(&self, indexes: &[usize]) -> PointCloudResult<Covariance>
+    where
+        Self: Sized,
+    {
+        moments::covariance(self, indexes)
+    }
+
+    /// Checks every point for non-finite values, all-zero points and exact duplicates. See [`crate::validation`].
+    fn validate(&self) -> PointCloudResult<ValidationReport>
+    where
+        Self: Sized,
+    {
+        validate_indexes(self, &self.reference_indexes())
+    }
 }
 
 /// A sparse adjacency matrix.
@@ -336,7 +434,10 @@ impl AdjMatrix {
 }
 
 /// A summary for labels and metadata. You can make this an empty zero sized type for when you don't need it.
-pub trait Summary: Serialize + Clone + Debug + Default + Send + Sync + 'static {
+/// Summaries are deserializable so that they can be saved with a tree.
+pub trait Summary:
+    Serialize + DeserializeOwned + Clone + Debug + Default + Send + Sync + 'static
+{
     /// Underlying type.
     type Label: ?Sized;
     /// Adding a single value to the summary.
@@ -346,6 +447,11 @@ pub trait Summary: Serialize + Clone + Debug + Default + Send + Sync + 'static {
     fn combine(&mut self, other: &Self);
     /// The number of elements this summary covers
     fn count(&self) -> usize;
+    /// If the label was added to this summary, `None` if the summary can't tell. Queries use this to skip the parts
+    /// of a tree that don't have a label, so only return `Some(false)` when you're sure.
+    fn contains(&self, _v: &Self::Label) -> Option<bool> {
+        None
+    }
 }
 
 impl Summary for () {
@@ -378,6 +484,7 @@ pub trait LabelSet: Debug + Send + Sync + 'static {
 
 /// Simply shoves together a point cloud and a label set, for a modular label system
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(bound(deserialize = ""))]
 pub struct SummaryCounter<S: Summary + Clone> {
     /// The categorical summary
     pub summary: S,
@@ -535,6 +642,16 @@ impl<D: PointCloud, N: NamedSet> SimpleNamedCloud<D, N> {
         assert_eq!(names.len(), data.len());
         SimpleNamedCloud { data, names }
     }
+
+    /// The underlying point cloud
+    pub fn data(&self) -> &D {
+        &self.data
+    }
+
+    /// The underlying name set
+    pub fn name_set(&self) -> &N {
+        &self.names
+    }
 }
 
 impl<D: PointCloud, N: NamedSet> PointCloud for SimpleNamedCloud<D, N> {
@@ -602,14 +719,98 @@ impl<D: PointCloud, N: NamedSet> PointCloud for SimpleNamedCloud<D, N> {
 }
 
 /// Allows for expensive metadata, this is identical to the label trait, but enables slower update
-pub trait MetaSet {
+pub trait MetaSet: Debug + Send + Sync + 'static {
     /// Underlying metadata
     type Metadata: ?Sized + Serialize;
     /// A summary of the underlying metadata
     type MetaSummary: Summary<Label = Self::Metadata>;
 
+    /// Number of elements in this metadata set
+    fn len(&self) -> usize;
+    /// If there are no elements left in this metadata set
+    fn is_empty(&self) -> bool;
+
     /// Expensive metadata object for the sample
     fn metadata(&self, pn: usize) -> PointCloudResult<Option<&Self::Metadata>>;
     /// Expensive metadata summary over the samples
     fn metasummary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::MetaSummary>>;
 }
+
+/// Simply shoves together a point cloud and a metadata set, for a modular metadata system
+#[derive(Debug)]
+pub struct SimpleMetaCloud<D, M> {
+    data: D,
+    metadata: M,
+}
+
+impl<D: PointCloud, M: MetaSet> SimpleMetaCloud<D, M> {
+    /// Creates a new one
+    pub fn new(data: D, metadata: M) -> Self {
+        assert_eq!(metadata.len(), data.len());
+        SimpleMetaCloud { data, metadata }
+    }
+}
+
+impl<D: PointCloud, M: MetaSet> PointCloud for SimpleMetaCloud<D, M> {
+    /// Underlying metric this point cloud uses
+    type Metric = D::Metric;
+    type Point = D::Point;
+    type PointRef<'a> = D::PointRef<'a>;
+    type Metadata = M::Metadata;
+    type MetaSummary = M::MetaSummary;
+
+    type Label = D::Label;
+    type LabelSummary = D::LabelSummary;
+
+    #[inline]
+    fn dim(&self) -> usize {
+        self.data.dim()
+    }
+    #[inline]
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+    #[inline]
+    fn reference_indexes(&self) -> Vec<usize> {
+        self.data.reference_indexes()
+    }
+    #[inline]
+    fn point<'a, 'b: 'a>(&'b self, i: usize) -> PointCloudResult<Self::PointRef<'a>> {
+        self.data.point(i)
+    }
+
+    fn metadata(&self, pn: usize) -> PointCloudResult<Option<&Self::Metadata>> {
+        self.metadata.metadata(pn)
+    }
+    /// Expensive metadata summary over the samples
+    fn metasummary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::MetaSummary>> {
+        self.metadata.metasummary(pns)
+    }
+
+    /// Grabs a label reference. Supports errors (the label could be remote),
+    /// and partially labeled datasets with the option.
+    fn label(&self, pn: usize) -> PointCloudResult<Option<&Self::Label>> {
+        self.data.label(pn)
+    }
+    /// Grabs a label summary of a set of indexes.
+    fn label_summary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::LabelSummary>> {
+        self.data.label_summary(pns)
+    }
+    /// Grabs the name of the point.
+    /// Returns an error if the access errors out, and a None if the name is unknown
+    fn name(&self, pi: usize) -> PointCloudResult<String> {
+        self.data.name(pi)
+    }
+    /// Converts a name to an index you can use
+    fn index(&self, pn: &str) -> PointCloudResult<usize> {
+        self.data.index(pn)
+    }
+    /// Gather's all valid known names
+    fn names(&self) -> Vec<String> {
+        self.data.names()
+    }
+}
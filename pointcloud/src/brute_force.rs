@@ -0,0 +1,144 @@
+//! Exact nearest neighbor and range queries that check every point. These are slow, but they're the ground truth
+//! for testing trees and measuring their recall.
+//!
+//! Results use the same `(index, distance)` format as the tree's queries, sorted by distance then index.
+
+use rayon::prelude::*;
+use std::cmp::Ordering;
+use std::ops::Deref;
+
+use crate::base_traits::*;
+use crate::pc_errors::*;
+
+/// The number of queries that are compared against each block of the data at once.
+const QUERY_BLOCK: usize = 16;
+
+#[inline]
+fn data_block(data_dim: usize) -> usize {
+    (100000 / data_dim.max(1)).clamp(16, 1024)
+}
+
+#[inline]
+fn cmp_neighbors(a: &(usize, f32), b: &(usize, f32)) -> Ordering {
+    a.1.partial_cmp(&b.1)
+        .unwrap_or(Ordering::Equal)
+        .then(a.0.cmp(&b.0))
+}
+
+/// Keeps the k best of the neighbors, sorted.
+#[inline]
+fn truncate_neighbors(neighbors: &mut Vec<(usize, f32)>, k: usize) {
+    if neighbors.len() > k {
+        neighbors.select_nth_unstable_by(k, cmp_neighbors);
+        neighbors.truncate(k);
+    }
+    neighbors.sort_unstable_by(cmp_neighbors);
+}
+
+/// The exact k nearest neighbors to a point, see [`PointCloud::brute_knn`].
+pub fn brute_knn<D: PointCloud, T: Deref<Target = D::Point> + Send + Sync>(
+    point_cloud: &D,
+    point: &T,
+    k: usize,
+) -> PointCloudResult<Vec<(usize, f32)>> {
+    let indexes = point_cloud.reference_indexes();
+    let block_neighbors = indexes
+        .par_chunks(data_block(point_cloud.dim()))
+        .map(|block| {
+            let dists = point_cloud.distances_to_point(point, block)?;
+            let mut neighbors: Vec<(usize, f32)> = block.iter().copied().zip(dists).collect();
+            truncate_neighbors(&mut neighbors, k);
+            Ok(neighbors)
+        })
+        .collect::<PointCloudResult<Vec<Vec<(usize, f32)>>>>()?;
+    let mut neighbors: Vec<(usize, f32)> = block_neighbors.into_iter().flatten().collect();
+    truncate_neighbors(&mut neighbors, k);
+    Ok(neighbors)
+}
+
+/// The exact k nearest neighbors to each of the points, see [`PointCloud::brute_knn_batch`].
+pub fn brute_knn_batch<D: PointCloud, T: Deref<Target = D::Point> + Send + Sync>(
+    point_cloud: &D,
+    points: &[T],
+    k: usize,
+) -> PointCloudResult<Vec<Vec<(usize, f32)>>> {
+    let indexes = point_cloud.reference_indexes();
+    let data_block = data_block(point_cloud.dim());
+    let query_blocks = points
+        .par_chunks(QUERY_BLOCK)
+        .map(|queries| {
+            let mut neighbors: Vec<Vec<(usize, f32)>> =
+                vec![Vec::with_capacity(2 * k); queries.len()];
+            // Each block of the data is read once for all the queries in this block.
+            for block in indexes.chunks(data_block) {
+                let block_points = block
+                    .iter()
+                    .map(|i| point_cloud.point(*i))
+                    .collect::<PointCloudResult<Vec<D::PointRef<'_>>>>()?;
+                for (query, query_neighbors) in queries.iter().zip(neighbors.iter_mut()) {
+                    for (i, y) in block.iter().zip(&block_points) {
+                        query_neighbors.push((*i, D::Metric::dist(query, y)));
+                    }
+                    if query_neighbors.len() > 2 * k {
+                        truncate_neighbors(query_neighbors, k);
+                    }
+                }
+            }
+            for query_neighbors in neighbors.iter_mut() {
+                truncate_neighbors(query_neighbors, k);
+            }
+            Ok(neighbors)
+        })
+        .collect::<PointCloudResult<Vec<Vec<Vec<(usize, f32)>>>>>()?;
+    Ok(query_blocks.into_iter().flatten().collect())
+}
+
+/// Every point within the radius of a point, see [`PointCloud::brute_range`].
+pub fn brute_range<D: PointCloud, T: Deref<Target = D::Point> + Send + Sync>(
+    point_cloud: &D,
+    point: &T,
+    radius: f32,
+) -> PointCloudResult<Vec<(usize, f32)>> {
+    let indexes = point_cloud.reference_indexes();
+    let dists = point_cloud.distances_to_point(point, &indexes)?;
+    let mut neighbors: Vec<(usize, f32)> = indexes
+        .into_iter()
+        .zip(dists)
+        .filter(|(_, d)| *d <= radius)
+        .collect();
+    neighbors.sort_unstable_by(cmp_neighbors);
+    Ok(neighbors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_sources::tests::{build_ram_fixed_test, build_ram_random_test};
+
+    #[test]
+    fn knn_fixed() {
+        // Point i is [i, i, i], so the neighbors of 2.9 are 3, 2, 4, 1
+        let cloud = build_ram_fixed_test(2000, 3);
+        let point = [2.9f32; 3];
+        let knn = cloud.brute_knn(&&point[..], 4).unwrap();
+        let indexes: Vec<usize> = knn.iter().map(|(i, _)| *i).collect();
+        assert_eq!(indexes, vec![3, 2, 4, 1]);
+        assert_approx_eq!(knn[0].1, (3.0 * 0.01f32).sqrt());
+
+        let range = cloud.brute_range(&&point[..], 1.8).unwrap();
+        let indexes: Vec<usize> = range.iter().map(|(i, _)| *i).collect();
+        assert_eq!(indexes, vec![3, 2]);
+    }
+
+    #[test]
+    fn batch_matches_single() {
+        let cloud = build_ram_random_test(3000, 5);
+        let queries: Vec<&[f32]> = (0..40).map(|i| cloud.point(i * 7).unwrap()).collect();
+        let batch = cloud.brute_knn_batch(&queries, 10).unwrap();
+        assert_eq!(batch.len(), 40);
+        for (query, neighbors) in queries.iter().zip(&batch) {
+            assert_eq!(neighbors, &cloud.brute_knn(query, 10).unwrap());
+        }
+        assert_eq!(batch[1][0], (7, 0.0));
+    }
+}
@@ -0,0 +1,429 @@
+//! Points stored in independently deflated blocks, for data that is too large to keep raw.
+//!
+//! Each block holds `block_size` points and can be decompressed on its own, so a point access only costs one block.
+//! Recently used blocks are kept decompressed in a small per-thread cache, which works well with the way the tree
+//! builder hands out contiguous work to each thread.
+
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use super::DataRam;
+use crate::base_traits::*;
+use crate::metrics::*;
+use crate::pc_errors::*;
+
+/// The number of decompressed blocks each thread keeps around.
+pub const BLOCK_CACHE_SIZE: usize = 8;
+
+const MAGIC: &[u8; 8] = b"GOKOBLK1";
+
+/// Each cloud gets a unique id so that the thread local caches can tell them apart.
+static NEXT_CLOUD_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Cloud id, block number and the decompressed block.
+type CachedBlock = (u64, usize, Arc<Vec<f32>>);
+
+thread_local! {
+    /// Most recently used first.
+    static BLOCK_CACHE: RefCell<Vec<CachedBlock>> = RefCell::new(Vec::with_capacity(BLOCK_CACHE_SIZE));
+}
+
+/// A point inside a decompressed block. Keeps the block alive, so it's valid after the block leaves the cache.
+#[derive(Debug, Clone)]
+pub struct BlockPoint {
+    block: Arc<Vec<f32>>,
+    start: usize,
+    dim: usize,
+}
+
+impl Deref for BlockPoint {
+    type Target = [f32];
+    fn deref(&self) -> &[f32] {
+        &self.block[self.start..self.start + self.dim]
+    }
+}
+
+impl PointRef for BlockPoint {
+    type DenseIter = std::vec::IntoIter<f32>;
+    fn dense(&self) -> Vec<f32> {
+        self.deref().to_vec()
+    }
+    fn dense_iter(&self) -> Self::DenseIter {
+        self.dense().into_iter()
+    }
+}
+
+/// Dense data stored in deflated blocks of `block_size` points, with an index of where each block starts.
+#[derive(Debug)]
+pub struct DataCompressed<M = L2> {
+    id: u64,
+    name: String,
+    dim: usize,
+    len: usize,
+    block_size: usize,
+    /// The start of each block in `bytes`, followed by the total length.
+    block_index: Vec<usize>,
+    bytes: Vec<u8>,
+    metric: PhantomData<M>,
+}
+
+impl<M> DataCompressed<M> {
+    fn from_parts(
+        name: String,
+        dim: usize,
+        len: usize,
+        block_size: usize,
+        block_index: Vec<usize>,
+        bytes: Vec<u8>,
+    ) -> DataCompressed<M> {
+        DataCompressed {
+            id: NEXT_CLOUD_ID.fetch_add(1, Ordering::Relaxed),
+            name,
+            dim,
+            len,
+            block_size,
+            block_index,
+            bytes,
+            metric: PhantomData,
+        }
+    }
+
+    /// Compresses raw, row-major data with `block_size` points per block.
+    pub fn new(data: &[f32], dim: usize, block_size: usize) -> PointCloudResult<DataCompressed<M>> {
+        assert_eq!(data.len() % dim, 0);
+        assert!(block_size > 0, "Blocks need at least one point");
+        let mut block_index = vec![0];
+        let mut bytes = Vec::new();
+        for block in data.chunks(block_size * dim) {
+            let mut encoder = DeflateEncoder::new(bytes, Compression::default());
+            for x in block {
+                encoder.write_all(&x.to_le_bytes())?;
+            }
+            bytes = encoder.finish()?;
+            block_index.push(bytes.len());
+        }
+        Ok(DataCompressed::from_parts(
+            "Compressed".to_string(),
+            dim,
+            data.len() / dim,
+            block_size,
+            block_index,
+            bytes,
+        ))
+    }
+
+    /// The number of points per block
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// The number of blocks
+    pub fn block_count(&self) -> usize {
+        self.block_index.len() - 1
+    }
+
+    /// The size of the compressed data in bytes
+    pub fn compressed_size(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Saves this to a file. The blocks are written as they are, so this doesn't recompress anything.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> PointCloudResult<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(MAGIC)?;
+        for x in &[self.dim, self.len, self.block_size, self.block_index.len()] {
+            file.write_all(&(*x as u64).to_le_bytes())?;
+        }
+        for x in &self.block_index {
+            file.write_all(&(*x as u64).to_le_bytes())?;
+        }
+        file.write_all(&self.bytes)?;
+        Ok(())
+    }
+
+    /// Opens a file written by [`DataCompressed::save`]. The name is the path.
+    pub fn load<P: AsRef<Path>>(path: P) -> PointCloudResult<DataCompressed<M>> {
+        let name = path.as_ref().to_string_lossy().to_string();
+        let file = File::open(path)?;
+        let file_size = file.metadata()?.len();
+        let mut file = BufReader::new(file);
+        let mut magic = [0u8; 8];
+        file.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(ParsingError::RegularParsingError("Not a compressed block file").into());
+        }
+        let mut read_u64 = || -> PointCloudResult<usize> {
+            let mut buf = [0u8; 8];
+            file.read_exact(&mut buf)?;
+            Ok(u64::from_le_bytes(buf) as usize)
+        };
+        let dim = read_u64()?;
+        let len = read_u64()?;
+        let block_size = read_u64()?;
+        let index_len = read_u64()?;
+        if dim == 0 || block_size == 0 {
+            return Err(ParsingError::RegularParsingError(
+                "Compressed block file has no dimension or block size",
+            )
+            .into());
+        }
+        // A block is decompressed into a buffer of this many bytes
+        if block_size
+            .checked_mul(dim)
+            .and_then(|n| n.checked_mul(4))
+            .is_none()
+        {
+            return Err(ParsingError::RegularParsingError(
+                "Compressed block file's blocks are too large",
+            )
+            .into());
+        }
+        let block_count = len / block_size + (len % block_size != 0) as usize;
+        if index_len != block_count + 1 || index_len as u64 > file_size / 8 {
+            return Err(ParsingError::RegularParsingError(
+                "Compressed block file's index doesn't match its length",
+            )
+            .into());
+        }
+        let block_index = (0..index_len)
+            .map(|_| read_u64())
+            .collect::<PointCloudResult<Vec<usize>>>()?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        if block_index[0] != 0
+            || block_index.windows(2).any(|w| w[0] > w[1])
+            || block_index.last() != Some(&bytes.len())
+        {
+            return Err(
+                ParsingError::RegularParsingError("Compressed block file is truncated").into(),
+            );
+        }
+        Ok(DataCompressed::from_parts(
+            name,
+            dim,
+            len,
+            block_size,
+            block_index,
+            bytes,
+        ))
+    }
+
+    fn decompress_block(&self, block: usize) -> PointCloudResult<Vec<f32>> {
+        let start = self.block_index[block];
+        let end = self.block_index[block + 1];
+        let block_len = self.block_size.min(self.len - block * self.block_size);
+        let mut raw = Vec::with_capacity(block_len * self.dim * 4);
+        DeflateDecoder::new(&self.bytes[start..end]).read_to_end(&mut raw)?;
+        if raw.len() != block_len * self.dim * 4 {
+            return Err(PointCloudError::data_access(
+                block * self.block_size,
+                format!("{}: the block has the wrong length", self.name),
+            ));
+        }
+        Ok(raw
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect())
+    }
+
+    fn block(&self, block: usize) -> PointCloudResult<Arc<Vec<f32>>> {
+        let cached = BLOCK_CACHE.with(|cache| {
+            let mut cache = cache.borrow_mut();
+            let pos = cache
+                .iter()
+                .position(|(id, b, _)| *id == self.id && *b == block)?;
+            let entry = cache.remove(pos);
+            let data = Arc::clone(&entry.2);
+            cache.insert(0, entry);
+            Some(data)
+        });
+        if let Some(data) = cached {
+            return Ok(data);
+        }
+        let data = Arc::new(self.decompress_block(block)?);
+        BLOCK_CACHE.with(|cache| {
+            let mut cache = cache.borrow_mut();
+            cache.truncate(BLOCK_CACHE_SIZE - 1);
+            cache.insert(0, (self.id, block, Arc::clone(&data)));
+        });
+        Ok(data)
+    }
+}
+
+impl<M> From<&DataRam<M>> for DataCompressed<M>
+where
+    M: Metric<[f32]>,
+{
+    /// Compresses the data with 1024 points per block.
+    fn from(data: &DataRam<M>) -> DataCompressed<M> {
+        let raw: Vec<f32> = data
+            .reference_indexes()
+            .iter()
+            .flat_map(|i| data.point(*i).unwrap().iter().copied())
+            .collect();
+        // Deflating into a vec can't fail
+        DataCompressed::new(&raw, data.dim(), 1024).unwrap()
+    }
+}
+
+impl<M: Metric<[f32]>> PointCloud for DataCompressed<M> {
+    type Metric = M;
+    type Point = [f32];
+    type PointRef<'a> = BlockPoint;
+    type LabelSummary = ();
+    type Label = ();
+    type MetaSummary = ();
+    type Metadata = ();
+
+    fn metadata(&self, _pn: usize) -> PointCloudResult<Option<&Self::Metadata>> {
+        Ok(None)
+    }
+    fn metasummary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::MetaSummary>> {
+        Ok(SummaryCounter {
+            summary: (),
+            nones: pns.len(),
+            errors: 0,
+        })
+    }
+    fn label(&self, _pn: usize) -> PointCloudResult<Option<&Self::Label>> {
+        Ok(None)
+    }
+    fn label_summary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::LabelSummary>> {
+        Ok(SummaryCounter {
+            summary: (),
+            nones: pns.len(),
+            errors: 0,
+        })
+    }
+    fn name(&self, pi: usize) -> PointCloudResult<String> {
+        Ok(pi.to_string())
+    }
+    fn index(&self, pn: &str) -> PointCloudResult<usize> {
+        pn.parse::<usize>().map_err(|_| {
+            ParsingError::RegularParsingError("Unable to parse your str into an usize").into()
+        })
+    }
+    fn names(&self) -> Vec<String> {
+        (0..self.len()).map(|i| i.to_string()).collect()
+    }
+
+    #[inline]
+    fn dim(&self) -> usize {
+        self.dim
+    }
+    #[inline]
+    fn len(&self) -> usize {
+        self.len
+    }
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    #[inline]
+    fn reference_indexes(&self) -> Vec<usize> {
+        (0..self.len).collect()
+    }
+    fn point<'a, 'b: 'a>(&'b self, i: usize) -> PointCloudResult<BlockPoint> {
+        if i >= self.len {
+            return Err(PointCloudError::data_access(i, self.name.clone()));
+        }
+        let block = self.block(i / self.block_size)?;
+        Ok(BlockPoint {
+            block,
+            start: (i % self.block_size) * self.dim,
+            dim: self.dim,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_sources::tests::build_ram_random_test;
+
+    #[test]
+    fn compressed_matches_ram() {
+        let ram = build_ram_random_test(100, 5);
+        let compressed = DataCompressed::<L2>::new(
+            &ram.points_dense_matrix(&ram.reference_indexes())
+                .unwrap()
+                .into_raw_vec(),
+            5,
+            16,
+        )
+        .unwrap();
+        assert_eq!(compressed.len(), 100);
+        assert_eq!(compressed.block_count(), 7);
+        // Go through twice to hit the cache and to evict from it
+        for _ in 0..2 {
+            for i in ram.reference_indexes().iter().rev() {
+                assert_eq!(*compressed.point(*i).unwrap(), *ram.point(*i).unwrap());
+            }
+        }
+        assert!(compressed.point(100).is_err());
+
+        let dists = compressed
+            .distances_to_point_index(3, &[0, 50, 99])
+            .unwrap();
+        let ram_dists = ram.distances_to_point_index(3, &[0, 50, 99]).unwrap();
+        assert_eq!(dists, ram_dists);
+    }
+
+    #[test]
+    fn compressed_save_load() {
+        let ram = build_ram_random_test(50, 3);
+        let compressed = DataCompressed::from(&ram);
+        assert_eq!(compressed.block_count(), 1);
+
+        let dir = tempdir::TempDir::new("compressed").unwrap();
+        let path = dir.path().join("data.blk");
+        compressed.save(&path).unwrap();
+        let loaded = DataCompressed::<L2>::load(&path).unwrap();
+        assert_eq!(loaded.len(), 50);
+        assert_eq!(loaded.compressed_size(), compressed.compressed_size());
+        for i in ram.reference_indexes() {
+            assert_eq!(*loaded.point(i).unwrap(), *ram.point(i).unwrap());
+        }
+
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        assert!(DataCompressed::<L2>::load(&path).is_err());
+        std::fs::write(&path, &bytes[..20]).unwrap();
+        assert!(DataCompressed::<L2>::load(&path).is_err());
+        let mut bad_index = bytes.clone();
+        bad_index[40..48].copy_from_slice(&(1u64 << 40).to_le_bytes());
+        std::fs::write(&path, &bad_index).unwrap();
+        assert!(DataCompressed::<L2>::load(&path).is_err());
+        // One block that's too large to decompress into
+        let mut bad_block_size = bytes.clone();
+        bad_block_size[24..32].copy_from_slice(&(1u64 << 62).to_le_bytes());
+        std::fs::write(&path, &bad_block_size).unwrap();
+        assert!(DataCompressed::<L2>::load(&path).is_err());
+    }
+
+    #[test]
+    fn short_blocks_are_errors() {
+        let ram = build_ram_random_test(50, 3);
+        let compressed = DataCompressed::<L2>::from(&ram);
+        // Claims more points than the block holds
+        let wrong_len = DataCompressed::<L2>::from_parts(
+            "wrong".to_string(),
+            3,
+            60,
+            compressed.block_size,
+            compressed.block_index.clone(),
+            compressed.bytes.clone(),
+        );
+        assert!(wrong_len.point(55).is_err());
+        assert!(wrong_len.point(0).is_err());
+    }
+}
@@ -117,6 +117,11 @@ impl Mmapf32 {
         self.inner.make_mut()?;
         Ok(MmapMutf32 { inner: self.inner })
     }
+
+    /// The mapped bytes, for files that aren't just floats. The length is the number of whole floats in the file.
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.inner.ptr(), self.len() * size_of::<f32>()) }
+    }
 }
 
 impl Deref for Mmapf32 {
@@ -18,13 +18,17 @@
 */
 
 //! Some data sources and a trait to dimension and uniformly reference the data contained.
-//! The only currently supported are memmaps and ram blobs.
+//! The only currently supported are memmaps, ram blobs and deflated blocks.
 
+mod compressed;
 mod memmap_ram;
 mod sparse_ram;
 
 #[allow(dead_code)]
 mod memmapf32;
 
+pub use compressed::*;
 #[doc(hidden)]
 pub use memmap_ram::*;
+#[doc(hidden)]
+pub use memmapf32::Mmapf32;
@@ -0,0 +1,194 @@
+//! Dense pairwise distance matrices, for handing off to clustering and embedding algorithms that want all of them.
+//!
+//! The matrices are computed in parallel tiles, so each point is only fetched once per tile. Use the condensed
+//! form when you only need each pair once, it takes half the memory.
+
+use ndarray::Array2;
+use rayon::prelude::*;
+
+use crate::base_traits::*;
+use crate::pc_errors::*;
+
+/// The side length of the tiles the matrices are computed in.
+const TILE_SIZE: usize = 64;
+
+/// The distances between every row point and every column point, in row-major order.
+fn tile<D: PointCloud>(
+    point_cloud: &D,
+    rows: &[usize],
+    cols: &[usize],
+) -> PointCloudResult<Vec<f32>> {
+    let col_points = cols
+        .iter()
+        .map(|j| point_cloud.point(*j))
+        .collect::<PointCloudResult<Vec<D::PointRef<'_>>>>()?;
+    let mut dists = Vec::with_capacity(rows.len() * cols.len());
+    for i in rows {
+        let x = point_cloud.point(*i)?;
+        dists.extend(col_points.iter().map(|y| D::Metric::dist(&x, y)));
+    }
+    Ok(dists)
+}
+
+/// Copies a tile from `tile` into the rows of a row-major matrix that has `width` columns, starting at `col_start`.
+fn copy_tile(dists: &[f32], tile_width: usize, out: &mut [f32], width: usize, col_start: usize) {
+    for (k, row) in dists.chunks(tile_width).enumerate() {
+        let start = k * width + col_start;
+        out[start..start + row.len()].copy_from_slice(row);
+    }
+}
+
+/// The distances between each point in `rows` and each point in `cols`, see [`PointCloud::cross_distance_matrix`].
+pub fn cross_distance_matrix<D: PointCloud>(
+    point_cloud: &D,
+    rows: &[usize],
+    cols: &[usize],
+) -> PointCloudResult<Array2<f32>> {
+    let width = cols.len();
+    let mut matrix = Array2::zeros((rows.len(), width));
+    if width == 0 {
+        return Ok(matrix);
+    }
+    // Each chunk of the output is a strip of rows, which is filled in one tile at a time
+    matrix
+        .as_slice_mut()
+        .unwrap()
+        .par_chunks_mut(TILE_SIZE * width)
+        .zip(rows.par_chunks(TILE_SIZE))
+        .try_for_each(|(out, row_block)| -> PointCloudResult<()> {
+            for (c, col_block) in cols.chunks(TILE_SIZE).enumerate() {
+                let dists = tile(point_cloud, row_block, col_block)?;
+                copy_tile(&dists, col_block.len(), out, width, c * TILE_SIZE);
+            }
+            Ok(())
+        })?;
+    Ok(matrix)
+}
+
+/// The symmetric matrix of distances between the points, see [`PointCloud::distance_matrix`].
+pub fn distance_matrix<D: PointCloud>(
+    point_cloud: &D,
+    indexes: &[usize],
+) -> PointCloudResult<Array2<f32>> {
+    let n = indexes.len();
+    let mut matrix = Array2::zeros((n, n));
+    if n == 0 {
+        return Ok(matrix);
+    }
+    // Only the tiles on and above the diagonal, the rest is filled in by symmetry.
+    matrix
+        .as_slice_mut()
+        .unwrap()
+        .par_chunks_mut(TILE_SIZE * n)
+        .zip(indexes.par_chunks(TILE_SIZE))
+        .enumerate()
+        .try_for_each(|(r, (out, row_block))| -> PointCloudResult<()> {
+            for (c, col_block) in indexes.chunks(TILE_SIZE).enumerate().skip(r) {
+                let dists = tile(point_cloud, row_block, col_block)?;
+                copy_tile(&dists, col_block.len(), out, n, c * TILE_SIZE);
+            }
+            Ok(())
+        })?;
+    for i in 0..n {
+        matrix[[i, i]] = 0.0;
+        for j in 0..i {
+            matrix[[i, j]] = matrix[[j, i]];
+        }
+    }
+    Ok(matrix)
+}
+
+/// The upper triangle of the distance matrix, see [`PointCloud::condensed_distance_matrix`].
+pub fn condensed_distance_matrix<D: PointCloud>(
+    point_cloud: &D,
+    indexes: &[usize],
+) -> PointCloudResult<Vec<f32>> {
+    let n = indexes.len();
+    let mut condensed = vec![0.0; n * n.saturating_sub(1) / 2];
+    // The rows of a condensed matrix get shorter, so the strips of `TILE_SIZE` rows are cut out by hand.
+    let mut strips = Vec::new();
+    let mut rest = &mut condensed[..];
+    for start in (0..n).step_by(TILE_SIZE) {
+        let end = n.min(start + TILE_SIZE);
+        let strip_len = (start..end).map(|i| n - 1 - i).sum();
+        let (strip, tail) = rest.split_at_mut(strip_len);
+        strips.push((start, end, strip));
+        rest = tail;
+    }
+    strips
+        .into_par_iter()
+        .try_for_each(|(start, end, strip)| -> PointCloudResult<()> {
+            let base = n * start - start * (start + 1) / 2;
+            for col_start in (start..n).step_by(TILE_SIZE) {
+                let col_block = &indexes[col_start..n.min(col_start + TILE_SIZE)];
+                let dists = tile(point_cloud, &indexes[start..end], col_block)?;
+                for (k, row) in dists.chunks(col_block.len()).enumerate() {
+                    let i = start + k;
+                    // Keep the entries to the right of the diagonal
+                    for (l, d) in row.iter().enumerate() {
+                        let j = col_start + l;
+                        if i < j {
+                            strip[condensed_index(n, i, j) - base] = *d;
+                        }
+                    }
+                }
+            }
+            Ok(())
+        })?;
+    Ok(condensed)
+}
+
+/// The position of the pair `(i, j)`, with `i < j`, in a condensed matrix over `n` points.
+pub fn condensed_index(n: usize, i: usize, j: usize) -> usize {
+    debug_assert!(i < j && j < n);
+    n * i - i * (i + 1) / 2 + (j - i - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_sources::tests::{build_ram_fixed_test, build_ram_random_test};
+
+    #[test]
+    fn fixed_distances() {
+        // Point i is [i, i], so the distance between i and j is |i - j| * sqrt(2)
+        let cloud = build_ram_fixed_test(5, 2);
+        let indexes = [4, 0, 2];
+        let matrix = cloud.distance_matrix(&indexes).unwrap();
+        assert_approx_eq!(matrix[[0, 1]], 4.0 * 2.0f32.sqrt());
+        assert_approx_eq!(matrix[[2, 1]], 2.0 * 2.0f32.sqrt());
+        assert_eq!(matrix[[1, 1]], 0.0);
+
+        let condensed = cloud.condensed_distance_matrix(&indexes).unwrap();
+        assert_eq!(condensed.len(), 3);
+        assert_approx_eq!(condensed[condensed_index(3, 1, 2)], 2.0 * 2.0f32.sqrt());
+
+        let cross = cloud.cross_distance_matrix(&[0, 1], &[3]).unwrap();
+        assert_eq!(cross.shape(), &[2, 1]);
+        assert_approx_eq!(cross[[1, 0]], 2.0 * 2.0f32.sqrt());
+        assert_eq!(
+            cloud.cross_distance_matrix(&[0, 1], &[]).unwrap().shape(),
+            &[2, 0]
+        );
+        assert!(cloud.condensed_distance_matrix(&[3]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn tiled_forms_agree() {
+        let cloud = build_ram_random_test(150, 4);
+        let indexes: Vec<usize> = (0..150).rev().collect();
+        let matrix = cloud.distance_matrix(&indexes).unwrap();
+        let condensed = cloud.condensed_distance_matrix(&indexes).unwrap();
+        let cross = cloud.cross_distance_matrix(&indexes, &indexes).unwrap();
+        assert_eq!(condensed.len(), 150 * 149 / 2);
+        for i in 0..150 {
+            for j in 0..150 {
+                assert_eq!(matrix[[i, j]], matrix[[j, i]]);
+                if i < j {
+                    assert_eq!(matrix[[i, j]], condensed[condensed_index(150, i, j)]);
+                    assert_eq!(matrix[[i, j]], cross[[i, j]]);
+                }
+            }
+        }
+    }
+}
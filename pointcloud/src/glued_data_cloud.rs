@@ -3,9 +3,17 @@
 use crate::pc_errors::{PointCloudError, PointCloudResult};
 
 use crate::base_traits::*;
+use crate::data_sources::DataMemmap;
+use crate::pc_errors::ParsingError;
 
 use fxhash::FxBuildHasher;
 use hashbrown::HashMap;
+use std::fs;
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 
 /// For large numbers of underlying point clouds
 #[derive(Debug)]
@@ -162,12 +170,221 @@ impl<D: PointCloud> PointCloud for HashGluedCloud<D> {
     }
 }
 
+/// A point from a [`LazyGluedCloud`]. It holds the shard's map open until it is dropped, so it stays valid even if
+/// the shard is evicted in the meantime.
+#[derive(Debug)]
+pub struct ShardPoint<M> {
+    shard: Arc<DataMemmap<M>>,
+    index: usize,
+}
+
+impl<M: Metric<[f32]>> Deref for ShardPoint<M> {
+    type Target = [f32];
+    fn deref(&self) -> &[f32] {
+        // The index was checked against the shard when this was made
+        self.shard.point(self.index).unwrap()
+    }
+}
+
+impl<M: Metric<[f32]>> PointRef for ShardPoint<M> {
+    type DenseIter = std::vec::IntoIter<f32>;
+    fn dense(&self) -> Vec<f32> {
+        self.deref().to_vec()
+    }
+    fn dense_iter(&self) -> Self::DenseIter {
+        self.dense().into_iter()
+    }
+}
+
+#[derive(Debug)]
+struct ShardSlot<M> {
+    shard: RwLock<Option<Arc<DataMemmap<M>>>>,
+    last_used: AtomicU64,
+}
+
+impl<M> ShardSlot<M> {
+    fn get(&self, tick: u64) -> Option<Arc<DataMemmap<M>>> {
+        let shard = self.shard.read().unwrap().as_ref().map(Arc::clone);
+        if shard.is_some() {
+            self.last_used.store(tick, Ordering::Relaxed);
+        }
+        shard
+    }
+}
+
+/// For very large numbers of memmap files. Unlike [`HashGluedCloud`] the shards are only mapped when a point in them
+/// is accessed, and at most `max_open` of them are kept open. The least recently used shard is unmapped to make room.
+///
+/// The shard lengths are read from the file sizes, so nothing is opened on creation. Each shard has its own lock, so
+/// reading points from shards that are already open doesn't block other threads.
+#[derive(Debug)]
+pub struct LazyGluedCloud<M> {
+    paths: Vec<PathBuf>,
+    /// The global index of the first point of each shard, followed by the total length.
+    offsets: Vec<usize>,
+    dim: usize,
+    max_open: usize,
+    slots: Vec<ShardSlot<M>>,
+    tick: AtomicU64,
+    /// The shards that are open. This is only locked to open or close a shard.
+    open: Mutex<Vec<usize>>,
+    metric: PhantomData<M>,
+}
+
+impl<M: Metric<[f32]>> LazyGluedCloud<M> {
+    /// Creates a new one over the memmaps at the paths, in that order.
+    pub fn new(dim: usize, paths: &[PathBuf], max_open: usize) -> PointCloudResult<Self> {
+        assert!(max_open > 0, "Need to be able to open at least one shard");
+        let point_size = (dim * std::mem::size_of::<f32>()) as u64;
+        let mut offsets = Vec::with_capacity(paths.len() + 1);
+        let mut total = 0;
+        offsets.push(total);
+        for path in paths {
+            total += (fs::metadata(path)?.len() / point_size) as usize;
+            offsets.push(total);
+        }
+        Ok(LazyGluedCloud {
+            paths: paths.to_vec(),
+            offsets,
+            dim,
+            max_open,
+            slots: paths
+                .iter()
+                .map(|_| ShardSlot {
+                    shard: RwLock::new(None),
+                    last_used: AtomicU64::new(0),
+                })
+                .collect(),
+            tick: AtomicU64::new(0),
+            open: Mutex::new(Vec::new()),
+            metric: PhantomData,
+        })
+    }
+
+    /// The number of shards that are currently mapped.
+    pub fn open_shards(&self) -> usize {
+        self.open.lock().unwrap().len()
+    }
+
+    /// The paths of the shards, in order.
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    #[inline]
+    fn get_address(&self, pn: usize) -> PointCloudResult<(usize, usize)> {
+        if pn >= self.len() {
+            return Err(PointCloudError::DataAccessError {
+                index: pn,
+                reason: "address not found".to_string(),
+            });
+        }
+        // The last shard that starts at or before the point, this skips empty shards.
+        let i = self.offsets[..self.paths.len()].partition_point(|o| *o <= pn) - 1;
+        Ok((i, pn - self.offsets[i]))
+    }
+
+    fn shard(&self, i: usize) -> PointCloudResult<Arc<DataMemmap<M>>> {
+        let tick = self.tick.fetch_add(1, Ordering::Relaxed) + 1;
+        let slot = &self.slots[i];
+        if let Some(shard) = slot.get(tick) {
+            return Ok(shard);
+        }
+        let mut open = self.open.lock().unwrap();
+        // Another thread may have opened it while we waited for the lock
+        if let Some(shard) = slot.get(tick) {
+            return Ok(shard);
+        }
+        if open.len() >= self.max_open {
+            let oldest = (0..open.len())
+                .min_by_key(|j| self.slots[open[*j]].last_used.load(Ordering::Relaxed));
+            if let Some(oldest) = oldest {
+                let oldest = open.swap_remove(oldest);
+                *self.slots[oldest].shard.write().unwrap() = None;
+            }
+        }
+        let shard = Arc::new(DataMemmap::new(self.dim, &self.paths[i])?);
+        slot.last_used.store(tick, Ordering::Relaxed);
+        *slot.shard.write().unwrap() = Some(Arc::clone(&shard));
+        open.push(i);
+        Ok(shard)
+    }
+}
+
+impl<M: Metric<[f32]>> PointCloud for LazyGluedCloud<M> {
+    type Metric = M;
+    type Point = [f32];
+    type PointRef<'a> = ShardPoint<M>;
+    type LabelSummary = ();
+    type Label = ();
+    type MetaSummary = ();
+    type Metadata = ();
+
+    fn point<'a, 'b: 'a>(&'b self, pi: usize) -> PointCloudResult<ShardPoint<M>> {
+        let (i, j) = self.get_address(pi)?;
+        let shard = self.shard(i)?;
+        // Catches files that changed size after we read the offsets
+        shard.point(j)?;
+        Ok(ShardPoint { shard, index: j })
+    }
+
+    fn len(&self) -> usize {
+        self.offsets[self.paths.len()]
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn reference_indexes(&self) -> Vec<usize> {
+        (0..self.len()).collect()
+    }
+
+    fn dim(&self) -> usize {
+        self.dim
+    }
+
+    fn metadata(&self, _pn: usize) -> PointCloudResult<Option<&Self::Metadata>> {
+        Ok(None)
+    }
+    fn metasummary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::MetaSummary>> {
+        Ok(SummaryCounter {
+            summary: (),
+            nones: pns.len(),
+            errors: 0,
+        })
+    }
+    fn label(&self, _pn: usize) -> PointCloudResult<Option<&Self::Label>> {
+        Ok(None)
+    }
+    fn label_summary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::LabelSummary>> {
+        Ok(SummaryCounter {
+            summary: (),
+            nones: pns.len(),
+            errors: 0,
+        })
+    }
+
+    fn name(&self, pi: usize) -> PointCloudResult<String> {
+        Ok(pi.to_string())
+    }
+    fn index(&self, pn: &str) -> PointCloudResult<usize> {
+        pn.parse::<usize>().map_err(|_| {
+            ParsingError::RegularParsingError("Unable to parse your str into an usize").into()
+        })
+    }
+    fn names(&self) -> Vec<String> {
+        (0..self.len()).map(|i| i.to_string()).collect()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::data_sources::tests::*;
     use crate::data_sources::*;
     use crate::label_sources::*;
+    use crate::metrics::L2;
 
     pub fn build_glue_random_labeled_test(
         partitions: usize,
@@ -265,6 +482,54 @@ mod tests {
         assert_eq!(label_summary.summary.items[0], (1, 5));
     }
 
+    #[test]
+    fn lazy_shards_evict() {
+        use std::io::Write;
+        let dir = tempdir::TempDir::new("lazy_glued").unwrap();
+        let shard_lens = [3, 0, 2, 4, 1];
+        let mut paths = Vec::new();
+        let mut value = 0.0f32;
+        for (i, len) in shard_lens.iter().enumerate() {
+            let path = dir.path().join(format!("shard_{}.dat", i));
+            let mut file = fs::File::create(&path).unwrap();
+            for _ in 0..*len {
+                for _ in 0..2 {
+                    file.write_all(&value.to_le_bytes()).unwrap();
+                }
+                value += 1.0;
+            }
+            paths.push(path);
+        }
+
+        let pc = LazyGluedCloud::<L2>::new(2, &paths, 2).unwrap();
+        assert_eq!(pc.len(), 10);
+        assert_eq!(pc.open_shards(), 0);
+        assert_eq!(pc.get_address(3).unwrap(), (2, 0));
+        assert_eq!(pc.get_address(9).unwrap(), (4, 0));
+        assert!(pc.get_address(10).is_err());
+
+        let held = pc.point(0).unwrap();
+        for i in pc.reference_indexes() {
+            let point = pc.point(i).unwrap();
+            assert_eq!(&*point, &[i as f32, i as f32][..]);
+            assert!(pc.open_shards() <= 2);
+        }
+        // The first shard was evicted, but the point we held on to is still mapped
+        assert_eq!(&*held, &[0.0, 0.0][..]);
+
+        let dists = pc.distances_to_point_index(0, &[1, 9]).unwrap();
+        assert_approx_eq!(dists[0], 2.0f32.sqrt());
+        assert_approx_eq!(dists[1], 9.0 * 2.0f32.sqrt());
+
+        // Reading from many threads at once still keeps the bound
+        use rayon::prelude::*;
+        (0..1000).into_par_iter().for_each(|k| {
+            let i = (k * 7) % 10;
+            assert_eq!(&*pc.point(i).unwrap(), &[i as f32, i as f32][..]);
+        });
+        assert!(pc.open_shards() <= 2);
+    }
+
     #[test]
     fn distance_correct() {
         let pc = build_glue_fixed_test(5, 2, 3);
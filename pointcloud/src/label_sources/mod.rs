@@ -3,6 +3,7 @@
 use crate::base_traits::*;
 use crate::pc_errors::*;
 use crate::summaries::*;
+use hashbrown::HashMap;
 
 /// Labels for a small number of categories, using ints
 #[derive(Debug)]
@@ -222,3 +223,343 @@ impl LabelSet for VecLabels {
         })
     }
 }
+
+/// Labels where each point can belong to several classes, stored as a flat list of class ids with offsets.
+#[derive(Debug)]
+pub struct MultiLabels {
+    labels: Vec<i64>,
+    offsets: Vec<usize>,
+    mask: Option<Vec<bool>>,
+}
+
+impl MultiLabels {
+    /// Creates a new multi-label set from the class ids of each point.
+    pub fn new(label_sets: &[Vec<i64>], mask: Option<Vec<bool>>) -> MultiLabels {
+        let mut offsets = Vec::with_capacity(label_sets.len() + 1);
+        offsets.push(0);
+        let mut labels = Vec::new();
+        for set in label_sets {
+            labels.extend(set);
+            offsets.push(labels.len());
+        }
+        MultiLabels::from_offsets(labels, offsets, mask)
+    }
+
+    /// Creates a new multi-label set from a flat list of class ids, with the ids of point `i` in
+    /// `labels[offsets[i]..offsets[i+1]]`.
+    pub fn from_offsets(labels: Vec<i64>, offsets: Vec<usize>, mask: Option<Vec<bool>>) -> MultiLabels {
+        assert!(!offsets.is_empty() && offsets.is_sorted());
+        assert_eq!(*offsets.last().unwrap(), labels.len());
+        if let Some(mask) = &mask {
+            assert_eq!(mask.len() + 1, offsets.len());
+        }
+        MultiLabels {
+            labels,
+            offsets,
+            mask,
+        }
+    }
+
+    #[inline]
+    fn label_set(&self, pn: usize) -> Option<&[i64]> {
+        let start = self.offsets.get(pn)?;
+        let end = self.offsets.get(pn + 1)?;
+        self.labels.get(*start..*end)
+    }
+}
+
+impl LabelSet for MultiLabels {
+    type Label = [i64];
+    type LabelSummary = MultiCategorySummary;
+
+    fn len(&self) -> usize {
+        self.offsets.len() - 1
+    }
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    fn label(&self, pn: usize) -> PointCloudResult<Option<&[i64]>> {
+        if let Some(mask) = &self.mask {
+            if mask[pn] {
+                Ok(self.label_set(pn))
+            } else {
+                Ok(None)
+            }
+        } else {
+            Ok(self.label_set(pn))
+        }
+    }
+    fn label_summary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::LabelSummary>> {
+        let mut summary = SummaryCounter::default();
+        for i in pns {
+            summary.add(self.label(*i));
+        }
+        Ok(summary)
+    }
+}
+
+/// Scalar regression targets, one float per point.
+#[derive(Debug)]
+pub struct RegressionLabels {
+    labels: Vec<f32>,
+    mask: Option<Vec<bool>>,
+}
+
+impl RegressionLabels {
+    /// Creates a new regression label set.
+    pub fn new(labels: Vec<f32>, mask: Option<Vec<bool>>) -> RegressionLabels {
+        if let Some(mask) = &mask {
+            assert_eq!(mask.len(), labels.len());
+        }
+        RegressionLabels { labels, mask }
+    }
+}
+
+impl LabelSet for RegressionLabels {
+    type Label = f32;
+    type LabelSummary = RegressionSummary;
+
+    fn len(&self) -> usize {
+        self.labels.len()
+    }
+    fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+    fn label(&self, pn: usize) -> PointCloudResult<Option<&f32>> {
+        if let Some(mask) = &self.mask {
+            if mask[pn] {
+                Ok(self.labels.get(pn))
+            } else {
+                Ok(None)
+            }
+        } else {
+            Ok(self.labels.get(pn))
+        }
+    }
+    fn label_summary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::LabelSummary>> {
+        let mut summary = SummaryCounter::default();
+        for i in pns {
+            summary.add(self.label(*i));
+        }
+        Ok(summary)
+    }
+}
+
+/// The id given to masked string labels
+const UNKNOWN_CLASS: u32 = u32::MAX;
+
+/// Labels for a small number of named categories. Each class name is interned, so every point only stores an id.
+#[derive(Debug, Default)]
+pub struct StringLabels {
+    labels: Vec<u32>,
+    mask: Option<Vec<bool>>,
+    class_names: Vec<String>,
+    class_ids: HashMap<String, u32>,
+}
+
+impl StringLabels {
+    /// Creates a new string label set, interning the class names.
+    pub fn new<S: AsRef<str>>(labels: &[S], mask: Option<Vec<bool>>) -> StringLabels {
+        if let Some(mask) = &mask {
+            assert_eq!(mask.len(), labels.len());
+        }
+        let mut string_labels = StringLabels {
+            labels: Vec::with_capacity(labels.len()),
+            mask,
+            class_names: Vec::new(),
+            class_ids: HashMap::new(),
+        };
+        for (i, label) in labels.iter().enumerate() {
+            // Masked labels are not interned, so they don't show up as a class.
+            let id = match &string_labels.mask {
+                Some(mask) if !mask[i] => UNKNOWN_CLASS,
+                _ => string_labels.intern(label.as_ref()),
+            };
+            string_labels.labels.push(id);
+        }
+        string_labels
+    }
+
+    fn intern(&mut self, name: &str) -> u32 {
+        match self.class_ids.get(name) {
+            Some(id) => *id,
+            None => {
+                let id = self.class_names.len() as u32;
+                self.class_names.push(name.to_string());
+                self.class_ids.insert(name.to_string(), id);
+                id
+            }
+        }
+    }
+
+    /// The distinct class names, in the order they were first seen. The position is the class's id.
+    pub fn class_names(&self) -> &[String] {
+        &self.class_names
+    }
+
+    /// The interned id of a class name, if it is present.
+    pub fn class_id(&self, name: &str) -> Option<u32> {
+        self.class_ids.get(name).copied()
+    }
+
+    /// Merges 2 labels together, remapping the other's class ids into this one's.
+    pub fn merge(&mut self, other: &Self) {
+        let remap: Vec<u32> = other
+            .class_names
+            .iter()
+            .map(|name| self.intern(name))
+            .collect();
+        let old_len = self.labels.len();
+        self.labels.extend(
+            other
+                .labels
+                .iter()
+                .map(|id| *remap.get(*id as usize).unwrap_or(&UNKNOWN_CLASS)),
+        );
+        match (self.mask.as_mut(), other.mask.as_ref()) {
+            (Some(s_mask), Some(o_mask)) => s_mask.extend(o_mask),
+            (Some(s_mask), None) => s_mask.extend(vec![true; other.labels.len()]),
+            (None, Some(o_mask)) => {
+                let mut mask = vec![true; old_len];
+                mask.extend(o_mask);
+                self.mask = Some(mask);
+            }
+            (None, None) => {}
+        }
+    }
+
+    /// Converts this to an integer label set, using the interned ids as the labels.
+    pub fn to_int_labels(&self) -> SmallIntLabels {
+        SmallIntLabels::new(
+            self.labels.iter().map(|id| *id as i64).collect(),
+            self.mask.clone(),
+        )
+    }
+}
+
+impl LabelSet for StringLabels {
+    type Label = str;
+    type LabelSummary = StringCategorySummary;
+
+    fn len(&self) -> usize {
+        self.labels.len()
+    }
+    fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+    fn label(&self, pn: usize) -> PointCloudResult<Option<&str>> {
+        if let Some(mask) = &self.mask {
+            if !mask[pn] {
+                return Ok(None);
+            }
+        }
+        Ok(self
+            .labels
+            .get(pn)
+            .map(|id| self.class_names[*id as usize].as_str()))
+    }
+    fn label_summary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::LabelSummary>> {
+        // Count by id first so that we only touch the strings once per class.
+        let mut counts = vec![0; self.class_names.len()];
+        let mut nones = 0;
+        if let Some(mask) = &self.mask {
+            for i in pns {
+                if mask[*i] {
+                    counts[self.labels[*i] as usize] += 1;
+                } else {
+                    nones += 1;
+                }
+            }
+        } else {
+            for i in pns {
+                counts[self.labels[*i] as usize] += 1;
+            }
+        }
+        let mut summary = StringCategorySummary::default();
+        for (name, count) in self.class_names.iter().zip(counts) {
+            if count > 0 {
+                summary.add_count(name, count);
+            }
+        }
+        Ok(SummaryCounter {
+            summary,
+            nones,
+            errors: 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn multi_label_summary() {
+        let labels = MultiLabels::new(
+            &[vec![0, 1], vec![1], vec![], vec![2, 1, 0]],
+            Some(vec![true, true, true, false]),
+        );
+        assert_eq!(labels.len(), 4);
+        assert_eq!(labels.label(0).unwrap(), Some(&[0, 1][..]));
+        assert_eq!(labels.label(3).unwrap(), None);
+
+        let mut summary = labels.label_summary(&[0, 1]).unwrap();
+        summary.combine(&labels.label_summary(&[2, 3]).unwrap());
+        let summary = summary.summary;
+        assert_eq!(summary.count, 3);
+        assert!(summary.items.contains(&(1, 2)));
+        assert!(summary.items.contains(&(0, 1)));
+        assert_eq!(summary.min_cardinality, Some(0));
+        assert_eq!(summary.max_cardinality, Some(2));
+        assert_approx_eq!(summary.mean_cardinality().unwrap(), 1.0);
+    }
+
+    #[test]
+    fn string_labels_merge() {
+        let mut labels = StringLabels::new(&["benign", "trojan.generic", "benign"], None);
+        let other = StringLabels::new(
+            &["adware", "benign", "unknown"],
+            Some(vec![true, true, false]),
+        );
+        labels.merge(&other);
+        assert_eq!(labels.len(), 6);
+        assert_eq!(labels.class_names().len(), 3);
+        assert_eq!(labels.label(3).unwrap(), Some("adware"));
+        assert_eq!(labels.label(4).unwrap(), Some("benign"));
+        assert_eq!(labels.label(5).unwrap(), None);
+
+        let summary = labels.label_summary(&[0, 1, 2, 3, 4, 5]).unwrap();
+        assert_eq!(summary.nones, 1);
+        assert!(summary.summary.items.contains(&("benign".to_string(), 3)));
+        assert!(summary
+            .summary
+            .items
+            .contains(&("trojan.generic".to_string(), 1)));
+        assert_eq!(summary.summary.count(), 5);
+    }
+
+    #[test]
+    fn regression_summary() {
+        let values: Vec<f32> = (0..1000).map(|i| i as f32).collect();
+        let labels = RegressionLabels::new(values, None);
+        let indexes: Vec<usize> = (0..1000).collect();
+
+        let mut summary = labels.label_summary(&indexes[..400]).unwrap();
+        summary.combine(&labels.label_summary(&indexes[400..]).unwrap());
+        let summary = summary.summary;
+        let full = labels.label_summary(&indexes).unwrap().summary;
+
+        assert_eq!(summary.count, 1000);
+        assert_approx_eq!(summary.mean, 499.5);
+        assert_approx_eq!(summary.var().unwrap(), full.var().unwrap(), 1e-6);
+        assert_approx_eq!(summary.var().unwrap(), 83333.25, 1e-3);
+        assert_eq!(summary.quantile(0.0), Some(0.0));
+        assert_eq!(summary.quantile(1.0), Some(999.0));
+        let median = summary.quantile(0.5).unwrap();
+        assert!(
+            (median - 499.5).abs() < 1000.0 / 32.0,
+            "median was {}",
+            median
+        );
+    }
+}
@@ -44,7 +44,17 @@ pub mod data_sources;
 
 pub mod glued_data_cloud;
 
+pub mod brute_force;
+pub mod distance_matrix;
+pub mod moments;
+
+pub mod subset_cloud;
+pub mod transforms;
+pub mod validation;
+
 pub mod label_sources;
+pub mod meta_sources;
+pub mod name_sources;
 pub mod summaries;
 
 pub mod loaders;
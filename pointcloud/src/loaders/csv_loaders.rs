@@ -68,3 +68,48 @@ fn read_csv<P: AsRef<Path> + std::fmt::Debug, R: Read>(
         Ok(SmallIntLabels::new(labels, None))
     }
 }
+
+/// Opens a CSV and reads a single column from it as a string class label. Empty labels are treated as unlabeled and are masked.
+pub fn open_string_csv<P: AsRef<Path> + std::fmt::Debug>(
+    path: &P,
+    index: usize,
+) -> PointCloudResult<StringLabels> {
+    if !path.as_ref().exists() {
+        panic!("CSV file {:?} does not exist", path);
+    }
+
+    match File::open(path) {
+        Ok(file) => {
+            if path.as_ref().extension().unwrap() == "gz" {
+                read_string_csv(index, Reader::from_reader(GzDecoder::new(file)))
+            } else {
+                read_string_csv(index, Reader::from_reader(file))
+            }
+        }
+        Err(e) => panic!("Unable to open csv file {:#?}", e),
+    }
+}
+
+fn read_string_csv<R: Read>(index: usize, mut rdr: Reader<R>) -> PointCloudResult<StringLabels> {
+    let mut labels = Vec::new();
+    let mut mask = Vec::new();
+
+    for result in rdr.records() {
+        let record = result.expect("Unable to read a record from the label CSV");
+        match record.get(index) {
+            Some(val) if !val.is_empty() => {
+                labels.push(val.to_string());
+                mask.push(true);
+            }
+            _ => {
+                labels.push(String::new());
+                mask.push(false);
+            }
+        }
+    }
+    if mask.iter().any(|f| !f) {
+        Ok(StringLabels::new(&labels, Some(mask)))
+    } else {
+        Ok(StringLabels::new(&labels, None))
+    }
+}
@@ -51,6 +51,15 @@ pub fn open_memmaps<M: Metric<[f32]>>(
     Ok(HashGluedCloud::new(collection?))
 }
 
+/// Opens a set of memmaps of just data lazily, keeping at most `max_open` of them mapped at a time.
+pub fn open_memmaps_lazy<M: Metric<[f32]>>(
+    data_dim: usize,
+    data_paths: &[PathBuf],
+    max_open: usize,
+) -> PointCloudResult<LazyGluedCloud<M>> {
+    LazyGluedCloud::new(data_dim, data_paths, max_open)
+}
+
 /// Concatenates a glued data memmap to a single ram dataset
 pub fn convert_glued_memmap_to_ram<M: Metric<[f32]>>(
     glued_cloud: HashGluedCloud<DataMemmap<M>>,
@@ -180,6 +180,70 @@ pub fn labels_from_yaml<P: AsRef<Path>>(path: P) -> PointCloudResult<SmallIntLab
         .unwrap())
 }
 
+/// Given a yaml file on disk, it builds a point cloud with string class labels. The labels are read from the
+/// `labels_index` column of the label CSVs. Minimal example below.
+/// ```yaml
+/// ---
+/// data_path: DATAMEMMAP
+/// labels_path: LABELS_CSV
+/// count: NUMBER_OF_DATA_POINTS
+/// data_dim: 784
+/// labels_index: 2
+/// ```
+pub fn string_labeled_ram_from_yaml<P: AsRef<Path>, M: Metric<[f32]>>(
+    path: P,
+) -> PointCloudResult<SimpleLabeledCloud<DataRam<M>, StringLabels>> {
+    let label_set = string_labels_from_yaml(&path)?;
+    let data_set = ram_from_yaml(&path)?;
+
+    Ok(SimpleLabeledCloud::new(data_set, label_set))
+}
+
+/// Given a yaml file on disk, it reads string class labels from the CSVs in `labels_path`. Minimal example below.
+/// ```yaml
+/// ---
+/// labels_path: LABELS_CSV
+/// labels_index: 2
+/// ```
+pub fn string_labels_from_yaml<P: AsRef<Path>>(path: P) -> PointCloudResult<StringLabels> {
+    info!("Opening string labels yaml with path {:?}", path.as_ref());
+    let config = fs::read_to_string(&path)
+        .unwrap_or_else(|_| panic!("Unable to read config file {:?}", path.as_ref()));
+    let path: &Path = path.as_ref();
+    let params_files = &YamlLoader::load_from_str(&config).unwrap()[0];
+
+    let labels_path = &get_file_list(
+        params_files["labels_path"]
+            .as_str()
+            .expect("Unable to read the 'labels_path'"),
+        path,
+    );
+    trace!("Label path list, post glob: {:?}", labels_path);
+
+    let labels_index = params_files["labels_index"]
+        .as_i64()
+        .expect("Unable to read the 'labels_index'") as usize;
+
+    let mut label_set = labels_path
+        .iter()
+        .map(|path| {
+            info!("Opening label file with path {:?}", path);
+            match path.extension().unwrap().to_str().unwrap() {
+                "csv" | "gz" => open_string_csv(&path, labels_index),
+                _ => panic!("String labels need to be in a csv, got {:?}", path),
+            }
+        })
+        .collect::<PointCloudResult<Vec<StringLabels>>>()?;
+
+    Ok(label_set
+        .drain(0..)
+        .reduce(|mut a, b| {
+            a.merge(&b);
+            a
+        })
+        .unwrap())
+}
+
 fn get_file_list(files_reg: &str, yaml_path: &Path) -> Vec<PathBuf> {
     let options = MatchOptions {
         case_sensitive: false,
@@ -223,3 +287,39 @@ fn get_file_list(files_reg: &str, yaml_path: &Path) -> Vec<PathBuf> {
     }
     paths
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempdir::TempDir;
+
+    #[test]
+    fn string_labels_yaml_round_trip() {
+        let dir = TempDir::new("string_labels").unwrap();
+        let data: Vec<f32> = (0..8).map(|i| i as f32).collect();
+        let mut data_file = fs::File::create(dir.path().join("data.dat")).unwrap();
+        for x in &data {
+            data_file.write_all(&x.to_ne_bytes()).unwrap();
+        }
+        fs::write(
+            dir.path().join("labels.csv"),
+            "name,class\na,benign\nb,trojan.generic\nc,\nd,benign\n",
+        )
+        .unwrap();
+        let yaml_path = dir.path().join("config.yml");
+        fs::write(
+            &yaml_path,
+            "---\ndata_path: data.dat\nlabels_path: labels.csv\ncount: 4\ndata_dim: 2\nlabels_index: 1\n",
+        )
+        .unwrap();
+
+        let cloud = string_labeled_ram_from_yaml::<_, L2>(&yaml_path).unwrap();
+        assert_eq!(cloud.len(), 4);
+        assert_eq!(cloud.label(1).unwrap(), Some("trojan.generic"));
+        assert_eq!(cloud.label(2).unwrap(), None);
+        let summary = cloud.label_summary(&[0, 1, 2, 3]).unwrap();
+        assert_eq!(summary.nones, 1);
+        assert!(summary.summary.items.contains(&("benign".to_string(), 2)));
+    }
+}
@@ -0,0 +1,253 @@
+//! Some metadata sets to modularly glue together with the data sources. See [`SimpleMetaCloud`].
+
+use hashbrown::HashMap;
+
+use crate::base_traits::*;
+use crate::pc_errors::*;
+use crate::summaries::*;
+
+#[inline]
+fn out_of_bounds(pn: usize) -> PointCloudError {
+    PointCloudError::DataAccessError {
+        index: pn,
+        reason: "metadata index out of bounds".to_string(),
+    }
+}
+
+/// Per-point timestamps, in whatever integer unit you like (seconds since the epoch is sensible).
+#[derive(Debug)]
+pub struct TimestampMeta {
+    timestamps: Vec<i64>,
+    mask: Option<Vec<bool>>,
+}
+
+impl TimestampMeta {
+    /// Creates a new timestamp set. The mask marks which timestamps are known.
+    pub fn new(timestamps: Vec<i64>, mask: Option<Vec<bool>>) -> TimestampMeta {
+        if let Some(mask) = &mask {
+            assert_eq!(mask.len(), timestamps.len());
+        }
+        TimestampMeta { timestamps, mask }
+    }
+}
+
+impl MetaSet for TimestampMeta {
+    type Metadata = i64;
+    type MetaSummary = TimeRangeSummary;
+
+    fn len(&self) -> usize {
+        self.timestamps.len()
+    }
+    fn is_empty(&self) -> bool {
+        self.timestamps.is_empty()
+    }
+    fn metadata(&self, pn: usize) -> PointCloudResult<Option<&i64>> {
+        let timestamp = self.timestamps.get(pn).ok_or_else(|| out_of_bounds(pn))?;
+        match &self.mask {
+            Some(mask) if !mask[pn] => Ok(None),
+            _ => Ok(Some(timestamp)),
+        }
+    }
+    fn metasummary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<TimeRangeSummary>> {
+        let mut summary = SummaryCounter::default();
+        for i in pns {
+            summary.add(self.metadata(*i));
+        }
+        Ok(summary)
+    }
+}
+
+/// Categorical string tags. Each distinct tag is stored once and the points refer to it by id.
+#[derive(Debug)]
+pub struct TagMeta {
+    tag_names: Vec<String>,
+    tag_ids: HashMap<String, u32>,
+    tags: Vec<Option<u32>>,
+}
+
+impl TagMeta {
+    /// Creates a new tag set from the per-point tags, interning them as it goes. A `None` is an untagged point.
+    pub fn new<S: AsRef<str>>(tags: &[Option<S>]) -> TagMeta {
+        let mut meta = TagMeta {
+            tag_names: Vec::new(),
+            tag_ids: HashMap::new(),
+            tags: Vec::with_capacity(tags.len()),
+        };
+        for tag in tags {
+            meta.push(tag.as_ref().map(|t| t.as_ref()));
+        }
+        meta
+    }
+
+    /// Adds a tag for the next point, interning it if we haven't seen it before.
+    pub fn push(&mut self, tag: Option<&str>) {
+        let id = tag.map(|t| self.intern(t));
+        self.tags.push(id);
+    }
+
+    fn intern(&mut self, tag: &str) -> u32 {
+        match self.tag_ids.get(tag) {
+            Some(id) => *id,
+            None => {
+                let id = self.tag_names.len() as u32;
+                self.tag_names.push(tag.to_string());
+                self.tag_ids.insert(tag.to_string(), id);
+                id
+            }
+        }
+    }
+
+    /// The distinct tags, in the order they were first seen. The position is the tag's id.
+    pub fn tag_names(&self) -> &[String] {
+        &self.tag_names
+    }
+
+    /// The interned id of a tag, if it is present.
+    pub fn tag_id(&self, tag: &str) -> Option<u32> {
+        self.tag_ids.get(tag).copied()
+    }
+
+    /// The interned id of a point's tag.
+    pub fn point_tag_id(&self, pn: usize) -> PointCloudResult<Option<u32>> {
+        self.tags.get(pn).copied().ok_or_else(|| out_of_bounds(pn))
+    }
+}
+
+impl MetaSet for TagMeta {
+    type Metadata = String;
+    type MetaSummary = StringSummary;
+
+    fn len(&self) -> usize {
+        self.tags.len()
+    }
+    fn is_empty(&self) -> bool {
+        self.tags.is_empty()
+    }
+    fn metadata(&self, pn: usize) -> PointCloudResult<Option<&String>> {
+        Ok(self
+            .point_tag_id(pn)?
+            .map(|id| &self.tag_names[id as usize]))
+    }
+    fn metasummary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<StringSummary>> {
+        // Count by id first so that we only touch the strings once per distinct tag.
+        let mut counts = vec![0; self.tag_names.len()];
+        let mut nones = 0;
+        let mut errors = 0;
+        for i in pns {
+            match self.tags.get(*i) {
+                Some(Some(id)) => counts[*id as usize] += 1,
+                Some(None) => nones += 1,
+                None => errors += 1,
+            }
+        }
+        let items = counts
+            .iter()
+            .zip(&self.tag_names)
+            .filter(|(c, _)| **c > 0)
+            .map(|(c, name)| (name.clone(), *c))
+            .collect();
+        Ok(SummaryCounter {
+            summary: StringSummary { items },
+            nones,
+            errors,
+        })
+    }
+}
+
+/// A numeric attribute per point, such as a file size or a score.
+#[derive(Debug)]
+pub struct NumericMeta {
+    values: Vec<f64>,
+    mask: Option<Vec<bool>>,
+}
+
+impl NumericMeta {
+    /// Creates a new numeric attribute set. The mask marks which values are known.
+    pub fn new(values: Vec<f64>, mask: Option<Vec<bool>>) -> NumericMeta {
+        if let Some(mask) = &mask {
+            assert_eq!(mask.len(), values.len());
+        }
+        NumericMeta { values, mask }
+    }
+}
+
+impl MetaSet for NumericMeta {
+    type Metadata = f64;
+    type MetaSummary = FloatSummary;
+
+    fn len(&self) -> usize {
+        self.values.len()
+    }
+    fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+    fn metadata(&self, pn: usize) -> PointCloudResult<Option<&f64>> {
+        let value = self.values.get(pn).ok_or_else(|| out_of_bounds(pn))?;
+        match &self.mask {
+            Some(mask) if !mask[pn] => Ok(None),
+            _ => Ok(Some(value)),
+        }
+    }
+    fn metasummary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<FloatSummary>> {
+        let mut summary = SummaryCounter::default();
+        for i in pns {
+            summary.add(self.metadata(*i));
+        }
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_sources::tests::build_ram_fixed_test;
+
+    #[test]
+    fn timestamp_summary() {
+        let meta = TimestampMeta::new(vec![5, 3, 9, 7], Some(vec![true, true, true, false]));
+        let summary = meta.metasummary(&[0, 1, 2, 3]).unwrap();
+        assert_eq!(summary.summary.min, Some(3));
+        assert_eq!(summary.summary.max, Some(9));
+        assert_eq!(summary.summary.duration(), Some(6));
+        assert_eq!(summary.nones, 1);
+        assert_eq!(summary.count(), 4);
+        assert!(meta.metadata(10).is_err());
+    }
+
+    #[test]
+    fn tag_interning() {
+        let meta = TagMeta::new(&[Some("cat"), Some("dog"), None, Some("cat")]);
+        assert_eq!(meta.tag_names().len(), 2);
+        assert_eq!(meta.tag_id("cat"), Some(0));
+        assert_eq!(meta.metadata(3).unwrap(), Some(&"cat".to_string()));
+        assert_eq!(meta.metadata(2).unwrap(), None);
+
+        let summary = meta.metasummary(&[0, 1, 2, 3, 10]).unwrap();
+        assert_eq!(summary.summary.items.get("cat"), Some(&2));
+        assert_eq!(summary.summary.items.get("dog"), Some(&1));
+        assert_eq!(summary.nones, 1);
+        assert_eq!(summary.errors, 1);
+    }
+
+    #[test]
+    fn numeric_summary_combine() {
+        let meta = NumericMeta::new(vec![1.0, -2.0, 4.0, 5.0], None);
+        let mut summary = meta.metasummary(&[0, 1]).unwrap();
+        summary.combine(&meta.metasummary(&[2, 3]).unwrap());
+        assert_eq!(summary.summary.min, Some(-2.0));
+        assert_eq!(summary.summary.max, Some(5.0));
+        assert_approx_eq!(summary.summary.mean().unwrap(), 2.0);
+    }
+
+    #[test]
+    fn meta_cloud_forwards() {
+        let data = build_ram_fixed_test(10, 3);
+        let timestamps: Vec<i64> = (0..data.len() as i64).collect();
+        let cloud = SimpleMetaCloud::new(data, TimestampMeta::new(timestamps, None));
+        let indexes = cloud.reference_indexes();
+        let summary = cloud.metasummary(&indexes).unwrap();
+        assert_eq!(summary.summary.count, cloud.len());
+        assert_eq!(cloud.metadata(2).unwrap(), Some(&2));
+        assert_eq!(cloud.point(2).unwrap().len(), cloud.dim());
+    }
+}
@@ -117,24 +117,28 @@ macro_rules! make_l2_distance {
             }
         }
         impl Metric<[$base]> for L2 {
+            const NAME: &'static str = "l2";
             fn dist(x: &[$base], y: &[$base]) -> f32 {
                 $dist_base(x.deref(), y.deref()).sqrt()
             }
         }
 
         impl<'a> Metric<RawSparse<$base, u32>> for L2 {
+            const NAME: &'static str = "l2";
             fn dist(x: &RawSparse<$base, u32>, y: &RawSparse<$base, u32>) -> f32 {
                 $sparse_base(x.indexes(), x.values(), y.indexes(), y.values()).sqrt()
             }
         }
 
         impl<'a> Metric<RawSparse<$base, u16>> for L2 {
+            const NAME: &'static str = "l2";
             fn dist(x: &RawSparse<$base, u16>, y: &RawSparse<$base, u16>) -> f32 {
                 $sparse_base(x.indexes(), x.values(), y.indexes(), y.values()).sqrt()
             }
         }
 
         impl<'a> Metric<RawSparse<$base, u8>> for L2 {
+            const NAME: &'static str = "l2";
             fn dist(x: &RawSparse<$base, u8>, y: &RawSparse<$base, u8>) -> f32 {
                 $sparse_base(x.indexes(), x.values(), y.indexes(), y.values()).sqrt()
             }
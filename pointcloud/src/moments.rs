@@ -0,0 +1,459 @@
+//! Numerically stable, mergeable moments of a set of points.
+//!
+//! These keep a running mean and the sums of powers of the deviations from it, rather than raw power sums, so they
+//! stay accurate for data far from the origin. Partial results computed on different shards or threads can be merged
+//! exactly with [`Moments::merge`] and [`Covariance::merge`]. The merge formulas are from Pébay's "Formulas for Robust,
+//! One-Pass Parallel Computation of Covariances and Arbitrary-Order Statistical Moments", and handle real valued
+//! weights.
+
+use ndarray::Array2;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::base_traits::*;
+use crate::pc_errors::*;
+
+/// The number of points each thread adds up before the partial moments are merged.
+const MOMENT_CHUNK: usize = 1000;
+
+/// The central moments of one coordinate: the mean and the sums of the 2nd, 3rd and 4th powers of the deviations.
+type Central = (f64, f64, f64, f64);
+
+/// Merges the central moments of two sets with total weights `na` and `nb`.
+#[inline]
+fn merge_central(na: f64, a: Central, nb: f64, b: Central) -> Central {
+    let n = na + nb;
+    if n == 0.0 {
+        return a;
+    }
+    let delta = b.0 - a.0;
+    let delta_n = delta / n;
+    let mean = a.0 + nb * delta_n;
+    let m2 = a.1 + b.1 + delta * delta_n * na * nb;
+    let m3 = a.2
+        + b.2
+        + delta * delta_n * delta_n * na * nb * (na - nb)
+        + 3.0 * delta_n * (na * b.1 - nb * a.1);
+    let m4 = a.3
+        + b.3
+        + delta * delta_n * delta_n * delta_n * na * nb * (na * na - na * nb + nb * nb)
+        + 6.0 * delta_n * delta_n * (na * na * b.1 + nb * nb * a.1)
+        + 4.0 * delta_n * (na * b.2 - nb * a.2);
+    (mean, m2, m3, m4)
+}
+
+/// Per coordinate weighted mean, variance, skewness and kurtosis of a set of points.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Moments {
+    weight: f64,
+    mean: Vec<f64>,
+    m2: Vec<f64>,
+    m3: Vec<f64>,
+    m4: Vec<f64>,
+}
+
+impl Moments {
+    /// The moments of the empty set.
+    pub fn new(dim: usize) -> Moments {
+        Moments {
+            weight: 0.0,
+            mean: vec![0.0; dim],
+            m2: vec![0.0; dim],
+            m3: vec![0.0; dim],
+            m4: vec![0.0; dim],
+        }
+    }
+
+    /// Dimension of the points
+    pub fn dim(&self) -> usize {
+        self.mean.len()
+    }
+
+    /// The total weight of the points added, for unweighted moments this is the number of points.
+    pub fn weight(&self) -> f64 {
+        self.weight
+    }
+
+    /// Adds a point with weight 1.
+    pub fn add<T: PointRef>(&mut self, point: &T) {
+        self.add_weighted(point, 1.0)
+    }
+
+    /// Adds a point with the given weight.
+    pub fn add_weighted<T: PointRef>(&mut self, point: &T, weight: f64) {
+        if weight == 0.0 {
+            return;
+        }
+        if self.mean.is_empty() {
+            *self = Moments::new(point.dense_iter().count());
+        }
+        for (i, x) in point.dense_iter().enumerate() {
+            let (mean, m2, m3, m4) = merge_central(
+                self.weight,
+                (self.mean[i], self.m2[i], self.m3[i], self.m4[i]),
+                weight,
+                (x as f64, 0.0, 0.0, 0.0),
+            );
+            self.mean[i] = mean;
+            self.m2[i] = m2;
+            self.m3[i] = m3;
+            self.m4[i] = m4;
+        }
+        self.weight += weight;
+    }
+
+    /// Removes a point with weight 1 that was previously added.
+    pub fn remove<T: PointRef>(&mut self, point: &T) {
+        self.remove_weighted(point, 1.0)
+    }
+
+    /// Removes a point that was previously added with the given weight. This inverts [`Moments::add_weighted`].
+    pub fn remove_weighted<T: PointRef>(&mut self, point: &T, weight: f64) {
+        let n = self.weight;
+        let na = n - weight;
+        if na <= 0.0 {
+            *self = Moments::new(self.dim());
+            return;
+        }
+        for (i, x) in point.dense_iter().enumerate() {
+            let x = x as f64;
+            let mean_a = (n * self.mean[i] - weight * x) / na;
+            let delta = x - mean_a;
+            let delta_n = delta / n;
+            // Undo the merge with a single point, lowest order first as the higher orders depend on them
+            let m2_a = self.m2[i] - delta * delta_n * na * weight;
+            let delta_n2 = delta_n * delta_n;
+            let weight_term = na * weight * (na * na - na * weight + weight * weight);
+            let m3_a = self.m3[i] - delta * delta_n2 * na * weight * (na - weight)
+                + 3.0 * delta_n * weight * m2_a;
+            let m4_a = self.m4[i]
+                - delta * delta_n2 * delta_n * weight_term
+                - 6.0 * delta_n2 * weight * weight * m2_a
+                + 4.0 * delta_n * weight * m3_a;
+            self.mean[i] = mean_a;
+            self.m2[i] = m2_a.max(0.0);
+            self.m3[i] = m3_a;
+            self.m4[i] = m4_a.max(0.0);
+        }
+        self.weight = na;
+    }
+
+    /// Merges the moments of another set into this one. The result is the moments of the union.
+    pub fn merge(&mut self, other: &Moments) {
+        if other.weight == 0.0 {
+            return;
+        }
+        if self.weight == 0.0 {
+            *self = other.clone();
+            return;
+        }
+        for i in 0..self.dim() {
+            let (mean, m2, m3, m4) = merge_central(
+                self.weight,
+                (self.mean[i], self.m2[i], self.m3[i], self.m4[i]),
+                other.weight,
+                (other.mean[i], other.m2[i], other.m3[i], other.m4[i]),
+            );
+            self.mean[i] = mean;
+            self.m2[i] = m2;
+            self.m3[i] = m3;
+            self.m4[i] = m4;
+        }
+        self.weight += other.weight;
+    }
+
+    /// The weighted mean
+    pub fn mean(&self) -> Vec<f32> {
+        self.mean.iter().map(|m| *m as f32).collect()
+    }
+
+    /// The weighted population variance, `m2 / weight`.
+    pub fn variance(&self) -> Vec<f32> {
+        if self.weight > 0.0 {
+            self.m2.iter().map(|m2| (m2 / self.weight) as f32).collect()
+        } else {
+            vec![0.0; self.dim()]
+        }
+    }
+
+    /// The population skewness, `sqrt(weight) m3 / m2^(3/2)`. This is NaN for constant coordinates.
+    pub fn skewness(&self) -> Vec<f32> {
+        self.m2
+            .iter()
+            .zip(&self.m3)
+            .map(|(m2, m3)| (self.weight.sqrt() * m3 / m2.powf(1.5)) as f32)
+            .collect()
+    }
+
+    /// The population excess kurtosis, `weight m4 / m2^2 - 3`. This is NaN for constant coordinates.
+    pub fn kurtosis(&self) -> Vec<f32> {
+        self.m2
+            .iter()
+            .zip(&self.m4)
+            .map(|(m2, m4)| (self.weight * m4 / (m2 * m2) - 3.0) as f32)
+            .collect()
+    }
+}
+
+/// The weighted mean and full covariance matrix of a set of points.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Covariance {
+    weight: f64,
+    mean: Vec<f64>,
+    /// The sums of the products of the deviations, row major.
+    comoment: Vec<f64>,
+}
+
+impl Covariance {
+    /// The covariance of the empty set.
+    pub fn new(dim: usize) -> Covariance {
+        Covariance {
+            weight: 0.0,
+            mean: vec![0.0; dim],
+            comoment: vec![0.0; dim * dim],
+        }
+    }
+
+    /// Dimension of the points
+    pub fn dim(&self) -> usize {
+        self.mean.len()
+    }
+
+    /// The total weight of the points added.
+    pub fn weight(&self) -> f64 {
+        self.weight
+    }
+
+    /// Adds a point with weight 1.
+    pub fn add<T: PointRef>(&mut self, point: &T) {
+        self.add_weighted(point, 1.0)
+    }
+
+    /// Adds a point with the given weight.
+    pub fn add_weighted<T: PointRef>(&mut self, point: &T, weight: f64) {
+        if weight == 0.0 {
+            return;
+        }
+        let x: Vec<f64> = point.dense_iter().map(|x| x as f64).collect();
+        if self.mean.is_empty() {
+            *self = Covariance::new(x.len());
+        }
+        let n = self.weight + weight;
+        let delta: Vec<f64> = x.iter().zip(&self.mean).map(|(x, m)| x - m).collect();
+        for (m, d) in self.mean.iter_mut().zip(&delta) {
+            *m += d * weight / n;
+        }
+        let dim = self.dim();
+        for (row, di) in self.comoment.chunks_mut(dim).zip(&delta) {
+            for ((c, xj), mj) in row.iter_mut().zip(&x).zip(&self.mean) {
+                *c += weight * di * (xj - mj);
+            }
+        }
+        self.weight = n;
+    }
+
+    /// Merges the covariance of another set into this one.
+    pub fn merge(&mut self, other: &Covariance) {
+        if other.weight == 0.0 {
+            return;
+        }
+        if self.weight == 0.0 {
+            *self = other.clone();
+            return;
+        }
+        let n = self.weight + other.weight;
+        let scale = self.weight * other.weight / n;
+        let delta: Vec<f64> = other
+            .mean
+            .iter()
+            .zip(&self.mean)
+            .map(|(b, a)| b - a)
+            .collect();
+        let dim = self.dim();
+        let rows = self
+            .comoment
+            .chunks_mut(dim)
+            .zip(other.comoment.chunks(dim));
+        for ((row, other_row), di) in rows.zip(&delta) {
+            for ((c, other_c), dj) in row.iter_mut().zip(other_row).zip(&delta) {
+                *c += other_c + di * dj * scale;
+            }
+        }
+        for (m, d) in self.mean.iter_mut().zip(&delta) {
+            *m += d * other.weight / n;
+        }
+        self.weight = n;
+    }
+
+    /// The weighted mean
+    pub fn mean(&self) -> Vec<f32> {
+        self.mean.iter().map(|m| *m as f32).collect()
+    }
+
+    /// The weighted population covariance matrix, `comoment / weight`.
+    pub fn covariance(&self) -> Array2<f32> {
+        let dim = self.dim();
+        let weight = if self.weight > 0.0 { self.weight } else { 1.0 };
+        Array2::from_shape_vec(
+            (dim, dim),
+            self.comoment.iter().map(|c| (c / weight) as f32).collect(),
+        )
+        .unwrap()
+    }
+}
+
+/// The moments of the points at the indexes, with optional weights. See [`PointCloud::moments`].
+pub fn moments<D: PointCloud>(
+    point_cloud: &D,
+    indexes: &[usize],
+    weights: Option<&[f32]>,
+) -> PointCloudResult<Moments> {
+    if let Some(weights) = weights {
+        assert_eq!(indexes.len(), weights.len());
+    }
+    let dim = point_cloud.dim();
+    let partials = indexes
+        .par_chunks(MOMENT_CHUNK)
+        .enumerate()
+        .map(|(c, chunk)| {
+            let mut moments = Moments::new(dim);
+            for (k, i) in chunk.iter().enumerate() {
+                let weight = weights
+                    .map(|w| w[c * MOMENT_CHUNK + k] as f64)
+                    .unwrap_or(1.0);
+                moments.add_weighted(&point_cloud.point(*i)?, weight);
+            }
+            Ok(moments)
+        })
+        .collect::<PointCloudResult<Vec<Moments>>>()?;
+    Ok(partials.iter().fold(Moments::new(dim), |mut a, b| {
+        a.merge(b);
+        a
+    }))
+}
+
+/// The covariance of the points at the indexes. See [`PointCloud::covariance`].
+pub fn covariance<D: PointCloud>(
+    point_cloud: &D,
+    indexes: &[usize],
+) -> PointCloudResult<Covariance> {
+    let dim = point_cloud.dim();
+    let partials = indexes
+        .par_chunks(MOMENT_CHUNK)
+        .map(|chunk| {
+            let mut covariance = Covariance::new(dim);
+            for i in chunk {
+                covariance.add(&point_cloud.point(*i)?);
+            }
+            Ok(covariance)
+        })
+        .collect::<PointCloudResult<Vec<Covariance>>>()?;
+    Ok(partials.iter().fold(Covariance::new(dim), |mut a, b| {
+        a.merge(b);
+        a
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_sources::tests::build_ram_random_test;
+    use crate::data_sources::DataRam;
+    use crate::metrics::L2;
+
+    #[test]
+    fn moments_known_values() {
+        // 1, 2, 3, 10 offset far from the origin
+        let data: Vec<f32> = [1.0, 2.0, 3.0, 10.0].iter().map(|x| x + 10000.0).collect();
+        let cloud = DataRam::<L2>::new(data, 1).unwrap();
+        let moments = cloud.moments(&[0, 1, 2, 3]).unwrap();
+        assert_approx_eq!(moments.mean()[0], 10004.0);
+        assert_approx_eq!(moments.variance()[0], 12.5);
+        // The deviations are -3, -2, -1, 6, so m2 = 50, m3 = 180 and m4 = 1394
+        assert_approx_eq!(moments.skewness()[0], 2.0 * 180.0 / 50.0f32.powf(1.5), 1e-4);
+        assert_approx_eq!(moments.kurtosis()[0], 4.0 * 1394.0 / 2500.0 - 3.0, 1e-3);
+
+        // A weight of 2 on a point is the same as adding it twice
+        let weighted = cloud
+            .weighted_moments(&[0, 1, 2, 3], &[2.0, 1.0, 1.0, 1.0])
+            .unwrap();
+        let doubled = cloud.moments(&[0, 0, 1, 2, 3]).unwrap();
+        for (a, b) in weighted.variance().iter().zip(doubled.variance()) {
+            assert_approx_eq!(a, b, 1e-3);
+        }
+        for (a, b) in weighted.kurtosis().iter().zip(doubled.kurtosis()) {
+            assert_approx_eq!(a, b, 1e-3);
+        }
+    }
+
+    #[test]
+    fn moments_merge_and_remove() {
+        let cloud = build_ram_random_test(2500, 3);
+        let indexes = cloud.reference_indexes();
+        let all = cloud.moments(&indexes).unwrap();
+        let mut merged = cloud.moments(&indexes[..700]).unwrap();
+        merged.merge(&cloud.moments(&indexes[700..]).unwrap());
+        assert_approx_eq!(all.weight(), merged.weight());
+        for i in 0..3 {
+            assert_approx_eq!(all.mean()[i], merged.mean()[i]);
+            assert_approx_eq!(all.variance()[i], merged.variance()[i]);
+            assert_approx_eq!(all.skewness()[i], merged.skewness()[i], 1e-3);
+            assert_approx_eq!(all.kurtosis()[i], merged.kurtosis()[i], 1e-3);
+        }
+
+        let mut removed = all.clone();
+        removed.remove(&cloud.point(2499).unwrap());
+        let expected = cloud.moments(&indexes[..2499]).unwrap();
+        for i in 0..3 {
+            assert_approx_eq!(expected.mean()[i], removed.mean()[i]);
+            assert_approx_eq!(expected.variance()[i], removed.variance()[i]);
+            assert_approx_eq!(expected.skewness()[i], removed.skewness()[i], 1e-3);
+            assert_approx_eq!(expected.kurtosis()[i], removed.kurtosis()[i], 1e-3);
+        }
+    }
+
+    #[test]
+    fn covariance_matches_variance() {
+        let data = vec![1.0, 2.0, 2.0, 4.0, 3.0, 6.5, 4.0, 8.0];
+        let cloud = DataRam::<L2>::new(data, 2).unwrap();
+        let indexes = cloud.reference_indexes();
+        let covariance = cloud.covariance(&indexes).unwrap().covariance();
+        let variance = cloud.moments(&indexes).unwrap().variance();
+        assert_approx_eq!(covariance[[0, 0]], variance[0]);
+        assert_approx_eq!(covariance[[1, 1]], variance[1]);
+        assert_approx_eq!(covariance[[0, 1]], covariance[[1, 0]]);
+        // mean of x is 2.5, mean of y is 5.125
+        let expected: f32 = [(-1.5, -3.125), (-0.5, -1.125), (0.5, 1.375), (1.5, 2.875)]
+            .iter()
+            .map(|(x, y): &(f32, f32)| x * y)
+            .sum::<f32>()
+            / 4.0;
+        assert_approx_eq!(covariance[[0, 1]], expected);
+
+        let mut merged = cloud.covariance(&indexes[..1]).unwrap();
+        merged.merge(&cloud.covariance(&indexes[1..]).unwrap());
+        assert_approx_eq!(merged.covariance()[[0, 1]], expected);
+    }
+
+    #[test]
+    fn zero_weights_are_ignored() {
+        let data = vec![1.0, 2.0, 3.0, 5.0];
+        let cloud = DataRam::<L2>::new(data, 2).unwrap();
+        let mut moments = Moments::new(2);
+        let mut covariance = Covariance::new(2);
+        moments.add_weighted(&cloud.point(0).unwrap(), 0.0);
+        covariance.add_weighted(&cloud.point(0).unwrap(), 0.0);
+        assert_eq!(moments, Moments::new(2));
+        assert_eq!(covariance, Covariance::new(2));
+
+        moments.add(&cloud.point(0).unwrap());
+        moments.add(&cloud.point(1).unwrap());
+        covariance.add(&cloud.point(0).unwrap());
+        covariance.add(&cloud.point(1).unwrap());
+        moments.add_weighted(&cloud.point(1).unwrap(), 0.0);
+        covariance.add_weighted(&cloud.point(1).unwrap(), 0.0);
+        assert_eq!(moments.mean(), vec![2.0, 3.5]);
+        assert_eq!(moments.variance(), vec![1.0, 2.25]);
+        assert_approx_eq!(covariance.covariance()[[0, 1]], 1.5);
+    }
+}
@@ -0,0 +1,70 @@
+//! Some name sets to modularly glue together with the data sources. See [`SimpleNamedCloud`].
+
+use hashbrown::HashMap;
+
+use crate::base_traits::*;
+use crate::pc_errors::*;
+
+/// A name for every point, stored in memory with a reverse index.
+#[derive(Debug)]
+pub struct VecNames {
+    names: Vec<String>,
+    indexes: HashMap<String, usize>,
+}
+
+impl VecNames {
+    /// Creates a new name set. If a name is repeated, `index` gives the last point with it.
+    pub fn new(names: Vec<String>) -> VecNames {
+        let indexes = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (name.clone(), i))
+            .collect();
+        VecNames { names, indexes }
+    }
+}
+
+impl NamedSet for VecNames {
+    fn len(&self) -> usize {
+        self.names.len()
+    }
+    fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+    fn name(&self, pi: usize) -> PointCloudResult<String> {
+        self.names
+            .get(pi)
+            .cloned()
+            .ok_or(PointCloudError::DataAccessError {
+                index: pi,
+                reason: "name index out of bounds".to_string(),
+            })
+    }
+    fn index(&self, pn: &str) -> PointCloudResult<usize> {
+        self.indexes
+            .get(pn)
+            .copied()
+            .ok_or(PointCloudError::UnknownName)
+    }
+    fn names(&self) -> Vec<String> {
+        self.names.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    #[test]
+    fn named_cloud() {
+        let data = DefaultCloud::<L2>::new(vec![0.0, 1.0, 2.0], 1).unwrap();
+        let names = VecNames::new(vec!["a".to_string(), "b".to_string(), "c".to_string()]);
+        let cloud = SimpleNamedCloud::new(data, names);
+        assert_eq!(cloud.name(1).unwrap(), "b");
+        assert_eq!(cloud.index("c").unwrap(), 2);
+        assert!(cloud.index("d").is_err());
+        assert!(cloud.name(3).is_err());
+        assert_eq!(cloud.names().len(), 3);
+    }
+}
@@ -23,6 +23,8 @@ use std::fmt;
 use std::io;
 use std::str;
 
+use crate::validation::ValidationReport;
+
 ///
 pub type PointCloudResult<T> = Result<T, PointCloudError>;
 
@@ -51,6 +53,8 @@ pub enum PointCloudError {
         /// Exact nesting error
         message: &'static str,
     },
+    /// The data failed validation, see [`crate::validation`]
+    InvalidData(Box<ValidationReport>),
 }
 
 impl fmt::Display for PointCloudError {
@@ -73,6 +77,13 @@ impl fmt::Display for PointCloudError {
                 "The metric failed, you probably mixed sparse and dense data"
             ),
             PointCloudError::NotSorted => write!(f, "Passed data that wasn't sorted"),
+            PointCloudError::InvalidData(ref r) => write!(
+                f,
+                "The data failed validation: {} non-finite, {} zero-norm and {} duplicate points",
+                r.non_finite.len(),
+                r.zero_norm.len(),
+                r.duplicates.len()
+            ),
         }
     }
 }
@@ -97,6 +108,7 @@ impl Error for PointCloudError {
                 "The metric failed, you probably mixed sparse and dense data"
             }
             PointCloudError::NotSorted => "Passed data that wasn't sorted",
+            PointCloudError::InvalidData(..) => "The data failed validation",
         }
     }
 
@@ -109,6 +121,7 @@ impl Error for PointCloudError {
             PointCloudError::NodeNestingError { .. } => None,
             PointCloudError::MetricError { .. } => None,
             PointCloudError::NotSorted { .. } => None,
+            PointCloudError::InvalidData(..) => None,
         }
     }
 }
@@ -16,6 +16,28 @@ impl<'a> PointRef for &'a [f32] {
     }
 }
 
+/// A dense point that owns its values. Used by clouds that have to compute or decode a point on access, so there's
+/// nothing to borrow from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OwnedPoint(pub Vec<f32>);
+
+impl Deref for OwnedPoint {
+    type Target = [f32];
+    fn deref(&self) -> &[f32] {
+        &self.0
+    }
+}
+
+impl PointRef for OwnedPoint {
+    type DenseIter = std::vec::IntoIter<f32>;
+    fn dense(&self) -> Vec<f32> {
+        self.0.clone()
+    }
+    fn dense_iter(&self) -> Self::DenseIter {
+        self.0.clone().into_iter()
+    }
+}
+
 macro_rules! make_misc_point {
     ($base:ident, $iter_name:ident) => {
         /// Helper iterator for converting one type into another. Cleans up a really messy map.
@@ -0,0 +1,216 @@
+//! A zero-copy view of a subset, or reordering, of another point cloud.
+//!
+//! This is useful for train/test splits and cross validation, where we want to build a tree on a fold without
+//! copying the underlying features.
+
+use fxhash::FxBuildHasher;
+use hashbrown::HashMap;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use std::sync::Arc;
+
+use crate::base_traits::*;
+use crate::pc_errors::*;
+
+/// Exposes the points of the parent cloud at `indexes` as the points `0..indexes.len()`. Labels, names and metadata are
+/// forwarded from the parent with the index translated.
+#[derive(Debug)]
+pub struct SubsetCloud<D> {
+    data: Arc<D>,
+    indexes: Vec<usize>,
+    local_indexes: HashMap<usize, usize, FxBuildHasher>,
+}
+
+impl<D: PointCloud> SubsetCloud<D> {
+    /// Creates a new view over the points of `data` at `indexes`, in that order. Errors out if one of the indexes
+    /// isn't in the parent.
+    pub fn new(data: Arc<D>, indexes: Vec<usize>) -> PointCloudResult<SubsetCloud<D>> {
+        let mut local_indexes =
+            HashMap::with_capacity_and_hasher(indexes.len(), FxBuildHasher::default());
+        for (i, pi) in indexes.iter().enumerate() {
+            if *pi >= data.len() {
+                return Err(PointCloudError::data_access(
+                    *pi,
+                    "subset index is out of bounds of the parent cloud".to_string(),
+                ));
+            }
+            local_indexes.insert(*pi, i);
+        }
+        Ok(SubsetCloud {
+            data,
+            indexes,
+            local_indexes,
+        })
+    }
+
+    /// A seeded, random reordering of every point in `data`.
+    pub fn permutation(data: Arc<D>, seed: u64) -> SubsetCloud<D> {
+        let mut indexes: Vec<usize> = (0..data.len()).collect();
+        indexes.shuffle(&mut StdRng::seed_from_u64(seed));
+        SubsetCloud::new(data, indexes).unwrap()
+    }
+
+    /// Splits `data` into `k` folds after a seeded shuffle. Returns the `(train, test)` pair for each fold, where the
+    /// test set is the fold and the train set is everything else.
+    pub fn folds(data: &Arc<D>, k: usize, seed: u64) -> Vec<(SubsetCloud<D>, SubsetCloud<D>)> {
+        assert!(k > 1, "Need at least 2 folds, got {}", k);
+        let mut indexes: Vec<usize> = (0..data.len()).collect();
+        indexes.shuffle(&mut StdRng::seed_from_u64(seed));
+        (0..k)
+            .map(|fold| {
+                let start = fold * indexes.len() / k;
+                let end = (fold + 1) * indexes.len() / k;
+                let test = indexes[start..end].to_vec();
+                let train = indexes[..start]
+                    .iter()
+                    .chain(&indexes[end..])
+                    .copied()
+                    .collect();
+                (
+                    SubsetCloud::new(Arc::clone(data), train).unwrap(),
+                    SubsetCloud::new(Arc::clone(data), test).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    /// The parent's indexes, position `i` is the parent index of the point `i` of this view.
+    pub fn parent_indexes(&self) -> &[usize] {
+        &self.indexes
+    }
+
+    /// The underlying point cloud
+    pub fn parent(&self) -> &Arc<D> {
+        &self.data
+    }
+
+    /// Translates an index of this view into an index of the parent cloud.
+    #[inline]
+    pub fn parent_index(&self, pi: usize) -> PointCloudResult<usize> {
+        self.indexes.get(pi).copied().ok_or_else(|| {
+            PointCloudError::data_access(pi, "index is out of bounds of the subset".to_string())
+        })
+    }
+
+    #[inline]
+    fn parent_index_vec(&self, pns: &[usize]) -> PointCloudResult<Vec<usize>> {
+        pns.iter().map(|pi| self.parent_index(*pi)).collect()
+    }
+}
+
+impl<D: PointCloud> PointCloud for SubsetCloud<D> {
+    type Metric = D::Metric;
+    type Point = D::Point;
+    type PointRef<'a> = D::PointRef<'a>;
+    type Label = D::Label;
+    type LabelSummary = D::LabelSummary;
+    type Metadata = D::Metadata;
+    type MetaSummary = D::MetaSummary;
+
+    #[inline]
+    fn point<'a, 'b: 'a>(&'b self, pi: usize) -> PointCloudResult<Self::PointRef<'a>> {
+        self.data.point(self.parent_index(pi)?)
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.indexes.len()
+    }
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.indexes.is_empty()
+    }
+
+    #[inline]
+    fn reference_indexes(&self) -> Vec<usize> {
+        (0..self.indexes.len()).collect()
+    }
+
+    #[inline]
+    fn dim(&self) -> usize {
+        self.data.dim()
+    }
+
+    fn label(&self, pn: usize) -> PointCloudResult<Option<&Self::Label>> {
+        self.data.label(self.parent_index(pn)?)
+    }
+    fn label_summary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::LabelSummary>> {
+        self.data.label_summary(&self.parent_index_vec(pns)?)
+    }
+
+    fn name(&self, pi: usize) -> PointCloudResult<String> {
+        self.data.name(self.parent_index(pi)?)
+    }
+    fn index(&self, pn: &str) -> PointCloudResult<usize> {
+        let parent_index = self.data.index(pn)?;
+        self.local_indexes
+            .get(&parent_index)
+            .copied()
+            .ok_or(PointCloudError::UnknownName)
+    }
+    fn names(&self) -> Vec<String> {
+        self.indexes
+            .iter()
+            .filter_map(|pi| self.data.name(*pi).ok())
+            .collect()
+    }
+
+    fn metadata(&self, pn: usize) -> PointCloudResult<Option<&Self::Metadata>> {
+        self.data.metadata(self.parent_index(pn)?)
+    }
+    fn metasummary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::MetaSummary>> {
+        self.data.metasummary(&self.parent_index_vec(pns)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_sources::tests::build_ram_fixed_labeled_test;
+
+    #[test]
+    fn subset_translates_indexes() {
+        let data = Arc::new(build_ram_fixed_labeled_test(10, 3));
+        let subset = SubsetCloud::new(Arc::clone(&data), vec![7, 2, 5]).unwrap();
+
+        assert_eq!(subset.len(), 3);
+        assert_eq!(subset.point(0).unwrap(), &[7.0, 7.0, 7.0][..]);
+        assert_eq!(subset.label(1).unwrap(), Some(&2));
+        assert_eq!(subset.name(2).unwrap(), "5");
+        assert_eq!(subset.index("5").unwrap(), 2);
+        assert!(subset.index("3").is_err());
+        assert!(subset.point(3).is_err());
+        assert!(SubsetCloud::new(Arc::clone(&data), vec![10]).is_err());
+
+        let summary = subset.label_summary(&[0, 1, 2]).unwrap();
+        assert_eq!(summary.count(), 3);
+        assert!(summary.summary.items.contains(&(7, 1)));
+
+        let dists = subset.distances_to_point_index(0, &[1, 2]).unwrap();
+        let parent_dists = data.distances_to_point_index(7, &[2, 5]).unwrap();
+        assert_eq!(dists, parent_dists);
+    }
+
+    #[test]
+    fn folds_partition() {
+        let data = Arc::new(build_ram_fixed_labeled_test(10, 2));
+        let folds = SubsetCloud::folds(&data, 3, 0);
+        let mut seen: Vec<usize> = Vec::new();
+        for (train, test) in folds.iter() {
+            assert_eq!(train.len() + test.len(), 10);
+            for i in test.parent_indexes() {
+                assert!(!train.parent_indexes().contains(i));
+            }
+            seen.extend(test.parent_indexes());
+        }
+        seen.sort_unstable();
+        assert_eq!(seen, (0..10).collect::<Vec<usize>>());
+
+        let permutation = SubsetCloud::permutation(Arc::clone(&data), 0);
+        let mut indexes = permutation.parent_indexes().to_vec();
+        indexes.sort_unstable();
+        assert_eq!(indexes, (0..10).collect::<Vec<usize>>());
+    }
+}
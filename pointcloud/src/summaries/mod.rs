@@ -60,6 +60,59 @@ impl Summary for CategorySummary {
     fn count(&self) -> usize {
         self.items.iter().map(|(_a, b)| b).sum()
     }
+
+    fn contains(&self, val: &i64) -> Option<bool> {
+        Some(self.items.iter().any(|(stored_val, _)| stored_val == val))
+    }
+}
+
+/// A summary for a small number of named categories, the string keyed version of [`CategorySummary`].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StringCategorySummary {
+    /// Counts of how many of each class name there is
+    pub items: SmallVec<[(String, usize); 4]>,
+}
+
+impl Default for StringCategorySummary {
+    fn default() -> Self {
+        StringCategorySummary {
+            items: SmallVec::new(),
+        }
+    }
+}
+
+impl StringCategorySummary {
+    pub(crate) fn add_count(&mut self, val: &str, count: usize) {
+        match self
+            .items
+            .iter_mut()
+            .find(|(stored_val, _)| stored_val == val)
+        {
+            Some((_, totals)) => *totals += count,
+            None => self.items.push((val.to_string(), count)),
+        }
+    }
+}
+
+impl Summary for StringCategorySummary {
+    type Label = str;
+    fn add(&mut self, val: &str) {
+        self.add_count(val, 1);
+    }
+
+    fn combine(&mut self, other: &StringCategorySummary) {
+        for (val, count) in other.items.iter() {
+            self.add_count(val, *count);
+        }
+    }
+
+    fn count(&self) -> usize {
+        self.items.iter().map(|(_a, b)| b).sum()
+    }
+
+    fn contains(&self, val: &str) -> Option<bool> {
+        Some(self.items.iter().any(|(stored_val, _)| stored_val == val))
+    }
 }
 
 /// Summary of vectors
@@ -123,6 +176,21 @@ pub struct FloatSummary {
     pub moment2: f64,
     /// The count of the number of labels included
     pub count: usize,
+    /// The smallest value seen
+    pub min: Option<f64>,
+    /// The largest value seen
+    pub max: Option<f64>,
+}
+
+impl FloatSummary {
+    /// The mean of the values, `None` if the summary is empty.
+    pub fn mean(&self) -> Option<f64> {
+        if self.count > 0 {
+            Some(self.moment1 / self.count as f64)
+        } else {
+            None
+        }
+    }
 }
 
 impl Summary for FloatSummary {
@@ -132,11 +200,19 @@ impl Summary for FloatSummary {
         self.moment1 += val;
         self.moment2 += val * val;
         self.count += 1;
+        self.min = Some(self.min.map_or(*val, |m| m.min(*val)));
+        self.max = Some(self.max.map_or(*val, |m| m.max(*val)));
     }
     fn combine(&mut self, other: &FloatSummary) {
         self.moment1 += other.moment1;
         self.moment2 += other.moment2;
         self.count += other.count;
+        if let Some(other_min) = other.min {
+            self.min = Some(self.min.map_or(other_min, |m| m.min(other_min)));
+        }
+        if let Some(other_max) = other.max {
+            self.max = Some(self.max.map_or(other_max, |m| m.max(other_max)));
+        }
     }
 
     fn count(&self) -> usize {
@@ -205,3 +281,285 @@ impl Summary for StringSummary {
         self.items.values().sum()
     }
 }
+
+/// Summary of a set of timestamps, keeps track of the time range covered.
+#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+pub struct TimeRangeSummary {
+    /// The earliest timestamp seen
+    pub min: Option<i64>,
+    /// The latest timestamp seen
+    pub max: Option<i64>,
+    /// The count of the number of timestamps included
+    pub count: usize,
+}
+
+impl TimeRangeSummary {
+    /// The span of time this summary covers, `None` if it's empty.
+    pub fn duration(&self) -> Option<i64> {
+        match (self.min, self.max) {
+            (Some(min), Some(max)) => Some(max - min),
+            _ => None,
+        }
+    }
+}
+
+impl Summary for TimeRangeSummary {
+    type Label = i64;
+
+    fn add(&mut self, val: &i64) {
+        self.min = Some(self.min.map_or(*val, |m| m.min(*val)));
+        self.max = Some(self.max.map_or(*val, |m| m.max(*val)));
+        self.count += 1;
+    }
+    fn combine(&mut self, other: &TimeRangeSummary) {
+        if let Some(other_min) = other.min {
+            self.min = Some(self.min.map_or(other_min, |m| m.min(other_min)));
+        }
+        if let Some(other_max) = other.max {
+            self.max = Some(self.max.map_or(other_max, |m| m.max(other_max)));
+        }
+        self.count += other.count;
+    }
+
+    fn count(&self) -> usize {
+        self.count
+    }
+}
+
+/// A summary for sets of categories, where each point can belong to several classes at once.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct MultiCategorySummary {
+    /// How many points have each class
+    pub items: SmallVec<[(i64, usize); 4]>,
+    /// The count of the number of label sets included
+    pub count: usize,
+    /// The total number of class memberships, summed over the label sets
+    pub cardinality_moment1: usize,
+    /// The sum of the squared sizes of the label sets
+    pub cardinality_moment2: usize,
+    /// The size of the smallest label set
+    pub min_cardinality: Option<usize>,
+    /// The size of the largest label set
+    pub max_cardinality: Option<usize>,
+}
+
+impl Default for MultiCategorySummary {
+    fn default() -> Self {
+        MultiCategorySummary {
+            items: SmallVec::new(),
+            count: 0,
+            cardinality_moment1: 0,
+            cardinality_moment2: 0,
+            min_cardinality: None,
+            max_cardinality: None,
+        }
+    }
+}
+
+impl MultiCategorySummary {
+    fn add_class(&mut self, val: i64, count: usize) {
+        match self.items.iter_mut().find(|(stored_val, _)| *stored_val == val) {
+            Some((_, totals)) => *totals += count,
+            None => self.items.push((val, count)),
+        }
+    }
+
+    /// The average number of classes per point, the label cardinality.
+    pub fn mean_cardinality(&self) -> Option<f64> {
+        if self.count > 0 {
+            Some(self.cardinality_moment1 as f64 / self.count as f64)
+        } else {
+            None
+        }
+    }
+
+    /// The variance of the number of classes per point.
+    pub fn var_cardinality(&self) -> Option<f64> {
+        self.mean_cardinality().map(|mean| {
+            self.cardinality_moment2 as f64 / self.count as f64 - mean * mean
+        })
+    }
+}
+
+impl Summary for MultiCategorySummary {
+    type Label = [i64];
+    fn add(&mut self, val: &[i64]) {
+        for class in val {
+            self.add_class(*class, 1);
+        }
+        let cardinality = val.len();
+        self.count += 1;
+        self.cardinality_moment1 += cardinality;
+        self.cardinality_moment2 += cardinality * cardinality;
+        self.min_cardinality = Some(self.min_cardinality.map_or(cardinality, |m| m.min(cardinality)));
+        self.max_cardinality = Some(self.max_cardinality.map_or(cardinality, |m| m.max(cardinality)));
+    }
+
+    fn combine(&mut self, other: &MultiCategorySummary) {
+        for (val, count) in other.items.iter() {
+            self.add_class(*val, *count);
+        }
+        self.count += other.count;
+        self.cardinality_moment1 += other.cardinality_moment1;
+        self.cardinality_moment2 += other.cardinality_moment2;
+        if let Some(other_min) = other.min_cardinality {
+            self.min_cardinality = Some(self.min_cardinality.map_or(other_min, |m| m.min(other_min)));
+        }
+        if let Some(other_max) = other.max_cardinality {
+            self.max_cardinality = Some(self.max_cardinality.map_or(other_max, |m| m.max(other_max)));
+        }
+    }
+
+    fn count(&self) -> usize {
+        self.count
+    }
+}
+
+/// The number of centroids a [`QuantileSketch`] compresses down to.
+const QUANTILE_SKETCH_SIZE: usize = 64;
+
+/// A small mergable quantile sketch. It keeps at most `2 * QUANTILE_SKETCH_SIZE` weighted centroids, and
+/// compresses them into centroids of roughly equal weight when it overflows. The error in the rank of a
+/// quantile is about `1/QUANTILE_SKETCH_SIZE` of the total count.
+#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+pub struct QuantileSketch {
+    /// Sorted (value, weight) pairs
+    pub centroids: Vec<(f64, usize)>,
+    /// The total weight of the centroids
+    pub count: usize,
+    /// The exact minimum
+    pub min: Option<f64>,
+    /// The exact maximum
+    pub max: Option<f64>,
+}
+
+impl QuantileSketch {
+    /// Adds a value to the sketch.
+    pub fn add(&mut self, val: f64) {
+        self.insert(val, 1);
+        self.min = Some(self.min.map_or(val, |m| m.min(val)));
+        self.max = Some(self.max.map_or(val, |m| m.max(val)));
+        if self.centroids.len() > 2 * QUANTILE_SKETCH_SIZE {
+            self.compress();
+        }
+    }
+
+    /// Merges another sketch into this one.
+    pub fn merge(&mut self, other: &QuantileSketch) {
+        for (val, weight) in other.centroids.iter() {
+            self.insert(*val, *weight);
+        }
+        if let Some(other_min) = other.min {
+            self.min = Some(self.min.map_or(other_min, |m| m.min(other_min)));
+        }
+        if let Some(other_max) = other.max {
+            self.max = Some(self.max.map_or(other_max, |m| m.max(other_max)));
+        }
+        if self.centroids.len() > 2 * QUANTILE_SKETCH_SIZE {
+            self.compress();
+        }
+    }
+
+    fn insert(&mut self, val: f64, weight: usize) {
+        let i = self.centroids.partition_point(|(v, _)| *v < val);
+        self.centroids.insert(i, (val, weight));
+        self.count += weight;
+    }
+
+    fn compress(&mut self) {
+        let target = self.count / QUANTILE_SKETCH_SIZE + 1;
+        let mut compressed: Vec<(f64, usize)> = Vec::with_capacity(QUANTILE_SKETCH_SIZE + 1);
+        for (val, weight) in self.centroids.drain(..) {
+            match compressed.last_mut() {
+                Some((c_val, c_weight)) if *c_weight + weight <= target => {
+                    let total = *c_weight + weight;
+                    *c_val = (*c_val * *c_weight as f64 + val * weight as f64) / total as f64;
+                    *c_weight = total;
+                }
+                _ => compressed.push((val, weight)),
+            }
+        }
+        self.centroids = compressed;
+    }
+
+    /// Estimates the value at quantile `q`, which should be between 0 and 1. Returns `None` if the sketch is empty.
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+        let q = q.clamp(0.0, 1.0);
+        if q == 0.0 {
+            return self.min;
+        }
+        if q == 1.0 {
+            return self.max;
+        }
+        let rank = q * self.count as f64;
+        let mut seen = 0.0;
+        for (val, weight) in self.centroids.iter() {
+            seen += *weight as f64;
+            if seen >= rank {
+                return Some(*val);
+            }
+        }
+        self.max
+    }
+}
+
+/// Summary of a scalar regression target. Tracks a streaming mean and variance with Welford's algorithm,
+/// and approximate quantiles with a [`QuantileSketch`].
+#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+pub struct RegressionSummary {
+    /// The running mean
+    pub mean: f64,
+    /// The running sum of squared differences from the mean
+    pub m2: f64,
+    /// The count of the number of values included
+    pub count: usize,
+    /// Sketch of the distribution of the values
+    pub sketch: QuantileSketch,
+}
+
+impl RegressionSummary {
+    /// The population variance of the values, `None` if the summary is empty.
+    pub fn var(&self) -> Option<f64> {
+        if self.count > 0 {
+            Some(self.m2 / self.count as f64)
+        } else {
+            None
+        }
+    }
+
+    /// Estimates the value at quantile `q`, see [`QuantileSketch::quantile`].
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        self.sketch.quantile(q)
+    }
+}
+
+impl Summary for RegressionSummary {
+    type Label = f32;
+
+    fn add(&mut self, val: &f32) {
+        let val = *val as f64;
+        self.count += 1;
+        let delta = val - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (val - self.mean);
+        self.sketch.add(val);
+    }
+    fn combine(&mut self, other: &RegressionSummary) {
+        if other.count == 0 {
+            return;
+        }
+        let count = self.count + other.count;
+        let delta = other.mean - self.mean;
+        self.mean += delta * other.count as f64 / count as f64;
+        self.m2 += other.m2 + delta * delta * (self.count as f64 * other.count as f64) / count as f64;
+        self.count = count;
+        self.sketch.merge(&other.sketch);
+    }
+
+    fn count(&self) -> usize {
+        self.count
+    }
+}
@@ -0,0 +1,413 @@
+//! Lazy feature transforms. A [`TransformedCloud`] applies a [`Transform`] to each point as it is accessed, so you
+//! can build a tree over standardized or dimensionality-reduced features without materializing them.
+//!
+//! The transforms are serializable. Save the transform next to the tree and apply it to incoming query points with
+//! [`Transform::apply`] so they land in the same space as the tree.
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+
+use crate::base_traits::*;
+use crate::pc_errors::*;
+use crate::points::OwnedPoint;
+
+/// A map from the dense features of the underlying cloud to new dense features.
+pub trait Transform: Debug + Serialize + DeserializeOwned + Send + Sync + 'static {
+    /// The dimension of the points this accepts
+    fn input_dim(&self) -> usize;
+    /// The dimension of the points this produces
+    fn output_dim(&self) -> usize;
+    /// Transforms a dense point. The iterator should yield `input_dim` values.
+    fn apply_iter<I: Iterator<Item = f32>>(&self, point: I) -> Vec<f32>;
+    /// Transforms a dense point, use this for query points.
+    fn apply(&self, point: &[f32]) -> Vec<f32> {
+        assert_eq!(point.len(), self.input_dim());
+        self.apply_iter(point.iter().copied())
+    }
+}
+
+/// Per-dimension standardization, `(x - mean) / std`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Standardization {
+    /// The mean of each dimension
+    pub mean: Vec<f32>,
+    /// The standard deviation of each dimension. Dimensions with no variance have this set to 1.
+    pub std: Vec<f32>,
+}
+
+impl Standardization {
+    /// Fits the standardization over the given indexes of the cloud, with the cloud's `moment_1` and `moment_2`.
+    pub fn fit<D: PointCloud>(cloud: &D, indexes: &[usize]) -> PointCloudResult<Standardization> {
+        let count = indexes.len() as f32;
+        let moment1 = cloud.moment_1(indexes)?;
+        let moment2 = cloud.moment_2(indexes)?;
+        let mean: Vec<f32> = moment1.iter().map(|m| m / count).collect();
+        let std = moment2
+            .iter()
+            .zip(&mean)
+            .map(|(m2, m)| {
+                let var = m2 / count - m * m;
+                if var > f32::EPSILON {
+                    var.sqrt()
+                } else {
+                    1.0
+                }
+            })
+            .collect();
+        Ok(Standardization { mean, std })
+    }
+}
+
+impl Transform for Standardization {
+    fn input_dim(&self) -> usize {
+        self.mean.len()
+    }
+    fn output_dim(&self) -> usize {
+        self.mean.len()
+    }
+    fn apply_iter<I: Iterator<Item = f32>>(&self, point: I) -> Vec<f32> {
+        point
+            .zip(self.mean.iter().zip(&self.std))
+            .map(|(x, (m, s))| (x - m) / s)
+            .collect()
+    }
+}
+
+/// Number of rounds of subspace iteration used to fit the PCA.
+const PCA_ITERATIONS: usize = 100;
+
+/// A projection onto the top principal components.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PcaProjection {
+    /// The mean of the sample the PCA was fitted on
+    pub mean: Vec<f32>,
+    /// The principal components, row major with one component per row
+    pub components: Vec<f32>,
+    /// The variance along each component
+    pub variances: Vec<f32>,
+    /// The number of components
+    pub output_dim: usize,
+}
+
+impl PcaProjection {
+    /// Fits a PCA with `output_dim` components on a seeded random sample of `sample_size` points of the cloud.
+    /// The components are found by subspace iteration on the sample's covariance matrix.
+    pub fn fit<D: PointCloud>(
+        cloud: &D,
+        output_dim: usize,
+        sample_size: usize,
+        seed: u64,
+    ) -> PointCloudResult<PcaProjection> {
+        let dim = cloud.dim();
+        assert!(
+            0 < output_dim && output_dim <= dim,
+            "Can't project a {} dimensional cloud to {} components",
+            dim,
+            output_dim
+        );
+        let mut rng = StdRng::seed_from_u64(seed);
+        let reference_indexes = cloud.reference_indexes();
+        let sample: Vec<usize> = reference_indexes
+            .choose_multiple(&mut rng, sample_size.min(reference_indexes.len()))
+            .copied()
+            .collect();
+        let count = sample.len() as f64;
+
+        let mut points = Vec::with_capacity(sample.len());
+        let mut mean = vec![0.0f64; dim];
+        for i in &sample {
+            let point: Vec<f64> = cloud.point(*i)?.dense_iter().map(|x| x as f64).collect();
+            mean.iter_mut().zip(&point).for_each(|(m, x)| *m += x);
+            points.push(point);
+        }
+        mean.iter_mut().for_each(|m| *m /= count);
+
+        let mut covariance = vec![0.0f64; dim * dim];
+        for point in points.iter_mut() {
+            point.iter_mut().zip(&mean).for_each(|(x, m)| *x -= m);
+            for (row, x) in covariance.chunks_exact_mut(dim).zip(point.iter()) {
+                row.iter_mut()
+                    .zip(point.iter())
+                    .for_each(|(c, y)| *c += x * y);
+            }
+        }
+        covariance.iter_mut().for_each(|c| *c /= count);
+
+        // Subspace iteration, q is dim x output_dim and column major.
+        let mut q: Vec<f64> = (0..dim * output_dim)
+            .map(|_| rng.gen_range(-1.0..1.0))
+            .collect();
+        orthonormalize(&mut q, dim);
+        for _ in 0..PCA_ITERATIONS {
+            q = mat_cols_mul(&covariance, &q, dim);
+            orthonormalize(&mut q, dim);
+        }
+        let cq = mat_cols_mul(&covariance, &q, dim);
+        let variances = q
+            .chunks_exact(dim)
+            .zip(cq.chunks_exact(dim))
+            .map(|(v, cv)| v.iter().zip(cv).map(|(a, b)| a * b).sum::<f64>() as f32)
+            .collect();
+
+        Ok(PcaProjection {
+            mean: mean.iter().map(|m| *m as f32).collect(),
+            components: q.iter().map(|c| *c as f32).collect(),
+            variances,
+            output_dim,
+        })
+    }
+}
+
+/// Multiplies the square matrix by each of the columns.
+fn mat_cols_mul(matrix: &[f64], columns: &[f64], dim: usize) -> Vec<f64> {
+    let mut result = Vec::with_capacity(columns.len());
+    for column in columns.chunks_exact(dim) {
+        result.extend(
+            matrix
+                .chunks_exact(dim)
+                .map(|row| row.iter().zip(column).map(|(a, b)| a * b).sum::<f64>()),
+        );
+    }
+    result
+}
+
+/// Modified Gram-Schmidt on the columns.
+fn orthonormalize(columns: &mut [f64], dim: usize) {
+    let count = columns.len() / dim;
+    for i in 0..count {
+        let (done, rest) = columns.split_at_mut(i * dim);
+        let column = &mut rest[..dim];
+        for prev in done.chunks_exact(dim) {
+            let proj: f64 = prev.iter().zip(column.iter()).map(|(a, b)| a * b).sum();
+            column
+                .iter_mut()
+                .zip(prev)
+                .for_each(|(c, p)| *c -= proj * p);
+        }
+        let norm = column.iter().map(|c| c * c).sum::<f64>().sqrt();
+        if norm > f64::EPSILON {
+            column.iter_mut().for_each(|c| *c /= norm);
+        }
+    }
+}
+
+impl Transform for PcaProjection {
+    fn input_dim(&self) -> usize {
+        self.mean.len()
+    }
+    fn output_dim(&self) -> usize {
+        self.output_dim
+    }
+    fn apply_iter<I: Iterator<Item = f32>>(&self, point: I) -> Vec<f32> {
+        let centered: Vec<f32> = point.zip(&self.mean).map(|(x, m)| x - m).collect();
+        self.components
+            .chunks_exact(self.mean.len())
+            .map(|component| component.iter().zip(&centered).map(|(c, x)| c * x).sum())
+            .collect()
+    }
+}
+
+/// A Johnson–Lindenstrauss random projection, with a random sign matrix scaled by `1/sqrt(output_dim)`.
+/// See [wikipedia](https://en.wikipedia.org/wiki/Random_projection).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RandomProjection {
+    /// The seed the matrix was generated with
+    pub seed: u64,
+    /// The dimension of the points this accepts
+    pub input_dim: usize,
+    /// The projection matrix, row major with one row per output dimension
+    pub matrix: Vec<f32>,
+}
+
+impl RandomProjection {
+    /// Creates a new random projection. The same seed and dimensions give the same projection.
+    pub fn new(input_dim: usize, output_dim: usize, seed: u64) -> RandomProjection {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let scale = 1.0 / (output_dim as f32).sqrt();
+        let matrix = (0..input_dim * output_dim)
+            .map(|_| if rng.gen::<bool>() { scale } else { -scale })
+            .collect();
+        RandomProjection {
+            seed,
+            input_dim,
+            matrix,
+        }
+    }
+}
+
+impl Transform for RandomProjection {
+    fn input_dim(&self) -> usize {
+        self.input_dim
+    }
+    fn output_dim(&self) -> usize {
+        self.matrix.len() / self.input_dim
+    }
+    fn apply_iter<I: Iterator<Item = f32>>(&self, point: I) -> Vec<f32> {
+        let point: Vec<f32> = point.collect();
+        self.matrix
+            .chunks_exact(self.input_dim)
+            .map(|row| row.iter().zip(&point).map(|(r, x)| r * x).sum())
+            .collect()
+    }
+}
+
+/// A point cloud that applies a transform to the points of the underlying cloud on access. Labels, names and
+/// metadata are passed thru untouched.
+#[derive(Debug)]
+pub struct TransformedCloud<D, T> {
+    data: D,
+    transform: T,
+}
+
+impl<D: PointCloud, T: Transform> TransformedCloud<D, T> {
+    /// Creates a new one
+    pub fn new(data: D, transform: T) -> Self {
+        assert_eq!(data.dim(), transform.input_dim());
+        TransformedCloud { data, transform }
+    }
+
+    /// The transform, save this to transform query points
+    pub fn transform(&self) -> &T {
+        &self.transform
+    }
+
+    /// The untransformed cloud
+    pub fn data(&self) -> &D {
+        &self.data
+    }
+}
+
+impl<D, T> PointCloud for TransformedCloud<D, T>
+where
+    D: PointCloud,
+    D::Metric: Metric<[f32]>,
+    T: Transform,
+{
+    type Metric = D::Metric;
+    type Point = [f32];
+    type PointRef<'a> = OwnedPoint;
+    type Metadata = D::Metadata;
+    type MetaSummary = D::MetaSummary;
+    type Label = D::Label;
+    type LabelSummary = D::LabelSummary;
+
+    #[inline]
+    fn dim(&self) -> usize {
+        self.transform.output_dim()
+    }
+    #[inline]
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+    #[inline]
+    fn reference_indexes(&self) -> Vec<usize> {
+        self.data.reference_indexes()
+    }
+    #[inline]
+    fn point<'a, 'b: 'a>(&'b self, i: usize) -> PointCloudResult<OwnedPoint> {
+        let point = self.data.point(i)?;
+        Ok(OwnedPoint(self.transform.apply_iter(point.dense_iter())))
+    }
+
+    fn metadata(&self, pn: usize) -> PointCloudResult<Option<&Self::Metadata>> {
+        self.data.metadata(pn)
+    }
+    fn metasummary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::MetaSummary>> {
+        self.data.metasummary(pns)
+    }
+    fn label(&self, pn: usize) -> PointCloudResult<Option<&Self::Label>> {
+        self.data.label(pn)
+    }
+    fn label_summary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::LabelSummary>> {
+        self.data.label_summary(pns)
+    }
+    fn name(&self, pi: usize) -> PointCloudResult<String> {
+        self.data.name(pi)
+    }
+    fn index(&self, pn: &str) -> PointCloudResult<usize> {
+        self.data.index(pn)
+    }
+    fn names(&self) -> Vec<String> {
+        self.data.names()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_sources::tests::build_ram_random_test;
+    use crate::data_sources::DataRam;
+    use crate::metrics::L2;
+
+    #[test]
+    fn standardization_is_standard() {
+        let data = build_ram_random_test(200, 5);
+        let indexes = data.reference_indexes();
+        let transform = Standardization::fit(&data, &indexes).unwrap();
+        let cloud = TransformedCloud::new(data, transform);
+
+        let moment1 = cloud.moment_1(&indexes).unwrap();
+        let moment2 = cloud.moment_2(&indexes).unwrap();
+        for (m1, m2) in moment1.iter().zip(&moment2) {
+            assert_approx_eq!(m1 / 200.0, 0.0, 1e-4);
+            assert_approx_eq!(m2 / 200.0, 1.0, 1e-3);
+        }
+    }
+
+    #[test]
+    fn pca_finds_the_line() {
+        // Points along (1,2,2)/3 with a little noise in the other directions.
+        let data: Vec<f32> = (0..100)
+            .flat_map(|i| {
+                let t = i as f32 - 50.0;
+                let noise = if i % 2 == 0 { 0.01 } else { -0.01 };
+                vec![t / 3.0 + noise, 2.0 * t / 3.0, 2.0 * t / 3.0 - noise]
+            })
+            .collect();
+        let data = DataRam::<L2>::new(data, 3).unwrap();
+        let pca = PcaProjection::fit(&data, 1, 100, 0).unwrap();
+        let component = &pca.components[0..3];
+        let alignment: f32 = component
+            .iter()
+            .zip(&[1.0 / 3.0, 2.0 / 3.0, 2.0 / 3.0])
+            .map(|(a, b)| a * b)
+            .sum();
+        assert_approx_eq!(alignment.abs(), 1.0, 1e-3);
+
+        let cloud = TransformedCloud::new(data, pca);
+        assert_eq!(cloud.dim(), 1);
+        // Points 10 apart along the line should be 10 apart after the projection.
+        let diff = cloud.point(60).unwrap()[0] - cloud.point(50).unwrap()[0];
+        assert_approx_eq!(diff.abs(), 10.0, 1e-2);
+    }
+
+    #[test]
+    fn random_projection_serializes() {
+        let data = build_ram_random_test(10, 20);
+        let projection = RandomProjection::new(20, 5, 7);
+        let json = serde_json::to_string(&projection).unwrap();
+        let reloaded: RandomProjection = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            RandomProjection::new(20, 5, 7).matrix,
+            reloaded.matrix,
+            "Same seed should give the same projection"
+        );
+
+        let query: Vec<f32> = data.point(3).unwrap().to_vec();
+        let cloud = TransformedCloud::new(data, projection);
+        assert_eq!(cloud.point(3).unwrap().len(), 5);
+        assert_eq!(*cloud.point(3).unwrap(), reloaded.apply(&query)[..]);
+        let dists = cloud
+            .distances_to_point(&OwnedPoint(reloaded.apply(&query)), &[3])
+            .unwrap();
+        assert_approx_eq!(dists[0], 0.0);
+    }
+}
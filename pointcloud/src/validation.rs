@@ -0,0 +1,213 @@
+//! Checks for points that break the metric, or the assumptions of a cover tree. A single NaN makes every distance to
+//! it NaN, and the comparisons that partition the data stop making sense.
+//!
+//! See [`PointCloud::validate`] to make a [`ValidationReport`], and [`ValidationReport::apply`] to decide what to do
+//! with the points it found.
+
+use fxhash::FxHasher;
+use hashbrown::HashMap;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::hash::Hasher;
+
+use crate::base_traits::*;
+use crate::pc_errors::*;
+
+/// What to do with the bad points a [`ValidationReport`] found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ValidationPolicy {
+    /// Fail with [`PointCloudError::InvalidData`] if any point was flagged.
+    Error,
+    /// Drop every flagged point, keeping the first copy of duplicated points.
+    Drop,
+    /// Drop the duplicates, keeping the first copy. Non-finite points still fail with
+    /// [`PointCloudError::InvalidData`], and zero-norm points are kept.
+    Dedup,
+}
+
+/// The indexes of the points that failed validation.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ValidationReport {
+    /// Points with a NaN or infinite value
+    pub non_finite: Vec<usize>,
+    /// Points that are all zero
+    pub zero_norm: Vec<usize>,
+    /// Pairs of `(duplicate, original)`, where the duplicate is exactly equal to the original. The original is
+    /// the first point in reference order with those values.
+    pub duplicates: Vec<(usize, usize)>,
+}
+
+impl ValidationReport {
+    /// If no point was flagged
+    pub fn is_valid(&self) -> bool {
+        self.non_finite.is_empty() && self.zero_norm.is_empty() && self.duplicates.is_empty()
+    }
+
+    /// Applies the policy to the indexes, returning the ones to keep.
+    pub fn apply(
+        &self,
+        policy: ValidationPolicy,
+        indexes: &[usize],
+    ) -> PointCloudResult<Vec<usize>> {
+        let mut dropped: Vec<usize> = match policy {
+            ValidationPolicy::Error => {
+                if self.is_valid() {
+                    return Ok(indexes.to_vec());
+                }
+                return Err(PointCloudError::InvalidData(Box::new(self.clone())));
+            }
+            ValidationPolicy::Drop => self
+                .non_finite
+                .iter()
+                .chain(&self.zero_norm)
+                .chain(self.duplicates.iter().map(|(dup, _)| dup))
+                .copied()
+                .collect(),
+            ValidationPolicy::Dedup => {
+                if !self.non_finite.is_empty() {
+                    return Err(PointCloudError::InvalidData(Box::new(self.clone())));
+                }
+                self.duplicates.iter().map(|(dup, _)| *dup).collect()
+            }
+        };
+        dropped.sort_unstable();
+        Ok(indexes
+            .iter()
+            .filter(|i| dropped.binary_search(i).is_err())
+            .copied()
+            .collect())
+    }
+}
+
+struct PointCheck {
+    index: usize,
+    finite: bool,
+    zero: bool,
+    hash: u64,
+}
+
+fn check_point<D: PointCloud>(point_cloud: &D, index: usize) -> PointCloudResult<PointCheck> {
+    let point = point_cloud.point(index)?;
+    let mut hasher = FxHasher::default();
+    let mut finite = true;
+    let mut zero = true;
+    for x in point.dense_iter() {
+        finite &= x.is_finite();
+        zero &= x == 0.0;
+        // -0.0 == 0.0, so they need to hash the same
+        hasher.write_u32(if x == 0.0 { 0 } else { x.to_bits() });
+    }
+    Ok(PointCheck {
+        index,
+        finite,
+        zero,
+        hash: hasher.finish(),
+    })
+}
+
+/// Validates the points at the given indexes, see [`PointCloud::validate`].
+pub fn validate_indexes<D: PointCloud>(
+    point_cloud: &D,
+    indexes: &[usize],
+) -> PointCloudResult<ValidationReport> {
+    let checks = indexes
+        .par_iter()
+        .map(|i| check_point(point_cloud, *i))
+        .collect::<PointCloudResult<Vec<PointCheck>>>()?;
+
+    let mut report = ValidationReport::default();
+    let mut seen: HashMap<u64, Vec<usize>> = HashMap::new();
+    for check in checks {
+        if !check.finite {
+            report.non_finite.push(check.index);
+            continue;
+        }
+        if check.zero {
+            report.zero_norm.push(check.index);
+        }
+        // Hashes can collide, so we confirm against the actual values.
+        let candidates = seen.entry(check.hash).or_insert_with(Vec::new);
+        let mut original = None;
+        if !candidates.is_empty() {
+            let values = point_cloud.point(check.index)?.dense();
+            for candidate in candidates.iter() {
+                let candidate_values = point_cloud.point(*candidate)?.dense();
+                if values == candidate_values {
+                    original = Some(*candidate);
+                    break;
+                }
+            }
+        }
+        match original {
+            Some(original) => report.duplicates.push((check.index, original)),
+            None => candidates.push(check.index),
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_sources::DataRam;
+    use crate::metrics::L2;
+
+    fn build_bad_cloud() -> DataRam<L2> {
+        let data = vec![
+            1.0,
+            2.0,
+            f32::NAN,
+            1.0,
+            0.0,
+            -0.0,
+            1.0,
+            2.0,
+            3.0,
+            f32::INFINITY,
+            0.0,
+            0.0,
+            4.0,
+            5.0,
+        ];
+        DataRam::new(data, 2).unwrap()
+    }
+
+    #[test]
+    fn finds_bad_points() {
+        let cloud = build_bad_cloud();
+        let report = cloud.validate().unwrap();
+        assert_eq!(report.non_finite, vec![1, 4]);
+        assert_eq!(report.zero_norm, vec![2, 5]);
+        assert_eq!(report.duplicates, vec![(3, 0), (5, 2)]);
+        assert!(!report.is_valid());
+    }
+
+    #[test]
+    fn policies() {
+        let cloud = build_bad_cloud();
+        let indexes = cloud.reference_indexes();
+        let report = cloud.validate().unwrap();
+
+        match report.apply(ValidationPolicy::Error, &indexes) {
+            Err(PointCloudError::InvalidData(r)) => assert_eq!(*r, report),
+            other => panic!("Expected an invalid data error, got {:?}", other),
+        }
+        assert!(report.apply(ValidationPolicy::Dedup, &indexes).is_err());
+        assert_eq!(
+            report.apply(ValidationPolicy::Drop, &indexes).unwrap(),
+            vec![0, 6]
+        );
+
+        let deduped = ValidationReport {
+            non_finite: vec![],
+            zero_norm: vec![2],
+            duplicates: vec![(3, 0)],
+        };
+        assert_eq!(
+            deduped
+                .apply(ValidationPolicy::Dedup, &[0, 1, 2, 3])
+                .unwrap(),
+            vec![0, 1, 2]
+        );
+    }
+}
@@ -229,6 +229,22 @@ impl CoverTree {
             .unwrap()
     }
 
+    pub fn brute_knn(&self, point: &PyArray1<f32>, k: usize) -> Vec<(usize, f32)> {
+        let reader = self.writer.as_ref().unwrap().reader();
+        reader
+            .point_cloud()
+            .brute_knn(&point.readonly().as_slice().unwrap(), k)
+            .unwrap()
+    }
+
+    pub fn stats(&self) -> PyResult<PyObject> {
+        let reader = self.writer.as_ref().unwrap().reader();
+        let stats = serde_json::to_string(&reader.stats()).unwrap();
+        let gil = pyo3::Python::acquire_gil();
+        let py = gil.python();
+        Ok(py.import("json")?.call_method1("loads", (stats,))?.into())
+    }
+
     pub fn path(&self, point: &PyArray1<f32>) -> Vec<((i32, usize), f32)> {
         let reader = self.writer.as_ref().unwrap().reader();
         reader.path(&point.readonly().as_slice().unwrap()).unwrap().to_valid_tuples()
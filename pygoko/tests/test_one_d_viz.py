@@ -13,6 +13,11 @@ tree.set_leaf_cutoff(0)
 tree.fit(data)
 
 print(tree.knn(tree.data_point(0), 5))
+brute_knn = tree.brute_knn(tree.data_point(0), 5)
+assert [pi for pi, _ in brute_knn] == [0, 1, 3, 2]
+assert [pi for pi, _ in brute_knn] == [pi for pi, _ in tree.knn(tree.data_point(0), 5)]
+stats = tree.stats()
+assert sum(stats["leaf_depths"].values()) == stats["leaf_count"]
 
 print("============= KL Divergence =============")
 prior_weight = 1.0
@@ -1,10 +1,13 @@
 use crate::core::*;
 use pointcloud::*;
 
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::borrow::Borrow;
 use std::ops::Deref;
 
 use goko::errors::GokoError;
+use goko::query_tools::{KnnExplanation, LabelFilter};
 
 use super::NamedDistance;
 
@@ -13,6 +16,10 @@ use super::NamedDistance;
 pub struct KnnRequest<T> {
     pub k: usize,
     pub point: T,
+    /// Only return points with this label. It's parsed as JSON into the point cloud's label type, falling back to
+    /// a plain string, so `1` is the integer label and `"1"` or `malicious` are string labels.
+    #[serde(default)]
+    pub label: Option<String>,
 }
 
 /// Request: [`KnnRequest`]
@@ -25,10 +32,19 @@ impl<T> KnnRequest<T> {
     pub fn process<D>(self, reader: &mut CoreReader<D, T>) -> Result<KnnResponse, GokoError>
     where
         D: PointCloud,
+        D::Label: QueryLabel,
         T: Deref<Target = D::Point> + Send + Sync,
     {
-        let knn = reader.tree.knn(&self.point, self.k)?;
         let pc = &reader.tree.parameters().point_cloud;
+        let knn = match self.label.as_deref().map(D::Label::parse_query) {
+            Some(Some(label)) => {
+                let filter = LabelFilter::new(pc.as_ref(), label.borrow());
+                reader.tree.filtered_knn(&self.point, self.k, &filter)?
+            }
+            // No label of this type can match
+            Some(None) => Vec::new(),
+            None => reader.tree.knn(&self.point, self.k)?,
+        };
         let resp: Result<Vec<NamedDistance>, GokoError> = knn
             .iter()
             .map(|(pi, distance)| {
@@ -43,6 +59,27 @@ impl<T> KnnRequest<T> {
     }
 }
 
+/// A label type that can be read out of a request and compared to the point cloud's labels.
+pub trait QueryLabel: PartialEq {
+    /// The owned label a request is parsed into
+    type Query: Borrow<Self>;
+    /// Parses the requested label as JSON, then as a plain string. `None` if it isn't a label of this type.
+    fn parse_query(label: &str) -> Option<Self::Query>;
+}
+
+impl<L: ?Sized + PartialEq + ToOwned> QueryLabel for L
+where
+    L::Owned: DeserializeOwned,
+{
+    type Query = L::Owned;
+
+    fn parse_query(label: &str) -> Option<L::Owned> {
+        serde_json::from_str(label)
+            .or_else(|_| serde_json::from_value(serde_json::Value::String(label.to_string())))
+            .ok()
+    }
+}
+
 /// Response: [`RoutingKnnResponse`]
 #[derive(Deserialize, Serialize)]
 pub struct RoutingKnnRequest<T> {
@@ -77,3 +114,43 @@ impl<T> RoutingKnnRequest<T> {
         Ok(RoutingKnnResponse { routing_knn: resp? })
     }
 }
+
+/// Response: [`KnnExplainResponse`]
+#[derive(Deserialize, Serialize)]
+pub struct KnnExplainRequest<T> {
+    pub k: usize,
+    pub point: T,
+}
+
+/// Request: [`KnnExplainRequest`]
+#[derive(Deserialize, Serialize)]
+pub struct KnnExplainResponse {
+    pub knn: Vec<NamedDistance>,
+    pub explanation: KnnExplanation,
+}
+
+impl<T> KnnExplainRequest<T> {
+    pub fn process<D>(self, reader: &CoreReader<D, T>) -> Result<KnnExplainResponse, GokoError>
+    where
+        D: PointCloud,
+        T: Deref<Target = D::Point> + Send + Sync,
+    {
+        let explanation = reader.tree.knn_explain(&self.point, self.k)?;
+        let pc = &reader.tree.parameters().point_cloud;
+        let resp: Result<Vec<NamedDistance>, GokoError> = explanation
+            .knn
+            .iter()
+            .map(|(pi, distance)| {
+                Ok(NamedDistance {
+                    name: pc.name(*pi)?,
+                    distance: *distance,
+                })
+            })
+            .collect();
+
+        Ok(KnnExplainResponse {
+            knn: resp?,
+            explanation,
+        })
+    }
+}
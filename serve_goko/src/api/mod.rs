@@ -24,7 +24,8 @@ pub enum GokoRequest<T> {
     /// Response: [`ParametersResponse`]
     Parameters(ParametersRequest),
     /// With the HTTP server, send a `GET` request to `/knn?k=5` with a set of features in the body for this query,
-    /// will return with the response with the nearest 5 routing nbrs.
+    /// will return with the response with the nearest 5 routing nbrs. Add `&label=LABEL` to only get the points with
+    /// that label, e.g. `/knn?k=5&label=1`.
     ///
     /// See the chosen body parser for how to encode the body.
     ///
@@ -42,6 +43,21 @@ pub enum GokoRequest<T> {
     ///
     /// Response: [`PathResponse`]
     Path(PathRequest<T>),
+    /// With the HTTP server, send a `GET` request to `/knn/explain?k=5` with a set of features in the body for this
+    /// query, will return the nearest 5 nbrs along with every node the query visited, the children it pruned and
+    /// the state of the query heap at the end.
+    ///
+    /// See the chosen body parser for how to encode the body.
+    ///
+    /// Response: [`KnnExplainResponse`]
+    KnnExplain(KnnExplainRequest<T>),
+    /// With the HTTP server, send a `GET` request to `/path/explain` with a set of features in the body for this
+    /// query, will return the path along with the children of each node on it that were considered but not picked.
+    ///
+    /// See the chosen body parser for how to encode the body.
+    ///
+    /// Response: [`PathExplainResponse`]
+    PathExplain(PathExplainRequest<T>),
     /// The queries to manipulate the trackers, all under /track/
     ///
     /// See : [`TrackingRequest`]
@@ -82,11 +98,14 @@ pub enum TrackingRequestChoice<T> {
 
 /// The response one gets back from the core server loop.
 #[derive(Deserialize, Serialize)]
+#[serde(bound(deserialize = ""))]
 pub enum GokoResponse<L: Summary> {
     Parameters(ParametersResponse),
     Knn(KnnResponse),
     RoutingKnn(RoutingKnnResponse),
     Path(PathResponse<L>),
+    KnnExplain(KnnExplainResponse),
+    PathExplain(PathExplainResponse),
     Tracking(TrackingResponse),
     Unknown(String, u16),
 }
@@ -110,6 +129,7 @@ pub struct NamedDistance {
 
 /// Response for queries that include distances to nodes, usually in a vec
 #[derive(Deserialize, Serialize)]
+#[serde(bound(deserialize = ""))]
 pub struct NodeDistance<L: Summary + Clone> {
     /// The name of the center point of the node we're refering to
     pub name: String,
@@ -117,11 +137,14 @@ pub struct NodeDistance<L: Summary + Clone> {
     pub layer: i32,
     /// The distance to the central node
     pub distance: f32,
+    /// The summary of the labels covered by this node, if summaries were generated. This is serialized with the
+    /// point cloud's label summary, so a cloud with `StringLabels` reports class names rather than ids.
     pub label_summary: Option<SummaryCounter<L>>,
 }
 
 impl<D: PointCloud, P> CoreReader<D, P>
 where
+    D::Label: QueryLabel,
     P: Deref<Target = D::Point> + Send + Sync + 'static,
 {
     pub async fn process(
@@ -145,6 +168,14 @@ where
                 .process(self)
                 .map(|p| GokoResponse::Path(p))
                 .map_err(|e| e.into()),
+            GokoRequest::KnnExplain(p) => p
+                .process(self)
+                .map(GokoResponse::KnnExplain)
+                .map_err(|e| e.into()),
+            GokoRequest::PathExplain(p) => p
+                .process(self)
+                .map(GokoResponse::PathExplain)
+                .map_err(|e| e.into()),
             GokoRequest::Unknown(response_string, status) => {
                 Ok(GokoResponse::Unknown(response_string, status))
             }
@@ -2,6 +2,7 @@ use pointcloud::*;
 
 use crate::core::*;
 use goko::errors::GokoError;
+use goko::stats::TreeStats;
 use goko::PartitionType;
 use serde::{Deserialize, Serialize};
 
@@ -26,6 +27,8 @@ pub struct ParametersResponse {
     pub verbosity: u32,
     /// The seed to use for deterministic trees. This is xor-ed with the point index to create a seed for `rand::rngs::SmallRng`.
     pub rng_seed: Option<u64>,
+    /// The shape of the tree, see [`TreeStats`]
+    pub stats: TreeStats,
 }
 
 impl ParametersRequest {
@@ -42,6 +45,7 @@ impl ParametersRequest {
             partition_type: params.partition_type,
             verbosity: params.verbosity,
             rng_seed: params.rng_seed,
+            stats: TreeStats::clone(&reader.stats),
         })
     }
 }
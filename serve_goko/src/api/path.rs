@@ -6,6 +6,7 @@ use std::ops::Deref;
 use super::NodeDistance;
 use crate::core::*;
 use goko::errors::GokoError;
+use goko::query_tools::PathExplanation;
 
 /// Response: [`PathResponse`]
 #[derive(Deserialize, Serialize)]
@@ -15,6 +16,7 @@ pub struct PathRequest<T> {
 
 /// Request: [`PathRequest`]
 #[derive(Deserialize, Serialize)]
+#[serde(bound(deserialize = ""))]
 pub struct PathResponse<L: Summary> {
     pub path: Vec<NodeDistance<L>>,
 }
@@ -46,3 +48,26 @@ impl<T> PathRequest<T> {
         Ok(PathResponse { path: resp? })
     }
 }
+
+/// Response: [`PathExplainResponse`]
+#[derive(Deserialize, Serialize)]
+pub struct PathExplainRequest<T> {
+    pub point: T,
+}
+
+/// Request: [`PathExplainRequest`]
+#[derive(Deserialize, Serialize)]
+pub struct PathExplainResponse {
+    pub explanation: PathExplanation,
+}
+
+impl<T> PathExplainRequest<T> {
+    pub fn process<D>(self, reader: &CoreReader<D, T>) -> Result<PathExplainResponse, GokoError>
+    where
+        D: PointCloud,
+        T: Deref<Target = D::Point> + Send + Sync,
+    {
+        let explanation = reader.tree.path_explain(&self.point)?;
+        Ok(PathExplainResponse { explanation })
+    }
+}
@@ -1,3 +1,4 @@
+use goko::stats::TreeStats;
 use goko::{CoverTreeReader, CoverTreeWriter};
 use pointcloud::PointCloud;
 use std::collections::HashMap;
@@ -11,6 +12,8 @@ use internal_service::InternalServiceOperator;
 
 pub struct CoreWriter<D: PointCloud, T: Send + 'static> {
     pub(crate) tree: CoverTreeWriter<D>,
+    /// Walking the tree for these is slow, so they're computed once and shared with the readers
+    pub(crate) stats: Arc<TreeStats>,
     pub(crate) trackers:
         Arc<RwLock<HashMap<String, InternalServiceOperator<TrackingRequest<T>, TrackingResponse>>>>,
     pub(crate) main_tracker: Arc<InternalServiceOperator<TrackingRequest<T>, TrackingResponse>>,
@@ -20,9 +23,11 @@ impl<D: PointCloud, T: Deref<Target = D::Point> + Send + Sync> CoreWriter<D, T>
     pub fn new(writer: CoverTreeWriter<D>) -> Self {
         let trackers = Arc::new(RwLock::new(HashMap::new()));
         let main_tracker = Arc::new(TrackerWorker::operator(writer.reader()));
+        let stats = Arc::new(writer.reader().stats());
         CoreWriter {
             trackers,
             main_tracker,
+            stats,
             tree: writer,
         }
     }
@@ -32,6 +37,7 @@ impl<D: PointCloud, T: Deref<Target = D::Point> + Send + Sync> CoreWriter<D, T>
         CoreReader {
             trackers: Arc::clone(&self.trackers),
             main_tracker: Arc::clone(&self.main_tracker),
+            stats: Arc::clone(&self.stats),
             tree,
         }
     }
@@ -39,6 +45,7 @@ impl<D: PointCloud, T: Deref<Target = D::Point> + Send + Sync> CoreWriter<D, T>
 
 pub struct CoreReader<D: PointCloud, T: Send + 'static> {
     pub(crate) tree: CoverTreeReader<D>,
+    pub(crate) stats: Arc<TreeStats>,
     pub(crate) trackers:
         Arc<RwLock<HashMap<String, InternalServiceOperator<TrackingRequest<T>, TrackingResponse>>>>,
     pub(crate) main_tracker: Arc<InternalServiceOperator<TrackingRequest<T>, TrackingResponse>>,
@@ -14,6 +14,7 @@ use std::marker::PhantomData;
 use std::ops::Deref;
 
 use super::GokoHttp;
+use crate::api::QueryLabel;
 use crate::core::*;
 use crate::parsers::{PointBuffer, PointParser};
 
@@ -39,6 +40,7 @@ where
 impl<D, T, P> Service<T> for MakeGokoHttp<D, P>
 where
     D: PointCloud,
+    D::Label: QueryLabel,
     P: PointParser,
     P::Point: Deref<Target = D::Point> + Send + Sync + 'static,
 {
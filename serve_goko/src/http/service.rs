@@ -46,6 +46,42 @@ fn parse_knn_query(uri: &Uri) -> usize {
     }
 }
 
+fn parse_label_query(uri: &Uri) -> Option<String> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"(?:^|&)label=(?P<label>[^&]*)").unwrap();
+    }
+
+    uri.query()
+        .and_then(|s| RE.captures(s))
+        .map(|caps| percent_decode(&caps["label"]))
+}
+
+/// Decodes a query string value, `+` is a space and `%XX` is a byte. Malformed escapes are kept as they are.
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => match value
+                .get(i + 1..i + 3)
+                .filter(|h| h.bytes().all(|b| b.is_ascii_hexdigit()))
+                .and_then(|h| u8::from_str_radix(h, 16).ok())
+            {
+                Some(byte) => {
+                    decoded.push(byte);
+                    i += 2;
+                }
+                None => decoded.push(b'%'),
+            },
+            b => decoded.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
 fn parse_tracker_query(uri: &Uri) -> (Option<String>, Option<usize>) {
     lazy_static! {
         static ref RE_TRACKER: Regex = Regex::new(r"tracker_name=(?P<tracker_name>\w+)").unwrap();
@@ -75,8 +111,9 @@ pub(crate) async fn parse_http<P: PointParser>(
         (&Method::GET, "/") => Ok(GokoRequest::Parameters(ParametersRequest)),
         (&Method::GET, "/knn") => {
             let k = parse_knn_query(request.uri());
+            let label = parse_label_query(request.uri());
             let point = parser.point(request).await?;
-            Ok(GokoRequest::Knn(KnnRequest { point, k }))
+            Ok(GokoRequest::Knn(KnnRequest { point, k, label }))
         }
         (&Method::GET, "/routing_knn") => {
             let k = parse_knn_query(request.uri());
@@ -87,6 +124,15 @@ pub(crate) async fn parse_http<P: PointParser>(
             let point = parser.point(request).await?;
             Ok(GokoRequest::Path(PathRequest { point }))
         }
+        (&Method::GET, "/knn/explain") => {
+            let k = parse_knn_query(request.uri());
+            let point = parser.point(request).await?;
+            Ok(GokoRequest::KnnExplain(KnnExplainRequest { point, k }))
+        }
+        (&Method::GET, "/path/explain") => {
+            let point = parser.point(request).await?;
+            Ok(GokoRequest::PathExplain(PathExplainRequest { point }))
+        }
         (&Method::POST, "/track/add") => {
             let (tracker_name, window_size) = parse_tracker_query(request.uri());
             if let Some(window_size) = window_size {
@@ -142,6 +188,8 @@ pub(crate) fn into_http<L: Summary + Serialize>(
         GokoResponse::Knn(p) => serde_json::to_string(&p).unwrap(),
         GokoResponse::RoutingKnn(p) => serde_json::to_string(&p).unwrap(),
         GokoResponse::Path(p) => serde_json::to_string(&p).unwrap(),
+        GokoResponse::KnnExplain(p) => serde_json::to_string(&p).unwrap(),
+        GokoResponse::PathExplain(p) => serde_json::to_string(&p).unwrap(),
         GokoResponse::Tracking(p) => serde_json::to_string(&p).unwrap(),
         GokoResponse::Unknown(response_string, status) => {
             builder = builder.status(status);
@@ -154,6 +202,7 @@ pub(crate) fn into_http<L: Summary + Serialize>(
 impl<D, P> GokoHttp<D, P>
 where
     D: PointCloud,
+    D::Label: QueryLabel,
     P: PointParser,
     P::Point: Deref<Target = D::Point> + Send + Sync + 'static,
     D::LabelSummary: Serialize,
@@ -227,6 +276,7 @@ where
 impl<D, P> Service<Request<Body>> for GokoHttp<D, P>
 where
     D: PointCloud,
+    D::Label: QueryLabel,
     P: PointParser,
     P::Point: Deref<Target = D::Point> + Send + Sync + 'static,
     D::LabelSummary: Serialize,